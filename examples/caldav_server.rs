@@ -0,0 +1,527 @@
+//! A CalDAV stand-in server to try the sync against, keeping its calendars in memory
+//!
+//!     cargo run --example caldav_server -- 127.0.0.1:5232
+//!
+//! then, in config.toml:
+//!
+//!     [caldav]
+//!     url = "http://127.0.0.1:5232/"
+//!     calendar = "Team"
+//!
+//! It answers what the sync sends: the PROPFIND discovery, sync-collection, calendar-multiget
+//! and calendar-query REPORTs, GET, and PUT and DELETE with If-Match and If-None-Match.
+//! Credentials are not checked. Changes made with e.g. curl show up at the next sync
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+const PRINCIPAL: &str = "/principals/user/";
+const HOME: &str = "/calendars/user/";
+const TOKEN_PREFIX: &str = "http://rusty-calendar.invalid/sync/";
+
+struct Resource {
+    data: String,
+    etag: String,
+}
+
+struct CalendarData {
+    name: String,
+    components: &'static [&'static str],
+    /// By file name
+    resources: BTreeMap<String, Resource>,
+    /// Token and file name of every change, removals included
+    changes: Vec<(u64, String)>,
+}
+
+struct Server {
+    /// By path segment
+    calendars: BTreeMap<String, CalendarData>,
+    /// Incremented on every change, the sync token of all the calendars
+    token: u64,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct Response {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Response {
+    fn new(status: &'static str) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    fn multistatus(responses: String, sync_token: Option<u64>) -> Self {
+        let token = sync_token
+            .map(|t| format!("<d:sync-token>{}{}</d:sync-token>", TOKEN_PREFIX, t))
+            .unwrap_or_default();
+        Self {
+            status: "207 Multi-Status",
+            headers: vec![("Content-Type", "application/xml; charset=utf-8".into())],
+            body: format!(
+                concat!(
+                    r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                    r#"<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">"#,
+                    "{}{}</d:multistatus>"
+                ),
+                responses, token
+            ),
+        }
+    }
+}
+
+fn main() {
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("127.0.0.1:5232"));
+    let listener = TcpListener::bind(&address).unwrap_or_else(|e| {
+        eprintln!("Could not listen on {}: {}", address, e);
+        std::process::exit(1);
+    });
+    println!("CalDAV stand-in server on http://{}/", address);
+    println!("Calendars: {}team/ (Team) and {}personal/", HOME, HOME);
+
+    let server = Arc::new(Mutex::new(Server::new()));
+    for stream in listener.incoming().flatten() {
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &server) {
+                eprintln!("Connection error: {}", e);
+            }
+        });
+    }
+}
+
+fn handle_connection(stream: TcpStream, server: &Mutex<Server>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let Some(request) = read_request(&mut reader)? else {
+        return Ok(());
+    };
+    let response = server.lock().unwrap().handle(&request);
+    println!("{} {} -> {}", request.method, request.path, response.status);
+
+    let mut stream = stream;
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(response.body.as_bytes())?;
+    stream.flush()
+}
+
+fn read_request(reader: &mut impl BufRead) -> std::io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or("/").to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let length = headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    }))
+}
+
+impl Server {
+    fn new() -> Self {
+        let mut server = Self {
+            calendars: BTreeMap::new(),
+            token: 0,
+        };
+        server
+            .calendars
+            .insert(String::from("team"), CalendarData::new("Team", &["VEVENT"]));
+        server.calendars.insert(
+            String::from("personal"),
+            CalendarData::new("Personal", &["VEVENT", "VTODO"]),
+        );
+        let standup = [
+            "BEGIN:VCALENDAR",
+            "VERSION:2.0",
+            "PRODID:-//caldav_server example//EN",
+            "BEGIN:VEVENT",
+            "UID:standup@caldav-server.invalid",
+            "DTSTAMP:20261001T080000Z",
+            "DTSTART;TZID=Europe/Berlin:20261005T093000",
+            "DTEND;TZID=Europe/Berlin:20261005T094500",
+            "RRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR",
+            "SUMMARY:Team stand-up",
+            "LOCATION:Room 2",
+            "END:VEVENT",
+            "END:VCALENDAR",
+            "",
+        ]
+        .join("\r\n");
+        server.store("team", "standup.ics", standup);
+        server
+    }
+
+    fn store(&mut self, calendar: &str, file: &str, data: String) -> String {
+        self.token += 1;
+        let etag = format!("\"{}\"", self.token);
+        let calendar = self.calendars.get_mut(calendar).unwrap();
+        calendar.resources.insert(
+            file.to_string(),
+            Resource {
+                data,
+                etag: etag.clone(),
+            },
+        );
+        calendar.changes.push((self.token, file.to_string()));
+        etag
+    }
+
+    fn handle(&mut self, request: &Request) -> Response {
+        // "/calendars/user/team/standup.ics" -> ("team", "standup.ics")
+        let in_home = request.path.strip_prefix(HOME).unwrap_or_default();
+        let (calendar, file) = match in_home.split_once('/') {
+            Some((calendar, file)) => (calendar.to_string(), file.to_string()),
+            None => (in_home.to_string(), String::new()),
+        };
+        let known_calendar = self.calendars.contains_key(&calendar);
+        if request.path.starts_with(HOME) && !calendar.is_empty() && !known_calendar {
+            return Response::new("404 Not Found");
+        }
+
+        match request.method.as_str() {
+            "OPTIONS" => {
+                let mut response = Response::new("200 OK");
+                response
+                    .headers
+                    .push(("DAV", "1, 2, 3, calendar-access".into()));
+                response.headers.push((
+                    "Allow",
+                    "OPTIONS, GET, PUT, DELETE, PROPFIND, REPORT".into(),
+                ));
+                response
+            }
+            "PROPFIND" if known_calendar && file.is_empty() => {
+                let depth = request.header("Depth").unwrap_or("0");
+                self.propfind_calendar(&calendar, depth != "0")
+            }
+            "PROPFIND" if known_calendar => match self.calendars[&calendar].resources.get(&file) {
+                Some(resource) => {
+                    Response::multistatus(resource_response(&request.path, resource, false), None)
+                }
+                None => Response::new("404 Not Found"),
+            },
+            "PROPFIND" => self.propfind_home(&request.path, request.header("Depth") == Some("1")),
+            "REPORT" if known_calendar => self.report(&calendar, &request.body),
+            "GET" if known_calendar => match self.calendars[&calendar].resources.get(&file) {
+                Some(resource) => Response {
+                    status: "200 OK",
+                    headers: vec![
+                        ("Content-Type", "text/calendar; charset=utf-8".into()),
+                        ("ETag", resource.etag.clone()),
+                    ],
+                    body: resource.data.clone(),
+                },
+                None => Response::new("404 Not Found"),
+            },
+            "PUT" if known_calendar && !file.is_empty() => {
+                let existing = self.calendars[&calendar].resources.get(&file);
+                if let Some(status) = precondition_failure(request, existing) {
+                    return Response::new(status);
+                }
+                let status = if existing.is_some() {
+                    "204 No Content"
+                } else {
+                    "201 Created"
+                };
+                let etag = self.store(&calendar, &file, request.body.clone());
+                let mut response = Response::new(status);
+                response.headers.push(("ETag", etag));
+                response
+            }
+            "DELETE" if known_calendar && !file.is_empty() => {
+                let existing = self.calendars[&calendar].resources.get(&file);
+                if existing.is_none() {
+                    return Response::new("404 Not Found");
+                }
+                if let Some(status) = precondition_failure(request, existing) {
+                    return Response::new(status);
+                }
+                self.token += 1;
+                let data = self.calendars.get_mut(&calendar).unwrap();
+                data.resources.remove(&file);
+                data.changes.push((self.token, file));
+                Response::new("204 No Content")
+            }
+            _ => Response::new("405 Method Not Allowed"),
+        }
+    }
+
+    /// The server root, the principal and the calendar home all tell where the calendars are
+    fn propfind_home(&self, path: &str, with_calendars: bool) -> Response {
+        let resourcetype = if path == PRINCIPAL {
+            "<d:collection/><d:principal/>"
+        } else {
+            "<d:collection/>"
+        };
+        let mut responses = format!(
+            concat!(
+                "<d:response><d:href>{}</d:href><d:propstat><d:prop>",
+                "<d:resourcetype>{}</d:resourcetype>",
+                "<d:current-user-principal><d:href>{}</d:href></d:current-user-principal>",
+                "<c:calendar-home-set><d:href>{}</d:href></c:calendar-home-set>",
+                "</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"
+            ),
+            path, resourcetype, PRINCIPAL, HOME
+        );
+        if with_calendars && path == HOME {
+            for name in self.calendars.keys() {
+                responses.push_str(&self.calendar_response(name));
+            }
+        }
+        Response::multistatus(responses, None)
+    }
+
+    fn propfind_calendar(&self, calendar: &str, with_resources: bool) -> Response {
+        let mut responses = self.calendar_response(calendar);
+        if with_resources {
+            for (file, resource) in &self.calendars[calendar].resources {
+                let href = format!("{}{}/{}", HOME, calendar, file);
+                responses.push_str(&resource_response(&href, resource, false));
+            }
+        }
+        Response::multistatus(responses, None)
+    }
+
+    fn calendar_response(&self, calendar: &str) -> String {
+        let data = &self.calendars[calendar];
+        let components: String = data
+            .components
+            .iter()
+            .map(|c| format!(r#"<c:comp name="{}"/>"#, c))
+            .collect();
+        format!(
+            concat!(
+                "<d:response><d:href>{}{}/</d:href><d:propstat><d:prop>",
+                "<d:resourcetype><d:collection/><c:calendar/></d:resourcetype>",
+                "<d:displayname>{}</d:displayname>",
+                "<c:supported-calendar-component-set>{}</c:supported-calendar-component-set>",
+                "<d:sync-token>{}{}</d:sync-token>",
+                "</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"
+            ),
+            HOME, calendar, data.name, components, TOKEN_PREFIX, self.token
+        )
+    }
+
+    fn report(&self, calendar: &str, body: &str) -> Response {
+        let data = &self.calendars[calendar];
+        let href = |file: &str| format!("{}{}/{}", HOME, calendar, file);
+
+        if body.contains("sync-collection") {
+            let token = element_texts(body, "sync-token")
+                .into_iter()
+                .next()
+                .unwrap_or_default();
+            let since = if token.is_empty() {
+                None
+            } else {
+                match token
+                    .strip_prefix(TOKEN_PREFIX)
+                    .and_then(|t| t.parse::<u64>().ok())
+                    .filter(|t| *t <= self.token)
+                {
+                    Some(since) => Some(since),
+                    None => {
+                        let mut response = Response::new("403 Forbidden");
+                        response.body = String::from(concat!(
+                            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                            r#"<d:error xmlns:d="DAV:"><d:valid-sync-token/></d:error>"#
+                        ));
+                        return response;
+                    }
+                }
+            };
+            let mut responses = String::new();
+            let files: Vec<&String> = match since {
+                None => data.resources.keys().collect(),
+                Some(since) => {
+                    let mut files: Vec<&String> = data
+                        .changes
+                        .iter()
+                        .filter(|(t, _)| *t > since)
+                        .map(|(_, file)| file)
+                        .collect();
+                    files.sort();
+                    files.dedup();
+                    files
+                }
+            };
+            for file in files {
+                match data.resources.get(file) {
+                    Some(resource) => {
+                        responses.push_str(&resource_response(&href(file), resource, false))
+                    }
+                    None => responses.push_str(&removed_response(&href(file))),
+                }
+            }
+            return Response::multistatus(responses, Some(self.token));
+        }
+
+        if body.contains("calendar-multiget") {
+            let mut responses = String::new();
+            for wanted in element_texts(body, "href") {
+                let file = wanted.rsplit('/').next().unwrap_or_default();
+                match data.resources.get(file) {
+                    Some(resource) => {
+                        responses.push_str(&resource_response(&wanted, resource, true))
+                    }
+                    None => responses.push_str(&removed_response(&wanted)),
+                }
+            }
+            return Response::multistatus(responses, None);
+        }
+
+        // calendar-query: every resource, the filters are not applied
+        let responses: String = data
+            .resources
+            .iter()
+            .map(|(file, resource)| resource_response(&href(file), resource, true))
+            .collect();
+        Response::multistatus(responses, None)
+    }
+}
+
+impl CalendarData {
+    fn new(name: &str, components: &'static [&'static str]) -> Self {
+        Self {
+            name: name.to_string(),
+            components,
+            resources: BTreeMap::new(),
+            changes: Vec::new(),
+        }
+    }
+}
+
+fn resource_response(href: &str, resource: &Resource, with_data: bool) -> String {
+    let data = if with_data {
+        format!(
+            "<c:calendar-data>{}</c:calendar-data>",
+            escape(&resource.data)
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        concat!(
+            "<d:response><d:href>{}</d:href><d:propstat><d:prop>",
+            "<d:getetag>{}</d:getetag>",
+            "<d:getcontenttype>text/calendar; charset=utf-8</d:getcontenttype>{}",
+            "</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"
+        ),
+        href,
+        escape(&resource.etag),
+        data
+    )
+}
+
+fn removed_response(href: &str) -> String {
+    format!(
+        "<d:response><d:href>{}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+        href
+    )
+}
+
+/// The status of a failed If-Match or If-None-Match condition
+fn precondition_failure(request: &Request, existing: Option<&Resource>) -> Option<&'static str> {
+    if request.header("If-None-Match") == Some("*") && existing.is_some() {
+        return Some("412 Precondition Failed");
+    }
+    match (request.header("If-Match"), existing) {
+        (Some(etag), Some(resource)) if etag != "*" && etag != resource.etag => {
+            Some("412 Precondition Failed")
+        }
+        (Some(_), None) => Some("412 Precondition Failed"),
+        _ => None,
+    }
+}
+
+/// Texts of the elements with this local name, whatever their prefix
+fn element_texts(xml: &str, name: &str) -> Vec<String> {
+    let mut texts = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&format!("{}>", name)) {
+        let after = &rest[start + name.len() + 1..];
+        // Only start tags such as `<d:href>`, the text ends at the next tag
+        let prefix = rest[..start].rfind('<').map(|i| &rest[i + 1..start]);
+        let is_start_tag = prefix.is_some_and(|p| {
+            !p.starts_with('/')
+                && (p.is_empty() || p.ends_with(':'))
+                && !p.contains(char::is_whitespace)
+        });
+        let end = after.find('<').unwrap_or(after.len());
+        if is_start_tag {
+            texts.push(unescape(after[..end].trim()));
+        }
+        rest = &after[end..];
+    }
+    texts
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,204 @@
+use std::{fs, time::SystemTime};
+
+use chrono::{Datelike, Days, Local, Months, NaiveDate, NaiveDateTime};
+
+use crate::{
+    caldav::SyncWorker,
+    calendar_data::{Calendar, Events},
+    feeds::FeedWorker,
+    file_watcher::FileWatcher,
+    paths::AppPaths,
+    storage::Storage,
+    to_do_data::{ToDo, ToDoList},
+};
+
+/// Data shown by the panes, kept between frames
+/// The storage is only read again after a write, when another month is shown
+/// or when the files behind it are changed by another program
+pub struct AppState {
+    pub storage: Box<dyn Storage>,
+    /// Data directory of the storage, whose lock is taken around each command
+    pub paths: AppPaths,
+    /// Events that may occur in the shown month
+    pub calendar: Calendar,
+    pub todolist: ToDoList,
+    /// Errors of the last load, shown in place of the appointments or to-dos
+    pub events_error: Option<String>,
+    pub todos_error: Option<String>,
+    /// Message shown under the input box, e.g. after a reload
+    pub status: Option<String>,
+    /// Changes made on disk while a command was being typed, shown until the command is run or discarded
+    pub conflict: Option<Vec<String>>,
+    /// Answer to the last command (e.g. the list of backups), shown until the next one
+    pub notice: Option<Vec<String>>,
+    /// Background sync with the CalDAV server, if one is configured
+    pub sync: Option<SyncWorker>,
+    /// Last status of the sync, shown on the right under the input box
+    pub sync_status: Option<String>,
+    /// Background downloads of the subscribed feeds, if any
+    pub feeds: Option<FeedWorker>,
+    /// Without a watcher (e.g. if the system refuses one), the modification times are checked on every frame
+    watcher: Option<FileWatcher>,
+    loaded_month: Option<NaiveDate>,
+    modified_times: Vec<Option<SystemTime>>,
+    needs_reload: bool,
+}
+
+impl AppState {
+    pub fn new(storage: Box<dyn Storage>, paths: AppPaths) -> Self {
+        let watcher = FileWatcher::new(&storage.watched_paths()).ok();
+        Self {
+            storage,
+            paths,
+            calendar: Calendar::new(),
+            todolist: ToDoList::new(),
+            events_error: None,
+            todos_error: None,
+            status: None,
+            conflict: None,
+            notice: None,
+            sync: None,
+            sync_status: None,
+            feeds: None,
+            watcher,
+            loaded_month: None,
+            modified_times: Vec::new(),
+            needs_reload: true,
+        }
+    }
+
+    /// Makes the next `refresh` read the storage again, used after writes
+    pub fn mark_changed(&mut self) {
+        self.needs_reload = true;
+    }
+
+    /// Reads the storage again if needed, before drawing a frame for `calendar_date`
+    /// `has_pending_input` tells whether a command is being typed, which an outside change may conflict with
+    pub fn refresh(&mut self, calendar_date: NaiveDateTime, has_pending_input: bool) {
+        if let Some(status) = self.sync.as_ref().and_then(|sync| sync.latest_status()) {
+            if !status.details.is_empty() {
+                self.notice = Some(status.details);
+            }
+            self.sync_status = Some(status.line);
+        }
+        // The feeds are not in the storage, their events are replaced as they are downloaded
+        for feed in self.feeds.iter().flat_map(|feeds| feeds.updates()) {
+            if let Some(error) = &feed.error {
+                self.status = Some(format!("Could not refresh {}: {}", feed.name, error));
+            }
+            self.calendar.set_feed(feed);
+        }
+        let first_day = calendar_date.date().with_day(1).unwrap();
+        // The watcher only says when to look; the modification times tell our own writes
+        // (after which `needs_reload` is set) from the ones of other programs
+        let maybe_changed = match &self.watcher {
+            Some(watcher) => watcher.has_changed(),
+            None => true,
+        };
+        let modified_times = if maybe_changed || self.needs_reload {
+            self.get_modified_times()
+        } else {
+            self.modified_times.clone()
+        };
+        let changed_on_disk = !self.needs_reload && modified_times != self.modified_times;
+        if !self.needs_reload && !changed_on_disk && self.loaded_month == Some(first_day) {
+            return;
+        }
+
+        let old_events = std::mem::take(&mut self.calendar.all_events);
+        let old_todos = std::mem::take(&mut self.todolist.all_todos);
+
+        let last_day = first_day + Months::new(1) - Days::new(1);
+        self.events_error = self
+            .calendar
+            .load_events_between(self.storage.as_mut(), first_day, last_day)
+            .err()
+            .map(|e| e.to_string());
+        self.todos_error = self
+            .todolist
+            .load_todos(self.storage.as_mut())
+            .err()
+            .map(|e| e.to_string());
+
+        if changed_on_disk {
+            // Events of another month are not compared, they were not shown before
+            let old_events = if self.loaded_month == Some(first_day) {
+                old_events
+            } else {
+                self.calendar.all_events.clone()
+            };
+            let changes = describe_changes(
+                &old_events,
+                &self.calendar.all_events,
+                &old_todos,
+                &self.todolist.all_todos,
+            );
+            self.status = Some(format!(
+                "Reloaded at {}: {} change(s) made on disk",
+                Local::now().format("%H:%M:%S"),
+                changes.len()
+            ));
+            if has_pending_input && !changes.is_empty() {
+                self.conflict.get_or_insert_with(Vec::new).extend(changes);
+            }
+        }
+
+        self.loaded_month = Some(first_day);
+        self.modified_times = modified_times;
+        self.needs_reload = false;
+    }
+
+    fn get_modified_times(&self) -> Vec<Option<SystemTime>> {
+        self.storage
+            .watched_paths()
+            .iter()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+/// Lines describing the added (+), removed (-) and changed (~) events and to-dos
+fn describe_changes(
+    old_events: &[Events],
+    new_events: &[Events],
+    old_todos: &[ToDo],
+    new_todos: &[ToDo],
+) -> Vec<String> {
+    let mut changes = Vec::new();
+    push_changes(
+        &mut changes,
+        old_events,
+        new_events,
+        |ev| ev.id,
+        |ev| format!("Event {}: {} ({})", ev.id, ev.event_name, ev.date),
+    );
+    push_changes(
+        &mut changes,
+        old_todos,
+        new_todos,
+        |t| t.id,
+        |t| format!("To-do: {}", t.todo_name),
+    );
+    changes
+}
+
+fn push_changes<T: PartialEq>(
+    changes: &mut Vec<String>,
+    old: &[T],
+    new: &[T],
+    id: impl Fn(&T) -> u32,
+    label: impl Fn(&T) -> String,
+) {
+    for item in new {
+        match old.iter().find(|o| id(o) == id(item)) {
+            None => changes.push(format!("+ {}", label(item))),
+            Some(old_item) if old_item != item => changes.push(format!("~ {}", label(item))),
+            Some(_) => {}
+        }
+    }
+    for item in old {
+        if !new.iter().any(|n| id(n) == id(item)) {
+            changes.push(format!("- {}", label(item)));
+        }
+    }
+}
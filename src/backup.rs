@@ -0,0 +1,205 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chrono::{Local, NaiveDateTime};
+
+use crate::storage::StorageResult;
+
+/// Number of backups kept for each data file, the oldest ones are removed first
+pub const BACKUPS_KEPT: usize = 20;
+const BACKUP_DIRECTORY: &str = "backups";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%d-%H%M%S%.3f";
+
+/// A copy of a data file, taken before the file was overwritten
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// The data file it is a copy of
+    pub original: PathBuf,
+    pub time: NaiveDateTime,
+}
+
+impl Backup {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} from {}",
+            file_name(&self.original),
+            self.time.format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+/// Replaces the file at `path` without ever leaving it half-written
+/// The data goes to a temporary file next to it, which is flushed to disk and then renamed over
+/// the old file, so a crash or a full disk leaves either the old or the new contents
+pub fn write_atomic(path: &Path, data: &[u8]) -> StorageResult<()> {
+    let directory = parent_directory(path);
+    let (mut file, temp_path) = create_temp_file(&directory, path)
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+
+    let result = (|| -> std::io::Result<()> {
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(format!("Could not write {}: {}", path.display(), e).into());
+    }
+
+    // The rename itself is only durable once the directory is synced, which is not possible everywhere
+    if let Ok(directory) = File::open(&directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
+
+/// A new temporary file for `path`, whose name is unique to this call: the process ID keeps
+/// running instances apart and the counter the threads of one (e.g. the sync and the app)
+/// A file left by a crash with the same name is skipped
+fn create_temp_file(directory: &Path, path: &Path) -> std::io::Result<(File, PathBuf)> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let temp_path = directory.join(format!(
+            ".{}.{}.{}.tmp",
+            file_name(path),
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => return Ok((file, temp_path)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Copies the file at `path` into the `backups` directory next to it, then removes the
+/// backups of that file beyond the newest `BACKUPS_KEPT`
+pub fn backup_file(path: &Path) -> StorageResult<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let directory = backup_directory(path);
+    fs::create_dir_all(&directory)
+        .map_err(|e| format!("Could not create {}: {}", directory.display(), e))?;
+
+    let backup_path = directory.join(format!(
+        "{}-{}.{}",
+        file_stem(path),
+        Local::now().format(BACKUP_TIME_FORMAT),
+        file_extension(path)
+    ));
+    let data = fs::read(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+    write_atomic(&backup_path, &data)?;
+
+    for old_backup in list_backups(path)?.iter().skip(BACKUPS_KEPT) {
+        fs::remove_file(&old_backup.path)?;
+    }
+    Ok(())
+}
+
+/// Backups of the file at `path`, newest first
+pub fn list_backups(path: &Path) -> StorageResult<Vec<Backup>> {
+    let directory = backup_directory(path);
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+    let prefix = format!("{}-", file_stem(path));
+    let suffix = format!(".{}", file_extension(path));
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(&directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Other files in the directory are left alone
+        let time = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(&suffix))
+            .and_then(|time| NaiveDateTime::parse_from_str(time, BACKUP_TIME_FORMAT).ok());
+        if let Some(time) = time {
+            backups.push(Backup {
+                path: entry.path(),
+                original: path.to_path_buf(),
+                time,
+            });
+        }
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.time));
+    Ok(backups)
+}
+
+/// Puts the contents of a backup back in place of its data file
+/// The current file is backed up first, so that a restore can be undone as well
+pub fn restore_backup(backup: &Backup) -> StorageResult<()> {
+    let data = fs::read(&backup.path)
+        .map_err(|e| format!("Could not open {}: {}", backup.path.display(), e))?;
+    backup_file(&backup.original)?;
+    write_atomic(&backup.original, &data)
+}
+
+fn backup_directory(path: &Path) -> PathBuf {
+    parent_directory(path).join(BACKUP_DIRECTORY)
+}
+
+fn parent_directory(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn file_extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_writing_the_same_file() {
+        let dir = std::env::temp_dir().join(format!("write-atomic-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.json");
+        let contents: Vec<Vec<u8>> = (0..8u8).map(|n| vec![b'a' + n; 64 * 1024]).collect();
+
+        std::thread::scope(|scope| {
+            for data in &contents {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        write_atomic(path, data).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert!(contents.contains(&fs::read(&path).unwrap()));
+        // Every temporary file was renamed
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
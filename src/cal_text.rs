@@ -0,0 +1,152 @@
+use chrono::{Datelike, Days, Months, NaiveDate, Weekday};
+
+use crate::calendar_data::Calendar;
+
+/// Months shown next to each other, a year is printed as 4 rows of 3 months
+pub const MONTHS_PER_ROW: usize = 3;
+const MONTH_GAP: &str = "  ";
+/// Reverse video, as `cal` uses for today
+const ANSI_TODAY: &str = "\x1b[7m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// How the months are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CalOptions {
+    pub week_start: Weekday,
+    /// Adds a column with the ISO week numbers
+    pub week_numbers: bool,
+    /// Highlights today with ANSI escape codes
+    pub color: bool,
+    pub today: NaiveDate,
+}
+
+/// Lines of a single month, like `cal`: the title, the weekdays and 6 weeks
+/// Days with events are followed by a `*`. Every line has the same visible width
+/// (escape codes aside), so that months can be put next to each other
+pub fn month_lines(
+    calendar: &Calendar,
+    month: NaiveDate,
+    title: &str,
+    options: &CalOptions,
+) -> Vec<String> {
+    let width = block_width(options);
+    let first_day = month.with_day(1).unwrap();
+    let offset = (first_day.weekday().num_days_from_monday() + 7
+        - options.week_start.num_days_from_monday())
+        % 7;
+    let first_shown = first_day - Days::new(offset.into());
+
+    let mut lines = vec![format!("{:^width$}", title, width = width)];
+    let mut weekdays = String::new();
+    if options.week_numbers {
+        weekdays.push_str("Wk ");
+    }
+    let mut weekday = options.week_start;
+    for _ in 0..7 {
+        weekdays.push_str(&weekday.to_string()[..2]);
+        weekdays.push(' ');
+        weekday = weekday.succ();
+    }
+    lines.push(weekdays);
+
+    // Always 6 weeks, so that months next to each other line up
+    for week in 0..6 {
+        let week_start = first_shown + Days::new(week * 7);
+        let mut line = String::new();
+        if options.week_numbers {
+            // The week is numbered after its Monday, which all weeks contain
+            let monday = (0..7)
+                .map(|d| week_start + Days::new(d))
+                .find(|d| d.weekday() == Weekday::Mon)
+                .unwrap();
+            let in_month = (0..7).any(|d| (week_start + Days::new(d)).month() == month.month());
+            if in_month {
+                line.push_str(&format!("{:>2} ", monday.iso_week().week()));
+            } else {
+                line.push_str("   ");
+            }
+        }
+        for d in 0..7 {
+            let day = week_start + Days::new(d);
+            if day.month() != month.month() {
+                line.push_str("   ");
+                continue;
+            }
+            let day_text = format!("{:>2}", day.day());
+            if options.color && day == options.today {
+                line.push_str(&format!("{}{}{}", ANSI_TODAY, day_text, ANSI_RESET));
+            } else {
+                line.push_str(&day_text);
+            }
+            let has_events = calendar.has_events_on(day);
+            line.push(if has_events { '*' } else { ' ' });
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// `count` months starting with `first_month`, three per row
+pub fn months_text(
+    calendar: &Calendar,
+    first_month: NaiveDate,
+    count: u32,
+    options: &CalOptions,
+) -> String {
+    let blocks: Vec<Vec<String>> = (0..count)
+        .map(|i| {
+            let month = first_month + Months::new(i);
+            month_lines(calendar, month, &month.format("%B %Y").to_string(), options)
+        })
+        .collect();
+    join_blocks(blocks).join("\n")
+}
+
+/// The twelve months of a year in a 3×4 grid, under the year
+pub fn year_text(calendar: &Calendar, year: i32, options: &CalOptions) -> String {
+    let blocks: Vec<Vec<String>> = (1..=12)
+        .map(|month| {
+            let month = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+            // The year is only in the title above the grid
+            month_lines(calendar, month, &month.format("%B").to_string(), options)
+        })
+        .collect();
+    let width = MONTHS_PER_ROW * block_width(options) + (MONTHS_PER_ROW - 1) * MONTH_GAP.len();
+    let mut lines = vec![
+        format!("{:^width$}", year, width = width)
+            .trim_end()
+            .to_string(),
+        String::new(),
+    ];
+    lines.extend(join_blocks(blocks));
+    lines.join("\n")
+}
+
+/// Visible width of a month, 3 characters per day (and week number)
+fn block_width(options: &CalOptions) -> usize {
+    if options.week_numbers {
+        24
+    } else {
+        21
+    }
+}
+
+/// Puts the months next to each other, `MONTHS_PER_ROW` at a time, with an empty line between the rows
+/// The trailing spaces are trimmed from each line
+fn join_blocks(blocks: Vec<Vec<String>>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for row in blocks.chunks(MONTHS_PER_ROW) {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        for line_index in 0..row[0].len() {
+            let line = row
+                .iter()
+                .map(|block| block[line_index].as_str())
+                .collect::<Vec<&str>>()
+                .join(MONTH_GAP);
+            lines.push(line.trim_end().to_string());
+        }
+    }
+    lines
+}
@@ -0,0 +1,1344 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::Local;
+use reqwest::{
+    header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH},
+    Method, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backup::write_atomic,
+    calendar_data::Events,
+    config::CalDavConfig,
+    ical::{event_ics_text, import_ics},
+    paths::AppPaths,
+    storage::{open_storage, Storage, StorageKind, StorageLock, StorageResult},
+    xml::{self, Element, CALDAV, DAV},
+};
+
+/// Hrefs asked for in a single calendar-multiget REPORT
+const MULTIGET_BATCH: usize = 100;
+
+/// Whose version is kept when an event was changed both here and on the server since the last sync
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    #[default]
+    Server,
+    Local,
+}
+
+impl ConflictPolicy {
+    pub fn from_input(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "server" | "remote" => Some(ConflictPolicy::Server),
+            "local" => Some(ConflictPolicy::Local),
+            _ => None,
+        }
+    }
+}
+
+/// A calendar found on the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DavCalendar {
+    pub url: String,
+    pub name: String,
+}
+
+/// What is known of the server's calendar since the last sync, kept in `caldav-state.json`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncState {
+    pub calendar_url: String,
+    /// Token of the last sync-collection REPORT, the next one only lists the changes since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_token: Option<String>,
+    #[serde(default)]
+    pub items: Vec<SyncItem>,
+}
+
+/// A resource of the server's calendar
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncItem {
+    /// Local event, 0 for resources that are not events (e.g. to-dos), which are left alone
+    pub id: u32,
+    /// Path of the resource on the server
+    pub href: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// The event (as JSON) after the last sync, to tell the local changes
+    #[serde(default)]
+    pub synced: String,
+}
+
+impl SyncState {
+    fn load(path: &Path) -> StorageResult<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&data)
+            .map_err(|e| format!("Malformed {}: {}", path.display(), e))?)
+    }
+
+    fn save(&self, path: &Path) -> StorageResult<()> {
+        write_atomic(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    fn position(&self, href: &str) -> Option<usize> {
+        self.items.iter().position(|i| i.href == href)
+    }
+}
+
+/// What a sync did
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncReport {
+    pub calendar: String,
+    /// Events added or changed here
+    pub pulled: usize,
+    /// Events created or changed on the server
+    pub pushed: usize,
+    pub deleted_locally: usize,
+    pub deleted_remotely: usize,
+    /// Events changed on both sides, and whose version was kept
+    pub conflicts: Vec<String>,
+    /// Resources of the server that could not be read, and events that could not be stored
+    pub skipped: Vec<String>,
+}
+
+impl SyncReport {
+    /// One line, e.g. "Team: 2 pulled, 1 pushed, 1 conflict"
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if self.pulled > 0 {
+            parts.push(format!("{} pulled", self.pulled));
+        }
+        if self.pushed > 0 {
+            parts.push(format!("{} pushed", self.pushed));
+        }
+        let deleted = self.deleted_locally + self.deleted_remotely;
+        if deleted > 0 {
+            parts.push(format!("{} deleted", deleted));
+        }
+        if !self.conflicts.is_empty() {
+            parts.push(format!("{} conflict(s)", self.conflicts.len()));
+        }
+        if !self.skipped.is_empty() {
+            parts.push(format!("{} skipped", self.skipped.len()));
+        }
+        if parts.is_empty() {
+            parts.push(String::from("up to date"));
+        }
+        format!("{}: {}", self.calendar, parts.join(", "))
+    }
+
+    /// The summary followed by the conflicts and the skipped resources
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("Synced {}", self.summary())];
+        lines.extend(self.conflicts.iter().map(|c| format!("  Conflict: {}", c)));
+        lines.extend(self.skipped.iter().map(|s| format!("  Skipped {}", s)));
+        lines
+    }
+}
+
+/// Lists the calendars holding events that the configured URL leads to
+pub async fn list_calendars(config: &CalDavConfig) -> StorageResult<Vec<DavCalendar>> {
+    DavClient::new(config)?.discover(&config.url).await
+}
+
+/// Syncs the events of the configured project with the calendar on the server, both ways
+/// `state_path` keeps what was synced, so that the changes of each side can be told apart
+pub async fn sync(
+    storage: &mut dyn Storage,
+    config: &CalDavConfig,
+    state_path: &Path,
+) -> StorageResult<SyncReport> {
+    let client = DavClient::new(config)?;
+    let calendar = select_calendar(client.discover(&config.url).await?, config)?;
+    let mut state = SyncState::load(state_path)?;
+    if state.calendar_url != calendar.url {
+        // Another calendar: every event of the project is new to it
+        state = SyncState {
+            calendar_url: calendar.url.clone(),
+            ..SyncState::default()
+        };
+    }
+    let mut session = Session {
+        client,
+        calendar_url: Url::parse(&calendar.url)?,
+        config,
+        storage,
+        state,
+        report: SyncReport {
+            calendar: calendar.name,
+            ..SyncReport::default()
+        },
+    };
+    // Saved even if the sync stops half-way, the state matches what was done
+    let result = session.run().await;
+    session.state.save(state_path)?;
+    result.map(|_| session.report)
+}
+
+/// What the background sync tells the calendar
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncStatus {
+    /// e.g. "Synced 10:42: Team: up to date"
+    pub line: String,
+    /// Conflicts and skipped resources of the sync, listed in the To-Do pane
+    pub details: Vec<String>,
+}
+
+impl SyncStatus {
+    fn new(line: String) -> Self {
+        Self {
+            line,
+            details: Vec::new(),
+        }
+    }
+}
+
+/// Syncs in the background while the calendar is open: at start, every `interval_minutes`
+/// and when asked to. The worker has its own storage, locked with `StorageLock` during a sync;
+/// the calendar sees the changes on disk
+pub struct SyncWorker {
+    requests: Sender<()>,
+    statuses: Receiver<SyncStatus>,
+}
+
+impl SyncWorker {
+    pub fn start(config: CalDavConfig, kind: StorageKind, paths: AppPaths) -> Self {
+        let (requests, request_receiver) = channel();
+        let (status_sender, statuses) = channel();
+        thread::spawn(move || run_worker(config, kind, paths, request_receiver, status_sender));
+        Self { requests, statuses }
+    }
+
+    pub fn request_sync(&self) {
+        // The worker only stops with the app
+        let _ = self.requests.send(());
+    }
+
+    /// The last status sent since the previous call
+    pub fn latest_status(&self) -> Option<SyncStatus> {
+        self.statuses.try_iter().last()
+    }
+}
+
+fn run_worker(
+    config: CalDavConfig,
+    kind: StorageKind,
+    paths: AppPaths,
+    requests: Receiver<()>,
+    statuses: Sender<SyncStatus>,
+) {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            let _ = statuses.send(SyncStatus::new(format!("Sync unavailable: {}", e)));
+            return;
+        }
+    };
+    let state_path: PathBuf = paths.caldav_state();
+    loop {
+        let _ = statuses.send(SyncStatus::new(String::from("Syncing…")));
+        // The app waits for the sync before running a command, and the other way round
+        let result = StorageLock::acquire(&paths).and_then(|_lock| {
+            let mut storage = open_storage(kind, &paths)?;
+            runtime.block_on(sync(storage.as_mut(), &config, &state_path))
+        });
+        let time = Local::now().format("%H:%M");
+        let status = match result {
+            Ok(report) => SyncStatus {
+                line: format!("Synced {}: {}", time, report.summary()),
+                details: if report.conflicts.is_empty() && report.skipped.is_empty() {
+                    Vec::new()
+                } else {
+                    report.lines()
+                },
+            },
+            Err(e) => SyncStatus::new(format!("Sync failed {}: {}", time, e)),
+        };
+        if statuses.send(status).is_err() {
+            return;
+        }
+
+        let next = if config.interval_minutes == 0 {
+            requests.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            requests.recv_timeout(Duration::from_secs(config.interval_minutes * 60))
+        };
+        match next {
+            Ok(()) | Err(RecvTimeoutError::Timeout) => {
+                // Requests made during the sync are served by the next one
+                while requests.try_recv().is_ok() {}
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// The calendar named in the configuration, by display name or path, or the first one
+fn select_calendar(
+    calendars: Vec<DavCalendar>,
+    config: &CalDavConfig,
+) -> StorageResult<DavCalendar> {
+    let names = calendars
+        .iter()
+        .map(|c| c.name.clone())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let selected = match &config.calendar {
+        None => calendars.into_iter().next(),
+        Some(wanted) => calendars.into_iter().find(|c| {
+            c.name.eq_ignore_ascii_case(wanted)
+                || c.url
+                    .trim_end_matches('/')
+                    .ends_with(wanted.trim_end_matches('/'))
+        }),
+    };
+    match (selected, &config.calendar) {
+        (Some(calendar), _) => Ok(calendar),
+        (None, None) => Err("No calendar with events found on the server".into()),
+        (None, Some(wanted)) => Err(format!(
+            "No calendar {:?} on the server, found: {}",
+            wanted,
+            if names.is_empty() { "none" } else { &names }
+        )
+        .into()),
+    }
+}
+
+/// Changes made on this side to a synced event
+enum LocalChange {
+    Unchanged,
+    /// With the event's name, for the conflict reports
+    Modified(String),
+    Deleted,
+}
+
+/// One sync, from pulling the server's changes to pushing the local ones
+struct Session<'a> {
+    client: DavClient,
+    calendar_url: Url,
+    config: &'a CalDavConfig,
+    storage: &'a mut dyn Storage,
+    state: SyncState,
+    report: SyncReport,
+}
+
+impl Session<'_> {
+    async fn run(&mut self) -> StorageResult<()> {
+        let changes = self
+            .client
+            .remote_changes(&self.calendar_url, &self.state)
+            .await?;
+        let local = self.local_events()?;
+        let fetched = self
+            .client
+            .fetch(&self.calendar_url, &changes.changed)
+            .await?;
+        for (href, etag, data) in fetched {
+            self.pull(href, etag, &data, &local)?;
+        }
+        for href in &changes.removed {
+            self.pull_removal(href, &local)?;
+        }
+        self.state.sync_token = changes.sync_token;
+        self.push().await
+    }
+
+    /// Local events of the synced project
+    fn local_events(&mut self) -> StorageResult<Vec<Events>> {
+        let project = &self.config.project;
+        Ok(self
+            .storage
+            .load_events()?
+            .into_iter()
+            .filter(|ev| {
+                ev.project
+                    .as_deref()
+                    .is_some_and(|p| p.eq_ignore_ascii_case(project))
+            })
+            .collect())
+    }
+
+    fn local_change(&self, index: usize, local: &[Events]) -> LocalChange {
+        let item = &self.state.items[index];
+        match local.iter().find(|ev| ev.id == item.id) {
+            None => LocalChange::Deleted,
+            Some(ev) if event_json(ev) != item.synced => {
+                LocalChange::Modified(ev.event_name.clone())
+            }
+            Some(_) => LocalChange::Unchanged,
+        }
+    }
+
+    /// Applies a resource changed on the server
+    fn pull(
+        &mut self,
+        href: String,
+        etag: String,
+        data: &str,
+        local: &[Events],
+    ) -> StorageResult<()> {
+        let index = self.state.position(&href);
+        let Some(remote) = self.parse_remote(&href, data) else {
+            // Kept in the state so that it is not downloaded again until it changes
+            match index {
+                Some(index) => self.state.items[index].etag = Some(etag),
+                None => self.state.items.push(SyncItem {
+                    id: 0,
+                    href,
+                    etag: Some(etag),
+                    synced: String::new(),
+                }),
+            }
+            return Ok(());
+        };
+
+        let Some(index) = index else {
+            // A new event, or one imported before from the same .ics file
+            let linked = local.iter().find(|ev| {
+                ev.uid.is_some()
+                    && ev.uid == remote.uid
+                    && !self.state.items.iter().any(|i| i.id == ev.id)
+            });
+            let id = linked.map(|ev| ev.id);
+            return self.store_remote(remote, id, href, etag);
+        };
+        let id = self.state.items[index].id;
+        let name = remote.event_name.clone();
+        match (self.local_change(index, local), self.config.conflicts) {
+            (LocalChange::Unchanged, _) => self.store_remote(remote, Some(id), href, etag),
+            (LocalChange::Modified(local_name), ConflictPolicy::Server) => {
+                self.conflict(
+                    &local_name,
+                    "changed on both sides, kept the server's version",
+                );
+                self.store_remote(remote, Some(id), href, etag)
+            }
+            (LocalChange::Deleted, ConflictPolicy::Server) => {
+                self.conflict(&name, "deleted here but changed on the server, restored it");
+                self.store_remote(remote, None, href, etag)
+            }
+            (LocalChange::Modified(local_name), ConflictPolicy::Local) => {
+                self.conflict(&local_name, "changed on both sides, kept the local version");
+                // Pushed over the server's version below
+                self.state.items[index].etag = Some(etag);
+                Ok(())
+            }
+            (LocalChange::Deleted, ConflictPolicy::Local) => {
+                self.conflict(&name, "deleted here but changed on the server, deleted it");
+                self.state.items[index].etag = Some(etag);
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies a resource removed from the server
+    fn pull_removal(&mut self, href: &str, local: &[Events]) -> StorageResult<()> {
+        let Some(index) = self.state.position(href) else {
+            return Ok(());
+        };
+        let change = self.local_change(index, local);
+        let item = self.state.items.remove(index);
+        if item.id == 0 {
+            return Ok(());
+        }
+        match (change, self.config.conflicts) {
+            (LocalChange::Unchanged, _) => {
+                self.storage.delete_event(item.id)?;
+                self.report.deleted_locally += 1;
+            }
+            (LocalChange::Modified(name), ConflictPolicy::Server) => {
+                self.conflict(&name, "changed here but deleted on the server, deleted it");
+                self.storage.delete_event(item.id)?;
+                self.report.deleted_locally += 1;
+            }
+            (LocalChange::Modified(name), ConflictPolicy::Local) => {
+                // No longer tracked, it is created again below
+                self.conflict(&name, "changed here but deleted on the server, kept it");
+            }
+            (LocalChange::Deleted, _) => {}
+        }
+        Ok(())
+    }
+
+    /// Sends the local creations, changes and deletions
+    async fn push(&mut self) -> StorageResult<()> {
+        let local = self.local_events()?;
+
+        let deleted: Vec<SyncItem> = self
+            .state
+            .items
+            .iter()
+            .filter(|i| i.id != 0 && !local.iter().any(|ev| ev.id == i.id))
+            .cloned()
+            .collect();
+        for item in deleted {
+            let url = self.calendar_url.join(&item.href)?;
+            let precondition = item
+                .etag
+                .clone()
+                .map_or(Precondition::None, Precondition::Match);
+            match self.client.delete(&url, precondition).await? {
+                WriteResult::Done(_) => {
+                    if let Some(index) = self.state.position(&item.href) {
+                        self.state.items.remove(index);
+                    }
+                    self.report.deleted_remotely += 1;
+                }
+                WriteResult::Conflict => self.push_conflict(&item.href, None).await?,
+            }
+        }
+
+        for mut event in local {
+            match self.state.items.iter().position(|i| i.id == event.id) {
+                Some(index) if self.state.items[index].synced != event_json(&event) => {
+                    let item = self.state.items[index].clone();
+                    let url = self.calendar_url.join(&item.href)?;
+                    let precondition = item.etag.map_or(Precondition::None, Precondition::Match);
+                    match self
+                        .client
+                        .put(&url, event_ics_text(&event), precondition)
+                        .await?
+                    {
+                        WriteResult::Done(etag) => {
+                            self.state.items[index].etag = etag;
+                            self.state.items[index].synced = event_json(&event);
+                            self.report.pushed += 1;
+                        }
+                        WriteResult::Conflict => {
+                            self.push_conflict(&item.href, Some(&event)).await?
+                        }
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    // The UID is kept with the event, it names the resource on the server
+                    if event.uid.is_none() {
+                        event.uid = Some(new_uid(event.id));
+                        self.storage.update_event(&event)?;
+                    }
+                    let uid = event.uid.clone().unwrap_or_default();
+                    let url = self.calendar_url.join(&resource_name(&uid))?;
+                    match self
+                        .client
+                        .put(&url, event_ics_text(&event), Precondition::NoneMatch)
+                        .await?
+                    {
+                        WriteResult::Done(etag) => {
+                            self.state.items.push(SyncItem {
+                                id: event.id,
+                                href: url.path().to_string(),
+                                etag,
+                                synced: event_json(&event),
+                            });
+                            self.report.pushed += 1;
+                        }
+                        WriteResult::Conflict => self.report.skipped.push(format!(
+                            "{:?}: the server already has an event with UID {}",
+                            event.event_name, uid
+                        )),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The server's version changed since the last sync (a 412 answer to a change or deletion)
+    async fn push_conflict(&mut self, href: &str, event: Option<&Events>) -> StorageResult<()> {
+        let name = event.map_or(href, |ev| ev.event_name.as_str()).to_string();
+        let url = self.calendar_url.join(href)?;
+        match self.config.conflicts {
+            ConflictPolicy::Local => {
+                self.conflict(
+                    &name,
+                    "changed on the server meanwhile, kept the local version",
+                );
+                let index = self.state.position(href);
+                match event {
+                    Some(event) => {
+                        let etag = match self
+                            .client
+                            .put(&url, event_ics_text(event), Precondition::None)
+                            .await?
+                        {
+                            WriteResult::Done(etag) => etag,
+                            WriteResult::Conflict => None,
+                        };
+                        if let Some(index) = index {
+                            self.state.items[index].etag = etag;
+                            self.state.items[index].synced = event_json(event);
+                        }
+                        self.report.pushed += 1;
+                    }
+                    None => {
+                        self.client.delete(&url, Precondition::None).await?;
+                        if let Some(index) = index {
+                            self.state.items.remove(index);
+                        }
+                        self.report.deleted_remotely += 1;
+                    }
+                }
+            }
+            ConflictPolicy::Server => {
+                self.conflict(
+                    &name,
+                    "changed on the server meanwhile, kept the server's version",
+                );
+                let fetched = self
+                    .client
+                    .fetch(&self.calendar_url, &[href.to_string()])
+                    .await?;
+                for (href, etag, data) in fetched {
+                    let Some(remote) = self.parse_remote(&href, &data) else {
+                        continue;
+                    };
+                    let id = event.map(|ev| ev.id);
+                    self.store_remote(remote, id, href, etag)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The event of a resource, `None` for other components and unreadable data (reported)
+    fn parse_remote(&mut self, href: &str, data: &str) -> Option<Events> {
+        match import_ics(data, &[]) {
+            Ok(import) => import.events.into_iter().next(),
+            Err(e) => {
+                self.report.skipped.push(format!("{}: {}", href, e));
+                None
+            }
+        }
+    }
+
+    /// Writes the server's version of an event here, over the event `id` or as a new one
+    fn store_remote(
+        &mut self,
+        mut remote: Events,
+        id: Option<u32>,
+        href: String,
+        etag: String,
+    ) -> StorageResult<()> {
+        remote.project = Some(self.config.project.clone());
+        match id {
+            Some(id) => {
+                remote.id = id;
+                self.storage.update_event(&remote)?;
+            }
+            None => {
+                remote.id = 0;
+                remote.id = self.storage.insert_event(remote.clone())?;
+            }
+        }
+        let item = SyncItem {
+            id: remote.id,
+            href: href.clone(),
+            etag: Some(etag),
+            synced: event_json(&remote),
+        };
+        match self.state.position(&href) {
+            Some(index) => self.state.items[index] = item,
+            None => self.state.items.push(item),
+        }
+        self.report.pulled += 1;
+        Ok(())
+    }
+
+    fn conflict(&mut self, name: &str, what: &str) {
+        self.report.conflicts.push(format!("{:?} {}", name, what));
+    }
+}
+
+fn event_json(event: &Events) -> String {
+    serde_json::to_string(event).unwrap_or_default()
+}
+
+/// A UID for an event created here, unique enough without a random generator
+fn new_uid(id: u32) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{:x}-{}@rusty-calendar", nanos, std::process::id(), id)
+}
+
+/// File name of a new resource, from its UID, with only the characters safe in a URL
+fn resource_name(uid: &str) -> String {
+    let name: String = uid
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.ics", name)
+}
+
+/// Condition of a PUT or DELETE, so that changes made meanwhile on the server are not overwritten
+enum Precondition {
+    None,
+    /// Only if the resource still has this ETag
+    Match(String),
+    /// Only if the resource does not exist yet
+    NoneMatch,
+}
+
+enum WriteResult {
+    /// With the new ETag, when the server gives it
+    Done(Option<String>),
+    /// The precondition failed
+    Conflict,
+}
+
+/// One `<response>` of a multistatus
+struct DavResponse {
+    /// Path of the resource
+    href: String,
+    /// Status of the whole response, e.g. 404 for resources removed since a sync token
+    status: Option<u16>,
+    /// Properties found (the ones of the successful propstats)
+    props: Vec<Element>,
+}
+
+impl DavResponse {
+    fn prop(&self, namespace: &str, name: &str) -> Option<&Element> {
+        self.props.iter().find(|p| p.is(namespace, name))
+    }
+
+    /// The URL in a property such as `current-user-principal`
+    fn href_prop(&self, namespace: &str, name: &str) -> Option<String> {
+        self.prop(namespace, name)?.child_text(DAV, "href")
+    }
+
+    fn is_calendar(&self) -> bool {
+        self.prop(DAV, "resourcetype")
+            .is_some_and(|t| t.child(CALDAV, "calendar").is_some())
+    }
+
+    /// Calendars that do not tell their components may hold events
+    fn supports_events(&self) -> bool {
+        self.prop(CALDAV, "supported-calendar-component-set")
+            .is_none_or(|set| {
+                set.children(CALDAV, "comp")
+                    .any(|c| c.attribute("name") == Some("VEVENT"))
+            })
+    }
+}
+
+/// Body of a 207 Multi-Status answer
+struct Multistatus {
+    responses: Vec<DavResponse>,
+    sync_token: Option<String>,
+}
+
+/// Resources changed on the server since the last sync
+struct RemoteChanges {
+    /// Paths of the new and changed resources
+    changed: Vec<String>,
+    removed: Vec<String>,
+    sync_token: Option<String>,
+}
+
+/// Talks WebDAV to the server
+struct DavClient {
+    http: reqwest::Client,
+    username: String,
+    password: String,
+}
+
+impl DavClient {
+    fn new(config: &CalDavConfig) -> StorageResult<Self> {
+        Ok(Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()?,
+            username: config.username.clone(),
+            password: config.password.clone(),
+        })
+    }
+
+    fn request(&self, method: &str, url: &Url) -> StorageResult<reqwest::RequestBuilder> {
+        let request = self
+            .http
+            .request(Method::from_bytes(method.as_bytes())?, url.clone());
+        Ok(if self.username.is_empty() {
+            request
+        } else {
+            request.basic_auth(&self.username, Some(&self.password))
+        })
+    }
+
+    /// Sends a PROPFIND or REPORT, the multistatus is only read from a 207 answer
+    async fn dav_request(
+        &self,
+        method: &str,
+        url: &Url,
+        depth: Option<&str>,
+        body: String,
+    ) -> StorageResult<(StatusCode, Option<Multistatus>)> {
+        let mut request = self
+            .request(method, url)?
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(body);
+        if let Some(depth) = depth {
+            request = request.header("Depth", depth);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Could not reach {}: {}", url, e))?;
+        let status = response.status();
+        if status != StatusCode::MULTI_STATUS {
+            return Ok((status, None));
+        }
+        let text = response.text().await?;
+        let multistatus = parse_multistatus(url, &text)
+            .map_err(|e| format!("Malformed answer to {} {}: {}", method, url, e))?;
+        Ok((status, Some(multistatus)))
+    }
+
+    async fn propfind(&self, url: &Url, depth: &str, props: &str) -> StorageResult<Multistatus> {
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<d:propfind xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">"#,
+                "<d:prop>{}</d:prop></d:propfind>"
+            ),
+            props
+        );
+        match self.dav_request("PROPFIND", url, Some(depth), body).await? {
+            (_, Some(multistatus)) => Ok(multistatus),
+            (StatusCode::UNAUTHORIZED, None) => {
+                Err(format!("{} refused the credentials", url).into())
+            }
+            (status, None) => Err(format!("PROPFIND {} failed: {}", url, status).into()),
+        }
+    }
+
+    /// Finds the calendars from a URL that may be a calendar, a calendar home,
+    /// a principal or just the server
+    async fn discover(&self, url: &Url) -> StorageResult<Vec<DavCalendar>> {
+        let found = self
+            .propfind(
+                url,
+                "0",
+                "<d:resourcetype/><d:displayname/><d:current-user-principal/><c:calendar-home-set/>",
+            )
+            .await?;
+        let response = found
+            .responses
+            .first()
+            .ok_or(format!("{} answered without properties", url))?;
+        if response.is_calendar() {
+            return Ok(vec![calendar_from(url, response)]);
+        }
+
+        let home = match response.href_prop(CALDAV, "calendar-home-set") {
+            Some(home) => home,
+            None => {
+                let principal =
+                    response
+                        .href_prop(DAV, "current-user-principal")
+                        .ok_or(format!(
+                            "{} is not a calendar and does not tell the user's principal",
+                            url
+                        ))?;
+                let principal_url = url.join(&principal)?;
+                let found = self
+                    .propfind(&principal_url, "0", "<c:calendar-home-set/>")
+                    .await?;
+                found
+                    .responses
+                    .first()
+                    .and_then(|r| r.href_prop(CALDAV, "calendar-home-set"))
+                    .ok_or(format!("{} has no calendar home", principal_url))?
+            }
+        };
+        let home_url = url.join(&home)?;
+        let listing = self
+            .propfind(
+                &home_url,
+                "1",
+                "<d:resourcetype/><d:displayname/><c:supported-calendar-component-set/>",
+            )
+            .await?;
+        Ok(listing
+            .responses
+            .iter()
+            .filter(|r| r.is_calendar() && r.supports_events())
+            .map(|r| calendar_from(&home_url, r))
+            .collect())
+    }
+
+    /// A sync-collection REPORT, `None` if the server does not support it or refuses the token
+    async fn sync_collection(
+        &self,
+        calendar: &Url,
+        token: &str,
+    ) -> StorageResult<Option<Multistatus>> {
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<d:sync-collection xmlns:d="DAV:">"#,
+                "<d:sync-token>{}</d:sync-token><d:sync-level>1</d:sync-level>",
+                "<d:prop><d:getetag/></d:prop></d:sync-collection>"
+            ),
+            xml::escape(token)
+        );
+        match self.dav_request("REPORT", calendar, None, body).await? {
+            (_, Some(multistatus)) => Ok(Some(multistatus)),
+            (status, None)
+                if status.is_client_error() && status != StatusCode::UNAUTHORIZED
+                    || status == StatusCode::NOT_IMPLEMENTED =>
+            {
+                Ok(None)
+            }
+            (status, None) => Err(format!("REPORT {} failed: {}", calendar, status).into()),
+        }
+    }
+
+    /// Lists the changes since the last sync, with the sync token when the server
+    /// supports it, otherwise by comparing the ETags of all the resources
+    async fn remote_changes(
+        &self,
+        calendar: &Url,
+        state: &SyncState,
+    ) -> StorageResult<RemoteChanges> {
+        let mut complete = true;
+        let mut multistatus = None;
+        if let Some(token) = &state.sync_token {
+            multistatus = self.sync_collection(calendar, token).await?;
+            complete = multistatus.is_none();
+        }
+        if multistatus.is_none() {
+            // Without a token, or with an expired one, every resource is listed
+            multistatus = self.sync_collection(calendar, "").await?;
+        }
+        let multistatus = match multistatus {
+            Some(multistatus) => multistatus,
+            None => self.propfind(calendar, "1", "<d:getetag/>").await?,
+        };
+        Ok(changes_in(&multistatus, calendar, state, complete))
+    }
+
+    /// Downloads resources with calendar-multiget REPORTs, as (href, ETag, data)
+    /// Resources removed meanwhile are left out
+    async fn fetch(
+        &self,
+        calendar: &Url,
+        hrefs: &[String],
+    ) -> StorageResult<Vec<(String, String, String)>> {
+        let mut fetched = Vec::new();
+        for batch in hrefs.chunks(MULTIGET_BATCH) {
+            let hrefs: String = batch
+                .iter()
+                .map(|href| format!("<d:href>{}</d:href>", xml::escape(href)))
+                .collect();
+            let body = format!(
+                concat!(
+                    r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                    r#"<c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">"#,
+                    "<d:prop><d:getetag/><c:calendar-data/></d:prop>{}</c:calendar-multiget>"
+                ),
+                hrefs
+            );
+            let multistatus = match self
+                .dav_request("REPORT", calendar, Some("1"), body)
+                .await?
+            {
+                (_, Some(multistatus)) => multistatus,
+                (status, None) => {
+                    return Err(format!("REPORT {} failed: {}", calendar, status).into())
+                }
+            };
+            for response in multistatus.responses {
+                let etag = response
+                    .prop(DAV, "getetag")
+                    .map(|e| e.text.trim().to_string());
+                let data = response
+                    .prop(CALDAV, "calendar-data")
+                    .map(|e| e.text.clone());
+                if let (Some(etag), Some(data)) = (etag, data) {
+                    fetched.push((response.href, etag, data));
+                }
+            }
+        }
+        Ok(fetched)
+    }
+
+    async fn put(
+        &self,
+        url: &Url,
+        data: String,
+        precondition: Precondition,
+    ) -> StorageResult<WriteResult> {
+        let request = self
+            .request("PUT", url)?
+            .header(CONTENT_TYPE, "text/calendar; charset=utf-8")
+            .body(data);
+        self.write("PUT", url, request, precondition).await
+    }
+
+    /// A resource already removed counts as deleted
+    async fn delete(&self, url: &Url, precondition: Precondition) -> StorageResult<WriteResult> {
+        let request = self.request("DELETE", url)?;
+        self.write("DELETE", url, request, precondition).await
+    }
+
+    async fn write(
+        &self,
+        method: &str,
+        url: &Url,
+        request: reqwest::RequestBuilder,
+        precondition: Precondition,
+    ) -> StorageResult<WriteResult> {
+        let request = match precondition {
+            Precondition::None => request,
+            Precondition::Match(etag) => request.header(IF_MATCH, etag),
+            Precondition::NoneMatch => request.header(IF_NONE_MATCH, "*"),
+        };
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Could not reach {}: {}", url, e))?;
+        let status = response.status();
+        if status == StatusCode::PRECONDITION_FAILED {
+            return Ok(WriteResult::Conflict);
+        }
+        if !(status.is_success() || method == "DELETE" && status == StatusCode::NOT_FOUND) {
+            return Err(format!("{} {} failed: {}", method, url, status).into());
+        }
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(String::from);
+        Ok(WriteResult::Done(etag))
+    }
+}
+
+/// The resources of a listing that are new or changed since the last sync, and the ones removed:
+/// listed with a 404, or (if the listing is `complete`) known but no longer listed
+fn changes_in(
+    multistatus: &Multistatus,
+    calendar: &Url,
+    state: &SyncState,
+    complete: bool,
+) -> RemoteChanges {
+    let collection = calendar.path().trim_end_matches('/');
+    let mut changes = RemoteChanges {
+        changed: Vec::new(),
+        removed: Vec::new(),
+        sync_token: multistatus.sync_token.clone(),
+    };
+    let mut listed = Vec::new();
+    for response in &multistatus.responses {
+        if response.href.trim_end_matches('/') == collection {
+            continue;
+        }
+        if response.status == Some(404) {
+            changes.removed.push(response.href.clone());
+            continue;
+        }
+        let Some(etag) = response.prop(DAV, "getetag").map(|e| e.text.trim()) else {
+            continue;
+        };
+        listed.push(response.href.clone());
+        let known = state
+            .items
+            .iter()
+            .any(|i| i.href == response.href && i.etag.as_deref() == Some(etag));
+        if !known {
+            changes.changed.push(response.href.clone());
+        }
+    }
+    if complete {
+        for item in &state.items {
+            if !listed.contains(&item.href) && !changes.removed.contains(&item.href) {
+                changes.removed.push(item.href.clone());
+            }
+        }
+    }
+    changes
+}
+
+fn calendar_from(base: &Url, response: &DavResponse) -> DavCalendar {
+    let url = base
+        .join(&response.href)
+        .map_or(base.to_string(), |url| url.to_string());
+    let name = response
+        .prop(DAV, "displayname")
+        .map(|n| n.text.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| {
+            response
+                .href
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        });
+    DavCalendar { url, name }
+}
+
+/// Reads a multistatus, the hrefs are made paths (some servers give whole URLs)
+fn parse_multistatus(base: &Url, text: &str) -> Result<Multistatus, String> {
+    let root = xml::parse(text)?;
+    if !root.is(DAV, "multistatus") {
+        return Err(format!("expected a multistatus, got <{}>", root.name));
+    }
+    let mut responses = Vec::new();
+    for response in root.children(DAV, "response") {
+        let Some(href) = response.child_text(DAV, "href") else {
+            continue;
+        };
+        let href = base.join(&href).map_or(href, |url| url.path().to_string());
+        let status = response
+            .child_text(DAV, "status")
+            .and_then(|s| status_code(&s));
+        let props = response
+            .children(DAV, "propstat")
+            .filter(|propstat| {
+                propstat
+                    .child_text(DAV, "status")
+                    .and_then(|s| status_code(&s))
+                    .is_none_or(|code| (200..300).contains(&code))
+            })
+            .filter_map(|propstat| propstat.child(DAV, "prop"))
+            .flat_map(|prop| prop.children.iter().cloned())
+            .collect();
+        responses.push(DavResponse {
+            href,
+            status,
+            props,
+        });
+    }
+    Ok(Multistatus {
+        responses,
+        sync_token: root.child_text(DAV, "sync-token"),
+    })
+}
+
+/// The code of a status line, e.g. 404 in "HTTP/1.1 404 Not Found"
+fn status_code(line: &str) -> Option<u16> {
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://dav.example.com/calendars/me/work/").unwrap()
+    }
+
+    #[test]
+    fn multistatus_with_removed_resources() {
+        let text = concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<d:multistatus xmlns:d="DAV:">"#,
+            "<d:response>",
+            "<d:href>https://dav.example.com/calendars/me/work/a.ics</d:href>",
+            "<d:propstat><d:prop><d:getetag>\"2\"</d:getetag></d:prop>",
+            "<d:status>HTTP/1.1 200 OK</d:status></d:propstat>",
+            "</d:response>",
+            "<d:response>",
+            "<d:href>/calendars/me/work/b.ics</d:href>",
+            "<d:status>HTTP/1.1 404 Not Found</d:status>",
+            "</d:response>",
+            "<d:response>",
+            "<d:href>c%20d.ics</d:href>",
+            "<d:propstat><d:prop><d:getetag>\"7\"</d:getetag></d:prop>",
+            "<d:status>HTTP/1.1 200 OK</d:status></d:propstat>",
+            "<d:propstat><d:prop><d:displayname/></d:prop>",
+            "<d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>",
+            "</d:response>",
+            "<d:response><d:status>HTTP/1.1 200 OK</d:status></d:response>",
+            "<d:sync-token>https://dav.example.com/sync/42</d:sync-token>",
+            "</d:multistatus>"
+        );
+        let multistatus = parse_multistatus(&base(), text).unwrap();
+        assert_eq!(
+            multistatus.sync_token.as_deref(),
+            Some("https://dav.example.com/sync/42")
+        );
+        // The response without a href is left out
+        let [changed, removed, other] = &multistatus.responses[..] else {
+            panic!("3 responses expected");
+        };
+
+        assert_eq!(changed.href, "/calendars/me/work/a.ics");
+        assert_eq!(changed.status, None);
+        assert_eq!(changed.prop(DAV, "getetag").unwrap().text, "\"2\"");
+
+        assert_eq!(removed.href, "/calendars/me/work/b.ics");
+        assert_eq!(removed.status, Some(404));
+        assert!(removed.props.is_empty());
+
+        // Relative hrefs are resolved against the calendar, and missing properties are not kept
+        assert_eq!(other.href, "/calendars/me/work/c%20d.ics");
+        assert_eq!(other.props.len(), 1);
+        assert!(other.prop(DAV, "displayname").is_none());
+    }
+
+    fn state(items: &[(&str, &str)]) -> SyncState {
+        SyncState {
+            calendar_url: base().to_string(),
+            sync_token: Some(String::from("old")),
+            items: (1..)
+                .zip(items)
+                .map(|(id, (href, etag))| SyncItem {
+                    id,
+                    href: href.to_string(),
+                    etag: Some(etag.to_string()),
+                    synced: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn listing(responses: &[(&str, Option<&str>)]) -> Multistatus {
+        let responses: String = responses
+            .iter()
+            .map(|(href, etag)| match etag {
+                Some(etag) => format!(
+                    concat!(
+                        "<d:response><d:href>{}</d:href><d:propstat><d:prop>",
+                        "<d:getetag>{}</d:getetag></d:prop>",
+                        "<d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"
+                    ),
+                    href, etag
+                ),
+                None => format!(
+                    "<d:response><d:href>{}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+                    href
+                ),
+            })
+            .collect();
+        let text = format!(
+            r#"<d:multistatus xmlns:d="DAV:">{}<d:sync-token>new</d:sync-token></d:multistatus>"#,
+            responses
+        );
+        parse_multistatus(&base(), &text).unwrap()
+    }
+
+    #[test]
+    fn changes_since_a_sync_token() {
+        let state = state(&[
+            ("/calendars/me/work/a.ics", "1"),
+            ("/calendars/me/work/b.ics", "1"),
+        ]);
+        // Only the changes are listed, the resources left out are unchanged
+        let multistatus = listing(&[
+            ("/calendars/me/work/", Some("0")),
+            ("a.ics", Some("2")),
+            ("c.ics", Some("1")),
+            ("d.ics", None),
+        ]);
+        let changes = changes_in(&multistatus, &base(), &state, false);
+        assert_eq!(
+            changes.changed,
+            ["/calendars/me/work/a.ics", "/calendars/me/work/c.ics"]
+        );
+        assert_eq!(changes.removed, ["/calendars/me/work/d.ics"]);
+        assert_eq!(changes.sync_token.as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn changes_in_a_complete_listing() {
+        let state = state(&[
+            ("/calendars/me/work/a.ics", "1"),
+            ("/calendars/me/work/b.ics", "1"),
+            ("/calendars/me/work/c.ics", "1"),
+        ]);
+        let multistatus = listing(&[("a.ics", Some("1")), ("c.ics", None), ("e.ics", Some("1"))]);
+        let changes = changes_in(&multistatus, &base(), &state, true);
+        assert_eq!(changes.changed, ["/calendars/me/work/e.ics"]);
+        assert_eq!(
+            changes.removed,
+            ["/calendars/me/work/c.ics", "/calendars/me/work/b.ics"]
+        );
+    }
+
+    #[test]
+    fn calendars_in_a_multistatus() {
+        let text = concat!(
+            r#"<multistatus xmlns="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">"#,
+            "<response><href>/calendars/me/</href><propstat><prop>",
+            "<resourcetype><collection/></resourcetype>",
+            "</prop><status>HTTP/1.1 200 OK</status></propstat></response>",
+            "<response><href>/calendars/me/work/</href><propstat><prop>",
+            "<resourcetype><collection/><c:calendar/></resourcetype>",
+            "<displayname>Work &amp; co</displayname>",
+            "</prop><status>HTTP/1.1 200 OK</status></propstat></response>",
+            "<response><href>/calendars/me/tasks/</href><propstat><prop>",
+            "<resourcetype><collection/><c:calendar/></resourcetype>",
+            r#"<c:supported-calendar-component-set><c:comp name="VTODO"/></c:supported-calendar-component-set>"#,
+            "</prop><status>HTTP/1.1 200 OK</status></propstat></response>",
+            "<response><href>/calendars/me/home/</href><propstat><prop>",
+            "<resourcetype><collection/><c:calendar/></resourcetype>",
+            "<displayname/>",
+            "</prop><status>HTTP/1.1 200 OK</status></propstat></response>",
+            "</multistatus>"
+        );
+        let base = Url::parse("https://dav.example.com/calendars/me/").unwrap();
+        let multistatus = parse_multistatus(&base, text).unwrap();
+        let calendars: Vec<DavCalendar> = multistatus
+            .responses
+            .iter()
+            .filter(|r| r.is_calendar() && r.supports_events())
+            .map(|r| calendar_from(&base, r))
+            .collect();
+        assert_eq!(
+            calendars,
+            [
+                DavCalendar {
+                    url: String::from("https://dav.example.com/calendars/me/work/"),
+                    name: String::from("Work & co"),
+                },
+                DavCalendar {
+                    url: String::from("https://dav.example.com/calendars/me/home/"),
+                    name: String::from("home"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn not_a_multistatus() {
+        assert!(parse_multistatus(&base(), r#"<d:error xmlns:d="DAV:"/>"#).is_err());
+        assert!(parse_multistatus(&base(), "Internal Server Error").is_err());
+    }
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(status_code("HTTP/1.1 404 Not Found"), Some(404));
+        assert_eq!(status_code("HTTP/1.1"), None);
+    }
+
+    #[test]
+    fn resource_names_are_safe_in_urls() {
+        assert_eq!(
+            resource_name("a1/b c@example.com"),
+            "a1_b_c_example.com.ics"
+        );
+    }
+}
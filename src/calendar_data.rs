@@ -1,32 +1,179 @@
 #![allow(clippy::needless_range_loop)]
-use std::{
-    fs::{self, OpenOptions},
-    io::Write,
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Weekday};
+use chrono_tz::Tz;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Formats,
+    feeds::FeedCalendar,
+    recurrence::Recurrence,
+    schema::EventRecord,
+    storage::{Storage, StorageResult},
+    tags::{tags_text, Filter},
 };
 
-use chrono::{Datelike, Duration, Local, NaiveDateTime};
-
-#[derive(Default, Debug, Clone)]
+/// Stored through `EventRecord`, which checks the dates, time zone and recurrence when loading
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "EventRecord", into = "EventRecord")]
 pub struct Events {
+    /// Stable identifier of the event, 0 until the event is added to a calendar
+    pub id: u32,
     pub date: NaiveDateTime,
     pub event_name: String,
     pub location: String,
+    pub end: Option<NaiveDateTime>,
+    pub all_day: bool,
+    /// IANA zone the date and end are given in; `None` keeps the event at its wall-clock time (floating)
+    pub time_zone: Option<Tz>,
+    pub recurrence: Option<Recurrence>,
+    pub tags: Vec<String>,
+    /// Project (or calendar) the event belongs to
+    pub project: Option<String>,
+    /// UID of the event in the .ics file it was imported from, so that it is not imported twice
+    pub uid: Option<String>,
+    pub description: String,
 }
 
 impl Events {
     pub fn new(date: NaiveDateTime, event_name: String, location: String) -> Self {
         Self {
+            id: 0,
             date,
             event_name,
             location,
+            end: None,
+            all_day: false,
+            time_zone: None,
+            recurrence: None,
+            tags: Vec::new(),
+            project: None,
+            uid: None,
+            description: String::new(),
         }
     }
+
+    /// Length of the event, if it has an end time
+    pub fn duration(&self) -> Option<Duration> {
+        self.end.map(|end| end - self.date)
+    }
+
+    /// Last day covered by the event, which is the start day unless the event spans several days
+    pub fn last_day(&self) -> NaiveDate {
+        match self.end {
+            Some(end) if end > self.date => {
+                // An end at midnight does not cover the new day, unless the event is all-day
+                if !self.all_day && end.time() == NaiveTime::MIN {
+                    end.date().pred_opt().unwrap().max(self.date.date())
+                } else {
+                    end.date()
+                }
+            }
+            _ => self.date.date(),
+        }
+    }
+
+    /// Number of calendar days covered by the event
+    pub fn span_days(&self) -> u64 {
+        (self.last_day() - self.date.date()).num_days() as u64 + 1
+    }
+
+    /// Time range shown in the appointment pane, e.g. "09:00–10:30" or "All day"
+    /// Events spanning several days show the dates as well
+    pub fn time_range_text(&self, formats: &Formats) -> String {
+        if self.span_days() > 1 {
+            return if self.all_day {
+                format!(
+                    "All day, {}–{}",
+                    self.date.format(&formats.date),
+                    self.last_day().format(&formats.date)
+                )
+            } else {
+                let date_time_format = formats.date_time();
+                format!(
+                    "{}–{}",
+                    self.date.format(&date_time_format),
+                    self.end.unwrap().format(&date_time_format)
+                )
+            };
+        }
+        if self.all_day {
+            return String::from("All day");
+        }
+        match self.end {
+            Some(end) => format!(
+                "{}–{}",
+                self.date.format(&formats.time),
+                end.format(&formats.time)
+            ),
+            None => self.date.format(&formats.time).to_string(),
+        }
+    }
+
+    /// Converts a wall-clock time of the event into the local time zone
+    /// Floating and all-day events stay at their wall-clock time
+    pub fn to_local_time(&self, date_time: NaiveDateTime) -> NaiveDateTime {
+        match self.time_zone {
+            Some(tz) if !self.all_day => tz
+                .from_local_datetime(&date_time)
+                .earliest()
+                .map(|dt| dt.with_timezone(&Local).naive_local())
+                .unwrap_or(date_time),
+            _ => date_time,
+        }
+    }
+
+    /// Converts a local time back into the wall-clock time of the event's time zone
+    pub fn from_local_time(&self, date_time: NaiveDateTime) -> NaiveDateTime {
+        match self.time_zone {
+            Some(tz) if !self.all_day => Local
+                .from_local_datetime(&date_time)
+                .earliest()
+                .map(|dt| dt.with_timezone(&tz).naive_local())
+                .unwrap_or(date_time),
+            _ => date_time,
+        }
+    }
+
+    /// The occurrence of the event (or one of its repetitions) that covers the given day,
+    /// with its start and end converted to the local time zone
+    pub fn occurrence_covering(&self, date: NaiveDate) -> Option<Events> {
+        // An occurrence that started up to its duration earlier can still be running on that day
+        let span = self.duration().unwrap_or_default().max(Duration::zero());
+        let first_day = (date.and_time(NaiveTime::MIN) - span).date();
+        // The occurrences are searched one day further on both sides, since a time zone can move
+        // them to the previous or next day
+        let starts = match &self.recurrence {
+            Some(recurrence) => {
+                recurrence.occurrences_between(self.date, first_day.pred_opt()?, date.succ_opt()?)
+            }
+            None => vec![self.date],
+        };
+        starts
+            .into_iter()
+            .map(|start| self.to_local_time(start))
+            .filter(|start| first_day <= start.date() && start.date() <= date)
+            .find_map(|start| {
+                // The occurrence keeps the duration of the original event but moves to its own start
+                let mut occurrence = self.clone();
+                occurrence.date = start;
+                occurrence.end = self.duration().map(|d| start + d);
+                (date <= occurrence.last_day()).then_some(occurrence)
+            })
+    }
+
+    /// Whether the event (or one of its repetitions) takes place on the given day
+    pub fn occurs_on(&self, date: NaiveDate) -> bool {
+        self.occurrence_covering(date).is_some()
+    }
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct Calendar {
     pub current_date: chrono::NaiveDateTime,
     pub all_events: Vec<Events>,
+    /// Subscribed calendars, shown with the events but never written to the storage
+    pub feeds: Vec<FeedCalendar>,
 }
 
 impl Calendar {
@@ -34,6 +181,7 @@ impl Calendar {
         Self {
             current_date: chrono::Local::now().naive_local(),
             all_events: Vec::new(),
+            feeds: Vec::new(),
         }
     }
 
@@ -62,42 +210,97 @@ impl Calendar {
         }
     }
 
-    pub fn add_event_to_calendar(&mut self, event: Events) {
+    /// Next free event ID, IDs start at 1
+    pub fn next_event_id(&self) -> u32 {
+        self.all_events.iter().map(|ev| ev.id).max().unwrap_or(0) + 1
+    }
+
+    pub fn add_event_to_calendar(&mut self, mut event: Events) {
+        if event.id == 0 {
+            event.id = self.next_event_id();
+        }
         self.all_events.push(event);
     }
 
+    pub fn get_event_by_id_mut(&mut self, id: u32) -> Option<&mut Events> {
+        self.all_events.iter_mut().find(|ev| ev.id == id)
+    }
+
+    pub fn delete_event_from_calendar(&mut self, id: u32) -> Option<Events> {
+        let index = self.all_events.iter().position(|ev| ev.id == id)?;
+        Some(self.all_events.remove(index))
+    }
+
+    /// ID of the appointment listed at the given (1-based) position of the appointment pane for that day
+    pub fn get_event_id_by_index(&self, date: NaiveDateTime, index: usize) -> Option<u32> {
+        let events = self.get_event_from_calendar(date);
+        events.get(index.checked_sub(1)?).map(|ev| ev.id)
+    }
+
     pub fn get_event_from_calendar(&self, date: NaiveDateTime) -> Vec<Events> {
         // Since the dates are in NaiveDateTime, they need to be searched against NaiveDate only.
         // I want to find if there are any appointments for today (NaiveDate) only.
-        let indices: Vec<_> = self
+        // Recurring events are expanded, so that each occurrence is searched as well,
+        // and events spanning several days are found on every day they cover.
+        // The returned occurrences are in the local time zone.
+        let mut event_vec: Vec<Events> = self
             .all_events
             .iter()
-            .enumerate()
-            .filter_map(|(index, ev)| {
-                if ev.date.date() == date.date() {
-                    Some(index)
-                } else {
-                    None
-                }
+            .filter_map(|ev| ev.occurrence_covering(date.date()))
+            .collect();
+
+        // All-day events are listed first, the others by their start
+        event_vec.sort_by_key(|ev| (!ev.all_day, ev.date));
+
+        event_vec
+    }
+
+    /// Occurrences of the subscribed feeds on that day, with the name of their feed
+    /// They are listed apart, so that the positions used by del and edit stay the same
+    pub fn get_feed_events(&self, date: NaiveDateTime) -> Vec<(String, Events)> {
+        let mut feed_events: Vec<(String, Events)> = self
+            .feeds
+            .iter()
+            .flat_map(|feed| {
+                feed.events
+                    .iter()
+                    .filter_map(|ev| ev.occurrence_covering(date.date()))
+                    .map(|ev| (feed.name.clone(), ev))
             })
             .collect();
+        feed_events.sort_by_key(|(_, ev)| (!ev.all_day, ev.date));
+        feed_events
+    }
 
-        // Filter Map Logic: The filter_map closure should return the index if the condition is met, otherwise None (important)
+    /// Whether an event, own or subscribed, occurs on that day
+    pub fn has_events_on(&self, date: NaiveDate) -> bool {
+        self.all_events
+            .iter()
+            .chain(self.feeds.iter().flat_map(|feed| feed.events.iter()))
+            .any(|ev| ev.occurs_on(date))
+    }
 
-        let mut event_vec = Vec::new();
-        for &i in indices.iter() {
-            event_vec.push(self.all_events[i].clone());
+    /// Replaces the events of a feed with a newer download
+    pub fn set_feed(&mut self, feed: FeedCalendar) {
+        match self.feeds.iter().position(|f| f.name == feed.name) {
+            Some(index) => self.feeds[index] = feed,
+            None => self.feeds.push(feed),
         }
-
-        event_vec
     }
 
     // AI: Copilot generated function
     /// Get the month table for a given month
-    pub fn get_month_table(&self, calendar_date: &NaiveDateTime) -> Vec<Vec<u32>> {
+    /// Each row is a week starting on `week_start`
+    pub fn get_month_table(
+        &self,
+        calendar_date: &NaiveDateTime,
+        week_start: Weekday,
+    ) -> Vec<Vec<u32>> {
         // let first_date_of_month = calendar_date.with_day(1).unwrap();
         let first_date_of_month = *calendar_date - Duration::days(calendar_date.day0().into());
-        let day_of_firstdate = first_date_of_month.weekday().num_days_from_sunday(); // Start week from Sunday
+        let day_of_firstdate = (first_date_of_month.weekday().num_days_from_monday() + 7
+            - week_start.num_days_from_monday())
+            % 7;
 
         let mut iter_date = first_date_of_month;
 
@@ -127,20 +330,25 @@ impl Calendar {
         days_in_month
     }
 
-    pub fn generate_calendar_text(&self, calendar_date: &NaiveDateTime) -> String {
+    pub fn generate_calendar_text(
+        &self,
+        calendar_date: &NaiveDateTime,
+        week_start: Weekday,
+    ) -> String {
         let mut calendar_text = String::new();
-        let weekdays_list = vec![
-            "  Su  ", "  Mo  ", "  Tu  ", "  We  ", "  Th  ", "  Fr  ", "  Sa  ",
-        ]; // Start week from Monday
-        for weekday in weekdays_list {
-            calendar_text.push_str(&format!("{: <3}", weekday));
+        let mut weekday = week_start;
+        for _ in 0..7 {
+            // e.g. "  Mo  "
+            let weekday_label = format!("  {}  ", &weekday.to_string()[..2]);
+            calendar_text.push_str(&format!("{: <3}", weekday_label));
+            weekday = weekday.succ();
         }
         calendar_text.push('\n');
         calendar_text.push_str("--------------------------------------");
         calendar_text.push('\n');
         calendar_text.push('\n');
 
-        let month_table = self.get_month_table(calendar_date);
+        let month_table = self.get_month_table(calendar_date, week_start);
 
         for row in month_table {
             for day in row {
@@ -149,12 +357,23 @@ impl Calendar {
                                                                                // Note that the empty spaces are ignored in during rendering the frame
                                                                                // and so, we need to use the non-breaking space character (\u{00A0}) instead
                 } else {
+                    // Days with appointments (including every day of a multi-day event) are marked with a *
+                    let has_events = calendar_date
+                        .date()
+                        .with_day(day)
+                        .is_some_and(|d| self.has_events_on(d));
+                    let day_label = if has_events {
+                        format!("{}*", day)
+                    } else {
+                        day.to_string()
+                    };
+
                     let today = Local::now().naive_local().date();
                     if (calendar_date.date() == today) && (day == today.day()) {
                         // Mark today's date in the calendar view
-                        calendar_text.push_str(&format!(" ({:<1}) ", day));
+                        calendar_text.push_str(&format!("{: <6}", format!(" ({})", day_label)));
                     } else {
-                        calendar_text.push_str(&format!("  {: <3} ", day));
+                        calendar_text.push_str(&format!("  {: <3} ", day_label));
                     }
                 }
             }
@@ -165,103 +384,213 @@ impl Calendar {
         calendar_text
     }
 
-    pub fn generate_appointment_text(&mut self, date: NaiveDateTime) -> String {
+    /// Only the events matching the filter are shown, but they keep their position in the day
+    pub fn generate_appointment_text(
+        &self,
+        date: NaiveDateTime,
+        filter: &Filter,
+        formats: &Formats,
+    ) -> String {
         let mut appointment_text = String::new();
 
-        self.add_appointments_from_json();
-
         let events_to_search = self.get_event_from_calendar(date);
 
-        for ev in &events_to_search {
-            let event_name_str = String::from("- Event: ") + &ev.event_name;
-            let location_name_str = String::from("  Location: ") + &ev.location;
-            let event_time_str = String::from("Time: ") + &ev.date.time().to_string();
-            appointment_text.push_str(&format!("{: <8}", event_name_str));
-            appointment_text.push('\n');
-            appointment_text.push_str(&format!("{: <8}", location_name_str));
-            appointment_text.push('\n');
-            appointment_text.push_str(&format!("{: <8}", event_time_str));
-            appointment_text.push('\n');
-            appointment_text.push('\n');
+        for (count, ev) in events_to_search.iter().enumerate() {
+            if !filter.matches(&ev.tags, ev.project.as_deref()) {
+                continue;
+            }
+            // The position and ID are used by the del and edit commands
+            let event_name_str = format!("- Event {} (id {}): {}", count + 1, ev.id, ev.event_name);
+            push_event_details(&mut appointment_text, event_name_str, ev, date, formats);
+        }
+
+        // Subscribed events can be neither deleted nor edited, they have no position
+        for (feed, ev) in self.get_feed_events(date) {
+            if !filter.matches(&ev.tags, ev.project.as_deref()) {
+                continue;
+            }
+            let event_name_str = format!("- {} (read-only): {}", feed, ev.event_name);
+            push_event_details(&mut appointment_text, event_name_str, &ev, date, formats);
         }
 
-        if appointment_text.is_empty() {
+        if appointment_text.is_empty() && filter.is_empty() {
             appointment_text.push_str(&format!(
                 "You do not have any appointments for {:?}",
                 date.date()
             ))
+        } else if appointment_text.is_empty() {
+            appointment_text.push_str(&format!(
+                "You do not have any appointments matching {} for {:?}",
+                filter.describe(),
+                date.date()
+            ))
         }
 
         appointment_text
     }
 
-    pub fn add_appointments_from_json(&mut self) {
-        let appointment_path = "assets/appointments.json";
-        let data = fs::read_to_string(appointment_path).expect("Could not open file");
-        let app_json: serde_json::Value =
-            serde_json::from_str(&data).expect("Serde error in reading data from JSON");
+    /// Replaces the events with all the ones in the storage
+    pub fn load_events(&mut self, storage: &mut dyn Storage) -> StorageResult<()> {
+        self.all_events = storage.load_events()?;
+        Ok(())
+    }
 
-        // Manually add each element to the struct
-        let current_date_json = app_json["current_date"].as_str().unwrap().to_string();
-        let current_date_chrono = string_to_naive_date(&current_date_json);
+    /// Replaces the events with the ones that may occur between the two dates
+    pub fn load_events_between(
+        &mut self,
+        storage: &mut dyn Storage,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> StorageResult<()> {
+        self.all_events = storage.events_between(from, to)?;
+        Ok(())
+    }
+}
 
-        let events_json = app_json["all_events"]
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|event| Events {
-                date: string_to_naive_date(event["date"].as_str().unwrap()),
-                event_name: event["event_name"].as_str().unwrap().to_string(),
-                location: event["location"].as_str().unwrap().to_string(),
-            })
-            .collect::<Vec<Events>>();
-
-        self.current_date = current_date_chrono;
-        self.all_events.clear();
-        self.all_events = events_json;
-    }
-
-    pub fn add_back_events_to_json(&self) {
-        // Manually construct the updated JSON string
-        let mut updated_data = String::new();
-        updated_data.push_str("{\n");
-        updated_data.push_str(&format!("  \"current_date\": \"{}\",\n", self.current_date));
-        updated_data.push_str("  \"all_events\": [\n");
-        for (i, event) in self.all_events.iter().enumerate() {
-            updated_data.push_str("    {\n");
-            updated_data.push_str(&format!("      \"date\": \"{}\",\n", event.date));
-            updated_data.push_str(&format!(
-                "      \"event_name\": \"{}\",\n",
-                event.event_name
-            ));
-            updated_data.push_str(&format!("      \"location\": \"{}\"\n", event.location));
-            if i == self.all_events.len() - 1 {
-                updated_data.push_str("    }\n");
-            } else {
-                updated_data.push_str("    },\n");
-            }
+/// Adds an event of the appointment pane under its title line: location, tags and time
+fn push_event_details(
+    appointment_text: &mut String,
+    mut event_name_str: String,
+    ev: &Events,
+    date: NaiveDateTime,
+    formats: &Formats,
+) {
+    if ev.span_days() > 1 {
+        let day_of_event = (date.date() - ev.date.date()).num_days() + 1;
+        event_name_str.push_str(&format!(" (day {} of {})", day_of_event, ev.span_days()));
+    }
+    let location_name_str = String::from("  Location: ") + &ev.location;
+    let mut event_time_str = String::from("Time: ") + &ev.time_range_text(formats);
+    if let (Some(tz), false) = (ev.time_zone, ev.all_day) {
+        // Show the original time as well, when the event was converted from another time zone
+        let zone_time = ev.from_local_time(ev.date);
+        if zone_time != ev.date {
+            event_time_str.push_str(&format!(" ({} {})", zone_time.format(&formats.time), tz));
         }
-        updated_data.push_str("  ]\n");
-        updated_data.push_str("}\n");
-
-        // Write the updated JSON back to the file
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open("assets/appointments.json")
-            .unwrap();
-        file.write_all(updated_data.as_bytes()).unwrap();
     }
+    appointment_text.push_str(&format!("{: <8}", event_name_str));
+    appointment_text.push('\n');
+    appointment_text.push_str(&format!("{: <8}", location_name_str));
+    appointment_text.push('\n');
+    if !ev.tags.is_empty() || ev.project.is_some() {
+        let tags_str = String::from("  Tags: ") + &tags_text(&ev.tags, ev.project.as_deref());
+        appointment_text.push_str(&format!("{: <8}", tags_str));
+        appointment_text.push('\n');
+    }
+    appointment_text.push_str(&format!("{: <8}", event_time_str));
+    appointment_text.push('\n');
+    appointment_text.push('\n');
 }
 
 /// Converts string to chrono::NaiveDateTime format
 /// Both inputs of type %Y-%m-%d %H:%M:%S and %Y-%m-%dT%H:%M:%S
-/// are supported
-pub fn string_to_naive_date(s: &str) -> NaiveDateTime {
-    if let Ok(date_time) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S") {
-        date_time
+/// are supported. Fractional seconds (as written by older versions) are accepted as well
+pub fn try_string_to_naive_date(s: &str) -> Result<NaiveDateTime, chrono::ParseError> {
+    NaiveDateTime::parse_from_str(&s.trim().replace("T", " "), "%Y-%m-%d %H:%M:%S%.f")
+}
+
+/// Parses the start of an event. It may carry a time zone suffix, without it the event is floating.
+/// A start without a time (YYYY-MM-DD) makes an all-day event
+pub fn parse_event_start(input: &str) -> Option<(NaiveDateTime, bool, Option<Tz>)> {
+    let (start, time_zone) = split_time_zone(input);
+    let all_day = start.len() < 19;
+    let date = if all_day {
+        try_string_to_naive_date(&(start + &String::from(" 00:00:00")))
     } else {
-        let new_date_time = s.replace("T", " ");
-        NaiveDateTime::parse_from_str(&new_date_time, "%Y-%m-%d %H:%M:%S").unwrap()
+        try_string_to_naive_date(&start)
+    };
+    Some((date.ok()?, all_day, time_zone))
+}
+
+/// Splits an optional time zone suffix off a date-time string, e.g. `2024-09-14 13:00:00 Europe/Berlin`
+/// A trailing `Z` (as in `2024-09-14T13:00:00Z`) stands for UTC
+pub fn split_time_zone(s: &str) -> (String, Option<Tz>) {
+    let s = s.trim();
+    if let Some((date_time, zone)) = s.rsplit_once(' ') {
+        if let Ok(tz) = zone.parse::<Tz>() {
+            return (date_time.trim().to_string(), Some(tz));
+        }
+    }
+    if let Some(date_time) = s.strip_suffix('Z') {
+        return (date_time.to_string(), Some(chrono_tz::UTC));
+    }
+    (s.to_string(), None)
+}
+
+/// Converts the end of an event to chrono::NaiveDateTime format
+/// Accepts a full date-time (as in `try_string_to_naive_date`), a date, a time on the start day
+/// (%H:%M or %H:%M:%S) or a duration after the start such as 90m, 2h or 1h30m
+pub fn string_to_end_date(start: NaiveDateTime, s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim();
+    if s.len() >= 19 {
+        return NaiveDateTime::parse_from_str(&s.replace("T", " "), "%Y-%m-%d %H:%M:%S").ok();
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        // A date only ends the event at the end of that day
+        return date.and_hms_opt(23, 59, 59);
+    }
+    if let Ok(time) = chrono::NaiveTime::parse_from_str(s, "%H:%M:%S")
+        .or_else(|_| chrono::NaiveTime::parse_from_str(s, "%H:%M"))
+    {
+        return Some(start.date().and_time(time));
+    }
+
+    // Duration: hours and/or minutes
+    let mut minutes = 0;
+    let mut number = String::new();
+    for c in s.to_lowercase().chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'h' => minutes += std::mem::take(&mut number).parse::<i64>().ok()? * 60,
+            'm' => minutes += std::mem::take(&mut number).parse::<i64>().ok()?,
+            _ => return None,
+        }
+    }
+    if !number.is_empty() || minutes == 0 {
+        return None;
+    }
+    Some(start + Duration::minutes(minutes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(d: u32, h: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 9, d)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn occurrence_covering_a_later_day() {
+        // Every Monday and Wednesday, for 49 hours
+        let mut event = Events::new(at(2, 9), String::from("Shift"), String::new());
+        event.end = Some(at(4, 10));
+        event.recurrence = Some(Recurrence::from_rrule("FREQ=WEEKLY;BYDAY=MO,WE").unwrap());
+
+        let on = |d| event.occurrence_covering(at(d, 0).date()).map(|o| o.date);
+        assert_eq!(on(1), None);
+        assert_eq!(on(2), Some(at(2, 9)));
+        // Both the Monday and the Wednesday occurrences run on Wednesday
+        assert_eq!(on(4), Some(at(2, 9)));
+        assert_eq!(on(5), Some(at(4, 9)));
+        assert_eq!(on(6), Some(at(4, 9)));
+        assert_eq!(on(7), None);
+        assert_eq!(on(9), Some(at(9, 9)));
+        assert_eq!(
+            event.occurrence_covering(at(6, 0).date()).unwrap().end,
+            Some(at(6, 10))
+        );
+    }
+
+    #[test]
+    fn occurrence_ending_at_midnight() {
+        let mut event = Events::new(at(2, 22), String::from("Late"), String::new());
+        event.end = Some(at(3, 0));
+        event.recurrence = Some(Recurrence::from_rrule("FREQ=DAILY;COUNT=2").unwrap());
+        assert!(event.occurs_on(at(3, 0).date()));
+        assert!(!event.occurs_on(at(4, 0).date()));
     }
 }
@@ -8,53 +8,70 @@ use ratatui::{
     Frame,
 };
 
-use crate::calendar_data::Calendar;
+use crate::{
+    app_state::AppState,
+    config::{Config, PaneLayout},
+    tags::Filter,
+};
 
-fn get_calendar_title_block(month: u32, year: i32) -> Block<'static> {
+fn get_calendar_title_block(month: u32, year: i32, color: Color) -> Block<'static> {
     Block::default()
         .borders(Borders::ALL)
-        .fg(Color::Red)
+        .fg(color)
         .add_modifier(Modifier::BOLD)
         .title(format!(" Calendar - {:?} / {:?} ", month, year))
 }
 
-fn get_calendar_title_text() -> Paragraph<'static> {
+fn get_calendar_title_text(clock_format: &str, color: Color) -> Paragraph<'static> {
     let current_time = Local::now()
         .time()
-        .format_with_items(StrftimeItems::new("%H:%M:%S"));
+        .format_with_items(StrftimeItems::new(clock_format));
     Paragraph::new(current_time.to_string())
-        .fg(Color::LightRed)
+        .fg(color)
         // .add_modifier(Modifier::BOLD)
         .block(Block::new().padding(Padding::new(0, 2, 0, 0)))
         .alignment(Alignment::Right)
         .wrap(Wrap { trim: true })
 }
 
-fn get_calendar_text(calendar_text: String) -> Paragraph<'static> {
+fn get_calendar_text(
+    calendar_text: String,
+    color: Color,
+    layout: &PaneLayout,
+) -> Paragraph<'static> {
     Paragraph::new(calendar_text)
-        .fg(Color::DarkGray)
+        .fg(color)
         .add_modifier(Modifier::BOLD)
-        .block(Block::new().padding(Padding::new(5, 5, 2, 2)))
+        .block(Block::new().padding(Padding::new(
+            layout.padding_left,
+            layout.padding_left,
+            layout.padding_top,
+            layout.padding_top,
+        )))
         .alignment(Alignment::Left)
         .wrap(Wrap { trim: true })
 }
 
-fn get_calendar_month_block() -> Block<'static> {
-    Block::default().borders(Borders::ALL).fg(Color::DarkGray)
+fn get_calendar_month_block(color: Color) -> Block<'static> {
+    Block::default().borders(Borders::ALL).fg(color)
 }
 
-fn get_appointment_text(appointment_text: String) -> Paragraph<'static> {
+fn get_appointment_text(
+    appointment_text: String,
+    color: Color,
+    layout: &PaneLayout,
+) -> Paragraph<'static> {
     Paragraph::new(appointment_text)
-        .fg(Color::Green)
-        .block(Block::new().padding(Padding::new(5, 2, 2, 2)))
+        .fg(color)
+        .block(Block::new().padding(get_pane_padding(layout)))
         .alignment(Alignment::Left)
         .wrap(Wrap { trim: true })
 }
 
-fn get_appointment_block(day: u32, month: u32, year: i32) -> Block<'static> {
+fn get_appointment_block(day: u32, month: u32, year: i32, color: Color) -> Block<'static> {
     Block::default()
         .borders(Borders::ALL)
-        .fg(Color::Green)
+        .fg(color)
         .add_modifier(Modifier::BOLD)
         .title(format!(
             " Appointments - {:?} / {:?} / {:?} ",
@@ -64,44 +81,75 @@ fn get_appointment_block(day: u32, month: u32, year: i32) -> Block<'static> {
         ))
 }
 
-fn get_weather_block(city_name: &str) -> Block<'static> {
+fn get_weather_block(city_name: &str, color: Color) -> Block<'static> {
     Block::default()
         .borders(Borders::ALL)
-        .fg(Color::Magenta)
+        .fg(color)
         .title(format!("Weather for {:?}", city_name))
 }
 
-fn get_weather_text(weather_text: String) -> Paragraph<'static> {
+fn get_weather_text(weather_text: String, color: Color, layout: &PaneLayout) -> Paragraph<'static> {
     Paragraph::new(weather_text)
-        .fg(Color::Magenta)
-        .block(Block::new().padding(Padding::new(5, 2, 2, 2)))
+        .fg(color)
+        .block(Block::new().padding(get_pane_padding(layout)))
         .alignment(Alignment::Left)
         .wrap(Wrap { trim: true })
 }
 
+/// Padding inside the text panes, also used by the To-Do pane
+pub fn get_pane_padding(layout: &PaneLayout) -> Padding {
+    Padding::new(
+        layout.padding_left,
+        2,
+        layout.padding_top,
+        layout.padding_top,
+    )
+}
+
 pub fn main_calendar_layout(
     frame: &mut Frame<'_>,
     main_layout: &Rc<[Rect]>,
     calendar_date: &mut NaiveDateTime,
     weather_text: &String,
-    city_name: &str,
+    filter: &Filter,
+    app_state: &AppState,
+    config: &Config,
 ) {
-    let mut calendar = Calendar::new();
+    let theme = &config.theme;
     let day = calendar_date.day();
     let year = calendar_date.year();
     let month = calendar_date.month();
 
-    let calendar_title_text = get_calendar_title_text();
-    let calendar_text = calendar.generate_calendar_text(calendar_date);
-    let appointment_text = calendar.generate_appointment_text(*calendar_date);
+    let calendar_title_text = get_calendar_title_text(&config.formats.clock, theme.clock);
+    let calendar = &app_state.calendar;
+    let mut appointment_text = match &app_state.events_error {
+        Some(e) => format!("Could not load the appointments: {}", e),
+        None => calendar.generate_appointment_text(*calendar_date, filter, &config.formats),
+    };
+    // To-dos due on the selected day are listed with the appointments
+    let due_todo_text = match &app_state.todos_error {
+        Some(_) => String::new(),
+        None => {
+            app_state
+                .todolist
+                .generate_due_todo_text(calendar_date.date(), filter, &config.formats)
+        }
+    };
+    if !due_todo_text.is_empty() {
+        appointment_text.push_str("\n\n");
+        appointment_text.push_str(&due_todo_text);
+    }
+    let calendar_text = calendar.generate_calendar_text(calendar_date, config.week_start);
+
+    let pane_layout = &config.layout;
 
     let layout = Layout::default()
         .direction(Direction::Vertical)
         .constraints(
             [
-                Constraint::Percentage(5),
-                Constraint::Percentage(45),
-                Constraint::Percentage(50),
+                Constraint::Percentage(pane_layout.header_height),
+                Constraint::Percentage(pane_layout.month_height),
+                Constraint::Percentage(100 - pane_layout.header_height - pane_layout.month_height),
             ]
             .to_vec(),
         )
@@ -109,26 +157,38 @@ pub fn main_calendar_layout(
 
     let month_weather_layout = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].to_vec())
+        .constraints(
+            [
+                Constraint::Percentage(pane_layout.month_width),
+                Constraint::Percentage(100 - pane_layout.month_width),
+            ]
+            .to_vec(),
+        )
         .split(layout[1]);
 
-    let calendar_block = get_calendar_title_block(month, year);
-    let month_days_block = get_calendar_month_block();
-    let appointment_block = get_appointment_block(day, month, year);
-    let weather_block = get_weather_block(city_name);
+    let calendar_block = get_calendar_title_block(month, year, theme.calendar);
+    let month_days_block = get_calendar_month_block(theme.month);
+    let appointment_block = get_appointment_block(day, month, year, theme.appointments);
+    let weather_block = get_weather_block(&config.city, theme.weather);
 
     frame.render_widget(calendar_block.clone(), layout[0]);
     frame.render_widget(calendar_title_text, layout[0]);
 
     frame.render_widget(month_days_block.clone(), month_weather_layout[0]);
-    frame.render_widget(get_calendar_text(calendar_text), month_weather_layout[0]);
+    frame.render_widget(
+        get_calendar_text(calendar_text, theme.month, pane_layout),
+        month_weather_layout[0],
+    );
 
     frame.render_widget(weather_block.clone(), month_weather_layout[1]);
     frame.render_widget(
-        get_weather_text(weather_text.to_string()),
+        get_weather_text(weather_text.to_string(), theme.weather, pane_layout),
         month_weather_layout[1],
     );
 
     frame.render_widget(appointment_block.clone(), layout[2]);
-    frame.render_widget(get_appointment_text(appointment_text), layout[2]);
+    frame.render_widget(
+        get_appointment_text(appointment_text, theme.appointments, pane_layout),
+        layout[2],
+    );
 }
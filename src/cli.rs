@@ -0,0 +1,226 @@
+use std::path::PathBuf;
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    NaiveDate,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::{
+    csv::{CsvKind, Field},
+    paths::LEGACY_DATA_DIR,
+};
+
+/// A terminal calendar with appointments and to-dos
+/// Without a command the interactive calendar is started
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Directory holding the appointments and to-dos, instead of the platform's data directory
+    /// (can also be set with RUSTY_CALENDAR_DATA_DIR)
+    #[arg(long, value_name = "DIR", global = true)]
+    pub data_dir: Option<PathBuf>,
+
+    /// Configuration file to use instead of `config.toml` in the configuration directory
+    #[arg(long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Copy the data files of an older version (kept in `assets/` by default) into the data directory
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = LEGACY_DATA_DIR)]
+    pub migrate: Option<PathBuf>,
+
+    /// Print the result of a command as JSON
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Commands run without the interactive calendar, on the same data
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Add an appointment
+    AddEvent {
+        /// Start as YYYY-MM-DD HH:MM:SS, optionally followed by a time zone, or YYYY-MM-DD for an all-day event
+        start: String,
+        /// Title, #tags and a +project can be given in it
+        title: String,
+        #[arg(long, default_value = "")]
+        location: String,
+        /// End as a date-time, a time on the start day or a duration such as 1h30m
+        #[arg(long)]
+        end: Option<String>,
+        /// Recurrence rule, e.g. "FREQ=WEEKLY;BYDAY=MO,WE"
+        #[arg(long)]
+        rrule: Option<String>,
+    },
+    /// Add a to-do
+    AddTodo {
+        /// Title, #tags and a +project can be given in it
+        title: String,
+        /// A (highest) to E, 1 to 5 or none
+        #[arg(long, default_value = "none")]
+        priority: String,
+        /// Due date as YYYY-MM-DD, optionally followed by a time
+        #[arg(long)]
+        due: Option<String>,
+    },
+    /// List the appointments and the to-dos due between two dates (today by default)
+    List {
+        /// First day, as YYYY-MM-DD
+        #[arg(long, value_parser = parse_date)]
+        from: Option<NaiveDate>,
+        /// Last day (inclusive), the first day if not given
+        #[arg(long, value_parser = parse_date)]
+        to: Option<NaiveDate>,
+    },
+    /// Show the coming days, day by day, with the overdue to-dos
+    Agenda {
+        /// Number of days, starting today
+        #[arg(long, default_value_t = 7)]
+        days: u64,
+    },
+    /// Mark a to-do as done, by its ID
+    Done { id: u32 },
+    /// Import the appointments of an .ics file
+    /// Events already imported (with the same UID) are left out
+    Import { file: PathBuf },
+    /// Export the appointments and to-dos as an .ics file, for other calendar apps
+    Export {
+        /// File to write, the calendar is printed to stdout if not given
+        file: Option<PathBuf>,
+        /// Only the events with an occurrence on or after this day, and the to-dos due from it
+        #[arg(long, value_parser = parse_date)]
+        from: Option<NaiveDate>,
+        /// Only the events with an occurrence on or before this day, and the to-dos due by it
+        #[arg(long, value_parser = parse_date)]
+        to: Option<NaiveDate>,
+        /// Only the items carrying all these tags and this project, e.g. "#work +q4"
+        #[arg(long, value_name = "TAGS")]
+        filter: Option<String>,
+    },
+    /// Import appointments or to-dos from a CSV file, whose header names the columns
+    /// Rows with the same title and date as an item in the calendar are left out
+    ImportCsv {
+        file: PathBuf,
+        /// Read the rows without a type column as events or todos, guessed from the columns if not given
+        #[arg(long, value_parser = parse_csv_kind)]
+        kind: Option<CsvKind>,
+        /// chrono format of the dates, e.g. %d.%m.%Y, tried before the ones of the configuration
+        #[arg(long = "date-format", value_name = "FORMAT", value_parser = parse_date_format)]
+        date_formats: Vec<String>,
+        /// Column holding a field whose header is not recognized, e.g. title=Deliverable
+        #[arg(long = "column", value_name = "FIELD=HEADER", value_parser = parse_column)]
+        columns: Vec<(Field, String)>,
+        /// Only show what would be imported
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Export the appointments and to-dos as a CSV file, for spreadsheets
+    ExportCsv {
+        /// File to write, the rows are printed to stdout if not given
+        file: Option<PathBuf>,
+        /// Only write the events or the todos, both with a type column if not given
+        #[arg(long, value_parser = parse_csv_kind)]
+        kind: Option<CsvKind>,
+        /// Only the events with an occurrence on or after this day, and the to-dos due from it
+        #[arg(long, value_parser = parse_date)]
+        from: Option<NaiveDate>,
+        /// Only the events with an occurrence on or before this day, and the to-dos due by it
+        #[arg(long, value_parser = parse_date)]
+        to: Option<NaiveDate>,
+        /// Only the items carrying all these tags and this project, e.g. "#work +q4"
+        #[arg(long, value_name = "TAGS")]
+        filter: Option<String>,
+        /// chrono format of the dates, the first one of the configuration if not given
+        #[arg(long, value_name = "FORMAT", value_parser = parse_date_format)]
+        date_format: Option<String>,
+    },
+    /// Sync the events of the configured project with the CalDAV calendar of the `[caldav]` section
+    Sync {
+        /// Only list the calendars found on the server
+        #[arg(long)]
+        list_calendars: bool,
+    },
+    /// List the subscribed feeds with the state of their cached copies
+    Feeds {
+        /// Download every feed now
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// Print one or several months, or a whole year, like `cal` (the current month by default)
+    /// Days with appointments are followed by a `*`
+    #[command(visible_alias = "month")]
+    Cal {
+        /// Month as YYYY-MM, or a year as YYYY to print all of it
+        #[arg(value_parser = parse_cal_date)]
+        date: Option<CalDate>,
+        /// Number of months to print, starting with the given one
+        #[arg(long, short = 'n', default_value_t = 1)]
+        months: u32,
+        /// Print the whole year of the given month
+        #[arg(long, short = 'y')]
+        year: bool,
+        /// Show the ISO week numbers
+        #[arg(long, short = 'w')]
+        week_numbers: bool,
+        /// Highlight today
+        #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+        color: ColorChoice,
+    },
+}
+
+/// Month or year given to `cal`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalDate {
+    /// The first day of the month
+    Month(NaiveDate),
+    Year(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Only when printing to a terminal, and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("expected YYYY-MM-DD, got {:?}", s))
+}
+
+fn parse_cal_date(s: &str) -> Result<CalDate, String> {
+    if let Ok(month) = NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d") {
+        return Ok(CalDate::Month(month));
+    }
+    match s.parse::<i32>() {
+        Ok(year) if (1..=9999).contains(&year) => Ok(CalDate::Year(year)),
+        _ => Err(format!("expected YYYY-MM or YYYY, got {:?}", s)),
+    }
+}
+
+fn parse_csv_kind(s: &str) -> Result<CsvKind, String> {
+    CsvKind::from_input(s).ok_or(format!("expected events or todos, got {:?}", s))
+}
+
+fn parse_date_format(s: &str) -> Result<String, String> {
+    if StrftimeItems::new(s).any(|item| item == Item::Error) {
+        return Err(format!("invalid format {:?}", s));
+    }
+    Ok(s.to_string())
+}
+
+fn parse_column(s: &str) -> Result<(Field, String), String> {
+    let (field, header) = s
+        .split_once('=')
+        .ok_or(format!("expected FIELD=HEADER, got {:?}", s))?;
+    let field = Field::from_header(field).ok_or(format!(
+        "unknown field {:?}, use type, date, time, end, title, location, tags, project, \
+         priority, done or repeat",
+        field
+    ))?;
+    Ok((field, header.trim().to_string()))
+}
@@ -0,0 +1,740 @@
+use std::{io::IsTerminal, path::Path};
+
+use chrono::{Datelike, Days, Local, Months, NaiveDate, NaiveDateTime};
+use serde_json::{json, Value};
+
+use crate::{
+    backup::write_atomic,
+    cal_text::{months_text, year_text, CalOptions},
+    caldav::{list_calendars, sync},
+    calendar_data::{parse_event_start, split_time_zone, string_to_end_date, Calendar, Events},
+    cli::{CalDate, ColorChoice, Command},
+    config::{CalDavConfig, Config},
+    csv::{export_csv, import_csv_file, CsvOptions},
+    feeds::{fetch_all_feeds, load_cached_feeds, FeedCalendar},
+    ical::{export_ics, import_ics_file, ExportSelection},
+    paths::AppPaths,
+    recurrence::Recurrence,
+    storage::{Storage, StorageResult},
+    tags::{split_tags, tags_text, Filter},
+    to_do_data::{string_to_due_date, Priority, ToDo, ToDoList},
+};
+
+/// Runs a command of the command line on the storage and prints its result to stdout
+pub async fn run_command(
+    command: Command,
+    storage: &mut dyn Storage,
+    config: &Config,
+    paths: &AppPaths,
+    as_json: bool,
+) -> StorageResult<()> {
+    let output = match command {
+        Command::AddEvent {
+            start,
+            title,
+            location,
+            end,
+            rrule,
+        } => add_event(storage, &start, &title, location, end, rrule, as_json)?,
+        Command::AddTodo {
+            title,
+            priority,
+            due,
+        } => add_todo(storage, &title, &priority, due, as_json)?,
+        Command::List { from, to } => {
+            let from = from.unwrap_or(Local::now().date_naive());
+            let feeds = load_cached_feeds(&config.feeds, paths);
+            list(storage, &feeds, config, from, to.unwrap_or(from), as_json)?
+        }
+        Command::Agenda { days } => {
+            let feeds = load_cached_feeds(&config.feeds, paths);
+            agenda(storage, &feeds, config, days, as_json)?
+        }
+        Command::Done { id } => mark_done(storage, id, as_json)?,
+        Command::Import { file } => import(storage, &file, as_json)?,
+        Command::Export {
+            file,
+            from,
+            to,
+            filter,
+        } => {
+            let selection = export_selection(from, to, filter)?;
+            match file {
+                Some(file) => {
+                    let export = export_ics(storage, &selection)?;
+                    write_export(&file, &export.text, export.events, export.todos, as_json)?
+                }
+                None => {
+                    // The calendar itself is the output, with its CRLF line endings
+                    print!("{}", export_ics(storage, &selection)?.text);
+                    return Ok(());
+                }
+            }
+        }
+        Command::ImportCsv {
+            file,
+            kind,
+            mut date_formats,
+            columns,
+            dry_run,
+        } => {
+            date_formats.extend(config.csv_date_formats.iter().cloned());
+            let options = CsvOptions {
+                kind,
+                date_formats,
+                columns,
+            };
+            import_csv(storage, &file, &options, dry_run, config, as_json)?
+        }
+        Command::ExportCsv {
+            file,
+            kind,
+            from,
+            to,
+            filter,
+            date_format,
+        } => {
+            let selection = export_selection(from, to, filter)?;
+            let date_format = date_format
+                .or(config.csv_date_formats.first().cloned())
+                .unwrap_or(String::from("%Y-%m-%d"));
+            match file {
+                Some(file) => {
+                    let export = export_csv(storage, &selection, kind, &date_format)?;
+                    write_export(&file, &export.text, export.events, export.todos, as_json)?
+                }
+                None => {
+                    print!(
+                        "{}",
+                        export_csv(storage, &selection, kind, &date_format)?.text
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        Command::Sync { list_calendars } => {
+            let caldav = config
+                .caldav
+                .as_ref()
+                .ok_or("No [caldav] section in the configuration")?;
+            if list_calendars {
+                print_calendars(caldav, as_json).await?
+            } else {
+                sync_calendar(storage, caldav, &paths.caldav_state(), as_json).await?
+            }
+        }
+        Command::Feeds { refresh } => feeds_status(config, paths, refresh, as_json).await?,
+        Command::Cal {
+            date,
+            months,
+            year,
+            week_numbers,
+            color,
+        } => {
+            let today = Local::now().date_naive();
+            let options = CalOptions {
+                week_start: config.week_start,
+                week_numbers,
+                color: use_color(color),
+                today,
+            };
+            // A year given as YYYY, or --year, prints the whole year
+            let this_month = today.with_day(1).unwrap();
+            let (first_month, count, whole_year) = match date {
+                Some(CalDate::Year(year)) => {
+                    (NaiveDate::from_ymd_opt(year, 1, 1).unwrap(), 12, true)
+                }
+                Some(CalDate::Month(month)) if year => (month.with_month(1).unwrap(), 12, true),
+                Some(CalDate::Month(month)) => (month, months.max(1), false),
+                None if year => (this_month.with_month(1).unwrap(), 12, true),
+                None => (this_month, months.max(1), false),
+            };
+            let feeds = load_cached_feeds(&config.feeds, paths);
+            print_cal(
+                storage,
+                &feeds,
+                first_month,
+                count,
+                whole_year,
+                &options,
+                as_json,
+            )?
+        }
+    };
+    println!("{}", output);
+    Ok(())
+}
+
+fn add_event(
+    storage: &mut dyn Storage,
+    start: &str,
+    title: &str,
+    location: String,
+    end: Option<String>,
+    rrule: Option<String>,
+    as_json: bool,
+) -> StorageResult<String> {
+    // Same input as the `app` command of the TUI, but invalid parts are refused rather than skipped
+    let (date, all_day, time_zone) =
+        parse_event_start(start).ok_or(format!("Invalid start {:?}", start))?;
+    let end = match end {
+        Some(end) => Some(
+            string_to_end_date(date, &split_time_zone(&end).0)
+                .ok_or(format!("Invalid end {:?}", end))?,
+        ),
+        None => None,
+    };
+    let recurrence = match rrule {
+        Some(rrule) => Some(Recurrence::from_rrule_in(&rrule, time_zone)?),
+        None => None,
+    };
+    let (event_name, tags, project) = split_tags(title);
+    let event = Events {
+        id: 0,
+        date,
+        event_name,
+        location,
+        end,
+        all_day,
+        time_zone,
+        recurrence,
+        tags,
+        project,
+        uid: None,
+        description: String::new(),
+    };
+
+    let id = storage.insert_event(event.clone())?;
+    if as_json {
+        return Ok(json!({ "id": id, "event": event }).to_string());
+    }
+    Ok(format!("Added event {}: {}", id, event.event_name))
+}
+
+fn add_todo(
+    storage: &mut dyn Storage,
+    title: &str,
+    priority: &str,
+    due: Option<String>,
+    as_json: bool,
+) -> StorageResult<String> {
+    let priority =
+        Priority::from_input(priority).ok_or(format!("Invalid priority {:?}", priority))?;
+    let (due_date, due_time) = match due {
+        Some(due) => string_to_due_date(&due).ok_or(format!("Invalid due date {:?}", due))?,
+        None => (None, None),
+    };
+    let (todo_name, tags, project) = split_tags(title);
+    let todo = ToDo {
+        due_date,
+        due_time,
+        tags,
+        project,
+        ..ToDo::new(priority, todo_name)
+    };
+
+    let id = storage.insert_todo(todo.clone())?;
+    if as_json {
+        return Ok(json!({ "id": id, "todo": todo }).to_string());
+    }
+    Ok(format!("Added to-do {}: {}", id, todo.todo_name))
+}
+
+fn list(
+    storage: &mut dyn Storage,
+    feeds: &[FeedCalendar],
+    config: &Config,
+    from: NaiveDate,
+    to: NaiveDate,
+    as_json: bool,
+) -> StorageResult<String> {
+    check_range(from, to)?;
+    // An event spanning several days is listed once, on its first day in the range
+    let mut events: Vec<Events> = Vec::new();
+    let mut feed_events: Vec<(String, Events)> = Vec::new();
+    let mut lines = Vec::new();
+    for day in events_by_day(storage, feeds, from, to)? {
+        let day_events: Vec<Events> = day
+            .events
+            .into_iter()
+            .filter(|event| {
+                !events
+                    .iter()
+                    .any(|ev| ev.id == event.id && ev.date == event.date)
+            })
+            .collect();
+        let day_feed_events: Vec<(String, Events)> = day
+            .feed_events
+            .into_iter()
+            .filter(|(feed, event)| {
+                !feed_events.iter().any(|(f, ev)| {
+                    f == feed && ev.event_name == event.event_name && ev.date == event.date
+                })
+            })
+            .collect();
+        lines.extend(
+            event_lines(&day_events, &day_feed_events, config)
+                .into_iter()
+                .map(|(date, line)| format!("{}  {}", date.format(&config.formats.date), line)),
+        );
+        events.extend(day_events);
+        feed_events.extend(day_feed_events);
+    }
+    let mut todos = storage.todos_due_between(from, to)?;
+    todos.sort_by_key(|t| t.get_due_date_time());
+
+    if as_json {
+        let feed_events: Vec<Value> = feed_events
+            .iter()
+            .map(|(feed, ev)| feed_event_json(feed, ev))
+            .collect();
+        return Ok(json!({
+            "from": from.to_string(),
+            "to": to.to_string(),
+            "events": events,
+            "feed_events": feed_events,
+            "todos": todos,
+        })
+        .to_string());
+    }
+    lines.extend(todos.iter().map(|t| todo_line(t, config)));
+    if lines.is_empty() {
+        lines.push(format!("Nothing planned between {} and {}", from, to));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn agenda(
+    storage: &mut dyn Storage,
+    feeds: &[FeedCalendar],
+    config: &Config,
+    days: u64,
+    as_json: bool,
+) -> StorageResult<String> {
+    let now = Local::now().naive_local();
+    let from = now.date();
+    let to = from + Days::new(days.max(1) - 1);
+    let occurrences = events_by_day(storage, feeds, from, to)?;
+
+    let mut todolist = ToDoList::new();
+    todolist.load_todos(storage)?;
+    let mut overdue: Vec<ToDo> = todolist
+        .all_todos
+        .iter()
+        .filter(|t| t.is_overdue(now) && t.due_date < Some(from))
+        .cloned()
+        .collect();
+    overdue.sort_by_key(|t| t.get_due_date_time());
+    let agenda_days: Vec<(DayEvents, Vec<ToDo>)> = occurrences
+        .into_iter()
+        .map(|day| {
+            let todos = todolist.get_todos_due_on(day.day);
+            (day, todos)
+        })
+        .filter(|(day, todos)| !day.is_empty() || !todos.is_empty())
+        .collect();
+
+    if as_json {
+        let days: Vec<Value> = agenda_days
+            .iter()
+            .map(|(day, todos)| {
+                json!({
+                    "date": day.day.to_string(),
+                    "events": day.events,
+                    "feed_events": day.feed_events_json(),
+                    "todos": todos,
+                })
+            })
+            .collect();
+        return Ok(json!({
+            "from": from.to_string(),
+            "to": to.to_string(),
+            "overdue": overdue,
+            "days": days,
+        })
+        .to_string());
+    }
+    let mut lines = Vec::new();
+    if !overdue.is_empty() {
+        lines.push(String::from("Overdue"));
+        lines.extend(
+            overdue
+                .iter()
+                .map(|t| format!("  {}", todo_line(t, config))),
+        );
+    }
+    for (day, todos) in &agenda_days {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push(
+            day.day
+                .format(&format!("%a {}", config.formats.date))
+                .to_string(),
+        );
+        lines.extend(
+            event_lines(&day.events, &day.feed_events, config)
+                .into_iter()
+                .map(|(_, line)| format!("  {}", line)),
+        );
+        lines.extend(todos.iter().map(|t| format!("  {}", todo_line(t, config))));
+    }
+    if lines.is_empty() {
+        lines.push(format!("Nothing planned for the next {} day(s)", days));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn mark_done(storage: &mut dyn Storage, id: u32, as_json: bool) -> StorageResult<String> {
+    let mut todolist = ToDoList::new();
+    todolist.load_todos(storage)?;
+    let todo = todolist
+        .all_todos
+        .iter_mut()
+        .find(|t| t.id == id)
+        .ok_or(format!("No to-do with id {}", id))?;
+    todo.completed = Some(Local::now().naive_local());
+    storage.update_todo(todo)?;
+
+    if as_json {
+        return Ok(json!({ "id": id, "todo": todo }).to_string());
+    }
+    Ok(format!("Marked to-do {} as done: {}", id, todo.todo_name))
+}
+
+fn import(storage: &mut dyn Storage, path: &Path, as_json: bool) -> StorageResult<String> {
+    let import = import_ics_file(path, storage)?;
+    if as_json {
+        return Ok(json!({
+            "events": import.events,
+            "duplicates": import.duplicates,
+            "skipped": import.skipped,
+            "warnings": import.warnings,
+        })
+        .to_string());
+    }
+    Ok(import.report().join("\n"))
+}
+
+fn import_csv(
+    storage: &mut dyn Storage,
+    path: &Path,
+    options: &CsvOptions,
+    dry_run: bool,
+    config: &Config,
+    as_json: bool,
+) -> StorageResult<String> {
+    let import = import_csv_file(path, storage, options, dry_run)?;
+    if as_json {
+        return Ok(json!({
+            "dry_run": import.dry_run,
+            "events": import.events,
+            "todos": import.todos,
+            "duplicates": import.duplicates,
+            "skipped": import.skipped,
+            "ignored_columns": import.ignored_columns,
+        })
+        .to_string());
+    }
+    Ok(import.report(&config.formats).join("\n"))
+}
+
+async fn feeds_status(
+    config: &Config,
+    paths: &AppPaths,
+    refresh: bool,
+    as_json: bool,
+) -> StorageResult<String> {
+    let feeds = if refresh {
+        fetch_all_feeds(&config.feeds, paths).await
+    } else {
+        load_cached_feeds(&config.feeds, paths)
+    };
+    if as_json {
+        let feeds: Vec<Value> = feeds
+            .iter()
+            .zip(&config.feeds)
+            .map(|(feed, feed_config)| {
+                json!({
+                    "name": feed.name,
+                    "url": feed_config.url.to_string(),
+                    "events": feed.events.len(),
+                    "fetched": feed.fetched.map(|time| {
+                        chrono::DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M:%S").to_string()
+                    }),
+                    "error": feed.error,
+                })
+            })
+            .collect();
+        return Ok(json!({ "feeds": feeds }).to_string());
+    }
+    if feeds.is_empty() {
+        return Ok(String::from("No feeds in the configuration"));
+    }
+    Ok(feeds
+        .iter()
+        .map(FeedCalendar::describe)
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+async fn sync_calendar(
+    storage: &mut dyn Storage,
+    caldav: &CalDavConfig,
+    state_path: &Path,
+    as_json: bool,
+) -> StorageResult<String> {
+    let report = sync(storage, caldav, state_path).await?;
+    if as_json {
+        return Ok(serde_json::to_string(&report)?);
+    }
+    Ok(report.lines().join("\n"))
+}
+
+async fn print_calendars(caldav: &CalDavConfig, as_json: bool) -> StorageResult<String> {
+    let calendars = list_calendars(caldav).await?;
+    if as_json {
+        return Ok(json!({ "calendars": calendars }).to_string());
+    }
+    if calendars.is_empty() {
+        return Ok(String::from("No calendar with events found on the server"));
+    }
+    Ok(calendars
+        .iter()
+        .map(|c| format!("{}  {}", c.name, c.url))
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+/// The events and to-dos to export, between the two dates (if given) and matching the filter
+fn export_selection(
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    filter: Option<String>,
+) -> StorageResult<ExportSelection> {
+    if let (Some(from), Some(to)) = (from, to) {
+        check_range(from, to)?;
+    }
+    Ok(ExportSelection {
+        from,
+        to,
+        filter: Filter::from_input(&filter.unwrap_or_default()),
+    })
+}
+
+fn check_range(from: NaiveDate, to: NaiveDate) -> StorageResult<()> {
+    if to < from {
+        return Err(format!("The range ends ({}) before it starts ({})", to, from).into());
+    }
+    Ok(())
+}
+
+/// Writes an exported .ics or .csv file and reports what it holds
+fn write_export(
+    path: &Path,
+    text: &str,
+    events: usize,
+    todos: usize,
+    as_json: bool,
+) -> StorageResult<String> {
+    write_atomic(path, text.as_bytes())?;
+    if as_json {
+        return Ok(json!({
+            "file": path.display().to_string(),
+            "events": events,
+            "todos": todos,
+        })
+        .to_string());
+    }
+    Ok(format!(
+        "Exported {} event(s) and {} to-do(s) to {}",
+        events,
+        todos,
+        path.display()
+    ))
+}
+
+fn print_cal(
+    storage: &mut dyn Storage,
+    feeds: &[FeedCalendar],
+    first_month: NaiveDate,
+    count: u32,
+    whole_year: bool,
+    options: &CalOptions,
+    as_json: bool,
+) -> StorageResult<String> {
+    let last_day = first_month + Months::new(count) - Days::new(1);
+    if as_json {
+        // The days with appointments, month by month
+        let days = events_by_day(storage, feeds, first_month, last_day)?;
+        let months: Vec<Value> = (0..count)
+            .map(|i| {
+                let month = first_month + Months::new(i);
+                let month_days: Vec<Value> = days
+                    .iter()
+                    .filter(|day| {
+                        day.day.year() == month.year()
+                            && day.day.month() == month.month()
+                            && !day.is_empty()
+                    })
+                    .map(|day| {
+                        json!({
+                            "date": day.day.to_string(),
+                            "events": day.events,
+                            "feed_events": day.feed_events_json(),
+                        })
+                    })
+                    .collect();
+                json!({ "month": month.format("%Y-%m").to_string(), "days": month_days })
+            })
+            .collect();
+        return Ok(json!({ "months": months }).to_string());
+    }
+
+    let mut calendar = Calendar::new();
+    calendar.load_events_between(storage, first_month, last_day)?;
+    calendar.feeds = feeds.to_vec();
+    if whole_year {
+        return Ok(year_text(&calendar, first_month.year(), options));
+    }
+    Ok(months_text(&calendar, first_month, count, options))
+}
+
+/// Escape codes are only written to a terminal, unless asked for, and never with NO_COLOR set
+fn use_color(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+        }
+    }
+}
+
+/// Occurrences of a day: the own events, then the ones of the subscribed feeds with their name
+struct DayEvents {
+    day: NaiveDate,
+    events: Vec<Events>,
+    feed_events: Vec<(String, Events)>,
+}
+
+impl DayEvents {
+    fn is_empty(&self) -> bool {
+        self.events.is_empty() && self.feed_events.is_empty()
+    }
+
+    fn feed_events_json(&self) -> Vec<Value> {
+        self.feed_events
+            .iter()
+            .map(|(feed, ev)| feed_event_json(feed, ev))
+            .collect()
+    }
+}
+
+/// Occurrences of the events on each day between the two dates, in local time
+fn events_by_day(
+    storage: &mut dyn Storage,
+    feeds: &[FeedCalendar],
+    from: NaiveDate,
+    to: NaiveDate,
+) -> StorageResult<Vec<DayEvents>> {
+    let mut calendar = Calendar::new();
+    calendar.load_events_between(storage, from, to)?;
+    calendar.feeds = feeds.to_vec();
+    Ok(from
+        .iter_days()
+        .take_while(|day| *day <= to)
+        .map(|day| {
+            let date = day.and_hms_opt(0, 0, 0).unwrap();
+            DayEvents {
+                day,
+                events: calendar.get_event_from_calendar(date),
+                feed_events: calendar.get_feed_events(date),
+            }
+        })
+        .collect())
+}
+
+/// Lines of the own and subscribed events with their start, all-day events first and then by start
+fn event_lines(
+    events: &[Events],
+    feed_events: &[(String, Events)],
+    config: &Config,
+) -> Vec<(NaiveDateTime, String)> {
+    let mut lines: Vec<(bool, NaiveDateTime, String)> = events
+        .iter()
+        .map(|ev| (!ev.all_day, ev.date, event_line(ev, config)))
+        .chain(
+            feed_events
+                .iter()
+                .map(|(feed, ev)| (!ev.all_day, ev.date, feed_event_line(feed, ev, config))),
+        )
+        .collect();
+    lines.sort_by_key(|(not_all_day, date, _)| (*not_all_day, *date));
+    lines
+        .into_iter()
+        .map(|(_, date, line)| (date, line))
+        .collect()
+}
+
+/// A subscribed event in the JSON output, with the name of its feed
+fn feed_event_json(feed: &str, event: &Events) -> Value {
+    let mut value = json!(event);
+    value["feed"] = json!(feed);
+    value
+}
+
+/// e.g. "00:00  Christmas Day [Holidays]", subscribed events have no ID
+fn feed_event_line(feed: &str, event: &Events, config: &Config) -> String {
+    format!("{} [{}]", event_text(event, config), feed)
+}
+
+/// e.g. "09:00–10:00  Team Meeting (Office) #work [id 3]"
+fn event_line(event: &Events, config: &Config) -> String {
+    format!("{} [id {}]", event_text(event, config), event.id)
+}
+
+/// The time, title, location and tags of an event
+fn event_text(event: &Events, config: &Config) -> String {
+    let mut line = format!(
+        "{}  {}",
+        event.time_range_text(&config.formats),
+        event.event_name
+    );
+    if !event.location.is_empty() {
+        line.push_str(&format!(" ({})", event.location));
+    }
+    if !event.tags.is_empty() || event.project.is_some() {
+        line.push(' ');
+        line.push_str(&tags_text(&event.tags, event.project.as_deref()));
+    }
+    line
+}
+
+/// e.g. "[ ] Finish report (A, due 2024-09-07 17:00) [id 4]"
+fn todo_line(todo: &ToDo, config: &Config) -> String {
+    let mut line = format!(
+        "[{}] {}",
+        if todo.is_done() { "x" } else { " " },
+        todo.todo_name
+    );
+    let details: Vec<String> = todo
+        .priority
+        .to_letter()
+        .map(String::from)
+        .into_iter()
+        .chain(
+            todo.due_text(&config.formats)
+                .map(|due| format!("due {}", due)),
+        )
+        .collect();
+    if !details.is_empty() {
+        line.push_str(&format!(" ({})", details.join(", ")));
+    }
+    if !todo.tags.is_empty() || todo.project.is_some() {
+        line.push(' ');
+        line.push_str(&tags_text(&todo.tags, todo.project.as_deref()));
+    }
+    line.push_str(&format!(" [id {}]", todo.id));
+    line
+}
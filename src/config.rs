@@ -0,0 +1,524 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    Weekday,
+};
+use ratatui::style::Color;
+use serde::Deserialize;
+
+use crate::{caldav::ConflictPolicy, feeds::cache_name, storage::StorageKind, weather::Units};
+
+/// Environment variable holding the CalDAV password, instead of writing it in the file
+pub const CALDAV_PASSWORD_ENV: &str = "RUSTY_CALENDAR_CALDAV_PASSWORD";
+
+/// Settings read from `config.toml` at start-up
+/// Every setting has a default, which is used when it (or the whole file) is missing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// City the weather is shown for
+    pub city: String,
+    pub units: Units,
+    /// First day of the week in the month view
+    pub week_start: Weekday,
+    pub formats: Formats,
+    pub theme: Theme,
+    pub layout: PaneLayout,
+    /// The environment and the command line take precedence over these two
+    pub storage: Option<StorageKind>,
+    pub data_dir: Option<PathBuf>,
+    /// todo.txt file to keep the to-dos in, instead of the storage
+    pub todo_txt: Option<PathBuf>,
+    /// Server to sync with, if one is set up
+    pub caldav: Option<CalDavConfig>,
+    /// Subscribed calendars, shown next to the own events but never changed
+    pub feeds: Vec<FeedConfig>,
+    /// chrono formats of the dates in CSV files, tried in order; the first one is written on export
+    pub csv_date_formats: Vec<String>,
+}
+
+/// chrono format strings used to show dates and times
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Formats {
+    pub date: String,
+    pub time: String,
+    /// The clock in the title of the calendar
+    pub clock: String,
+}
+
+impl Default for Formats {
+    fn default() -> Self {
+        Self {
+            date: String::from("%Y-%m-%d"),
+            time: String::from("%H:%M"),
+            clock: String::from("%H:%M:%S"),
+        }
+    }
+}
+
+impl Formats {
+    pub fn date_time(&self) -> String {
+        format!("{} {}", self.date, self.time)
+    }
+}
+
+/// Colours of the panes and of the highlighted to-dos
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub calendar: Color,
+    pub clock: Color,
+    pub month: Color,
+    pub appointments: Color,
+    pub weather: Color,
+    pub todo: Color,
+    pub input: Color,
+    pub overdue: Color,
+    pub completed: Color,
+}
+
+/// Sizes of the panes, in percent of the space they share, and the padding inside them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaneLayout {
+    /// Width of the calendar side, the To-Do pane takes the rest
+    pub calendar_width: u16,
+    /// Height of the title bar with the clock
+    pub header_height: u16,
+    /// Height of the month view and the weather, the appointments take the rest
+    pub month_height: u16,
+    /// Width of the month view next to the weather
+    pub month_width: u16,
+    /// Height of the to-do list above the input box
+    pub todo_height: u16,
+    pub padding_left: u16,
+    pub padding_top: u16,
+}
+
+impl Default for PaneLayout {
+    fn default() -> Self {
+        Self {
+            calendar_width: 70,
+            header_height: 5,
+            month_height: 45,
+            month_width: 60,
+            todo_height: 80,
+            padding_left: 5,
+            padding_top: 2,
+        }
+    }
+}
+
+/// Calendar on a CalDAV server, synced with the events of one project
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalDavConfig {
+    /// Server, principal or calendar URL, the calendars are discovered from it
+    pub url: reqwest::Url,
+    pub username: String,
+    pub password: String,
+    /// Display name or path of the calendar, the first one found if not given
+    pub calendar: Option<String>,
+    /// Local events of this project are synced, the events of the server are added to it
+    pub project: String,
+    /// Minutes between two syncs while the calendar is open, 0 to only sync on request
+    pub interval_minutes: u64,
+    pub conflicts: ConflictPolicy,
+}
+
+/// A read-only calendar published as an .ics URL, e.g. public holidays
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedConfig {
+    /// Shown with its events, and names the cached copy
+    pub name: String,
+    pub url: reqwest::Url,
+    /// Minutes between two downloads, 0 to only download on request
+    pub refresh_minutes: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // The defaults of the file are always valid
+        Config::from_file(ConfigFile::default(), Path::new(".")).unwrap()
+    }
+}
+
+impl Config {
+    /// Reads the configuration file, the defaults are used if it does not exist
+    /// All the invalid settings are reported at once
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+        let file: ConfigFile =
+            toml::from_str(&data).map_err(|e| format!("Malformed {}: {}", path.display(), e))?;
+        // Relative data paths are taken from the directory of the file
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        Self::from_file(file, base_dir).map_err(|errors| {
+            format!(
+                "Invalid settings in {}:\n  {}",
+                path.display(),
+                errors.join("\n  ")
+            )
+        })
+    }
+
+    fn from_file(file: ConfigFile, base_dir: &Path) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+
+        let units = Units::from_input(&file.location.units).unwrap_or_else(|| {
+            errors.push(format!(
+                "location.units: unknown units {:?}, use metric or imperial",
+                file.location.units
+            ));
+            Units::default()
+        });
+        let week_start = Weekday::from_str(&file.calendar.week_start).unwrap_or_else(|_| {
+            errors.push(format!(
+                "calendar.week_start: unknown day {:?}",
+                file.calendar.week_start
+            ));
+            Weekday::Sun
+        });
+
+        let mut formats = Formats::default();
+        for (name, value, format) in [
+            ("date_format", file.calendar.date_format, &mut formats.date),
+            ("time_format", file.calendar.time_format, &mut formats.time),
+            (
+                "clock_format",
+                file.calendar.clock_format,
+                &mut formats.clock,
+            ),
+        ] {
+            if StrftimeItems::new(&value).any(|item| item == Item::Error) {
+                errors.push(format!("calendar.{}: invalid format {:?}", name, value));
+            } else {
+                *format = value;
+            }
+        }
+
+        let mut colour = |name: &str, value: &str| {
+            Color::from_str(value).unwrap_or_else(|_| {
+                errors.push(format!("theme.{}: unknown colour {:?}", name, value));
+                Color::Reset
+            })
+        };
+        let theme = Theme {
+            calendar: colour("calendar", &file.theme.calendar),
+            clock: colour("clock", &file.theme.clock),
+            month: colour("month", &file.theme.month),
+            appointments: colour("appointments", &file.theme.appointments),
+            weather: colour("weather", &file.theme.weather),
+            todo: colour("todo", &file.theme.todo),
+            input: colour("input", &file.theme.input),
+            overdue: colour("overdue", &file.theme.overdue),
+            completed: colour("completed", &file.theme.completed),
+        };
+
+        let layout = file.layout;
+        for (name, value) in [
+            ("calendar_width", layout.calendar_width),
+            ("header_height", layout.header_height),
+            ("month_height", layout.month_height),
+            ("month_width", layout.month_width),
+            ("todo_height", layout.todo_height),
+        ] {
+            if !(1..=99).contains(&value) {
+                errors.push(format!(
+                    "layout.{}: {} is not a percentage between 1 and 99",
+                    name, value
+                ));
+            }
+        }
+        if u32::from(layout.header_height) + u32::from(layout.month_height) >= 100 {
+            errors.push(String::from(
+                "layout: header_height and month_height leave no room for the appointments",
+            ));
+        }
+
+        let storage = file.storage.backend.as_ref().and_then(|backend| {
+            let kind = StorageKind::from_input(backend);
+            if kind.is_none() {
+                errors.push(format!(
+                    "storage.backend: unknown storage {:?}, use json or sqlite",
+                    backend
+                ));
+            }
+            kind
+        });
+        let data_dir = file
+            .storage
+            .data_dir
+            .map(|data_dir| expand_path(&data_dir, base_dir));
+        let todo_txt = file
+            .storage
+            .todo_txt
+            .map(|todo_txt| expand_path(&todo_txt, base_dir));
+
+        let caldav = file.caldav.url.as_ref().and_then(|url| {
+            let url = match reqwest::Url::parse(url) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Some(url),
+                _ => {
+                    errors.push(format!("caldav.url: {:?} is not an http(s) URL", url));
+                    None
+                }
+            };
+            let conflicts = ConflictPolicy::from_input(&file.caldav.conflicts);
+            if conflicts.is_none() {
+                errors.push(format!(
+                    "caldav.conflicts: unknown policy {:?}, use server or local",
+                    file.caldav.conflicts
+                ));
+            }
+            // The project is written like in a title, e.g. `+team`
+            let project = file.caldav.project.trim_start_matches('+').to_string();
+            if project.is_empty() || project.contains(char::is_whitespace) {
+                errors.push(format!(
+                    "caldav.project: {:?} is not a single word",
+                    file.caldav.project
+                ));
+            }
+            let password = std::env::var(CALDAV_PASSWORD_ENV)
+                .ok()
+                .or(file.caldav.password.clone())
+                .unwrap_or_default();
+            Some(CalDavConfig {
+                url: url?,
+                username: file.caldav.username.clone(),
+                password,
+                calendar: file.caldav.calendar.clone(),
+                project,
+                interval_minutes: file.caldav.interval_minutes,
+                conflicts: conflicts?,
+            })
+        });
+
+        let mut feeds: Vec<FeedConfig> = Vec::new();
+        for (index, feed) in file.feeds.iter().enumerate() {
+            let name = feed.name.trim().to_string();
+            if cache_name(&name).is_empty() {
+                errors.push(format!(
+                    "feeds[{}].name: {:?} has no letters or digits",
+                    index, name
+                ));
+                continue;
+            }
+            // The names also name the cached copies, which must not be shared
+            if feeds
+                .iter()
+                .any(|f| cache_name(&f.name) == cache_name(&name))
+            {
+                errors.push(format!("feeds[{}].name: {:?} is used twice", index, name));
+                continue;
+            }
+            match feed_url(&feed.url) {
+                Some(url) => feeds.push(FeedConfig {
+                    name,
+                    url,
+                    refresh_minutes: feed.refresh_minutes,
+                }),
+                None => errors.push(format!(
+                    "feeds[{}].url: {:?} is not an http(s) or webcal URL",
+                    index, feed.url
+                )),
+            }
+        }
+
+        for (index, format) in file.csv.date_formats.iter().enumerate() {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                errors.push(format!(
+                    "csv.date_formats[{}]: invalid format {:?}",
+                    index, format
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Self {
+            city: file.location.city,
+            units,
+            week_start,
+            formats,
+            theme,
+            layout,
+            storage,
+            data_dir,
+            todo_txt,
+            caldav,
+            feeds,
+            csv_date_formats: file.csv.date_formats,
+        })
+    }
+}
+
+/// Feeds are often published as `webcal://` links, which are fetched over https
+fn feed_url(url: &str) -> Option<reqwest::Url> {
+    let url = match url.trim().strip_prefix("webcal://") {
+        Some(rest) => reqwest::Url::parse(&format!("https://{}", rest)),
+        None => reqwest::Url::parse(url.trim()),
+    };
+    url.ok()
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+}
+
+/// `~/` stands for the home directory, other relative paths start from `base_dir`
+pub fn expand_path(path: &str, base_dir: &Path) -> PathBuf {
+    if let (Some(rest), Some(home)) = (path.strip_prefix("~/"), dirs::home_dir()) {
+        return home.join(rest);
+    }
+    base_dir.join(path)
+}
+
+/// The file as it is written, before the settings are checked
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    location: LocationSection,
+    calendar: CalendarSection,
+    theme: ThemeSection,
+    layout: PaneLayout,
+    storage: StorageSection,
+    caldav: CalDavSection,
+    feeds: Vec<FeedSection>,
+    csv: CsvSection,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LocationSection {
+    city: String,
+    units: String,
+}
+
+impl Default for LocationSection {
+    fn default() -> Self {
+        Self {
+            city: String::from("Guatemala"),
+            units: String::from("metric"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CalendarSection {
+    week_start: String,
+    date_format: String,
+    time_format: String,
+    clock_format: String,
+}
+
+impl Default for CalendarSection {
+    fn default() -> Self {
+        let formats = Formats::default();
+        Self {
+            week_start: String::from("sunday"),
+            date_format: formats.date,
+            time_format: formats.time,
+            clock_format: formats.clock,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeSection {
+    calendar: String,
+    clock: String,
+    month: String,
+    appointments: String,
+    weather: String,
+    todo: String,
+    input: String,
+    overdue: String,
+    completed: String,
+}
+
+impl Default for ThemeSection {
+    fn default() -> Self {
+        Self {
+            calendar: String::from("red"),
+            clock: String::from("lightred"),
+            month: String::from("darkgray"),
+            appointments: String::from("green"),
+            weather: String::from("magenta"),
+            todo: String::from("blue"),
+            input: String::from("darkgray"),
+            overdue: String::from("red"),
+            completed: String::from("darkgray"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StorageSection {
+    backend: Option<String>,
+    data_dir: Option<String>,
+    todo_txt: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CalDavSection {
+    url: Option<String>,
+    username: String,
+    password: Option<String>,
+    calendar: Option<String>,
+    project: String,
+    interval_minutes: u64,
+    conflicts: String,
+}
+
+impl Default for CalDavSection {
+    fn default() -> Self {
+        Self {
+            url: None,
+            username: String::new(),
+            password: None,
+            calendar: None,
+            project: String::from("caldav"),
+            interval_minutes: 15,
+            conflicts: String::from("server"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FeedSection {
+    name: String,
+    url: String,
+    refresh_minutes: u64,
+}
+
+impl Default for FeedSection {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            url: String::new(),
+            refresh_minutes: 360,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CsvSection {
+    date_formats: Vec<String>,
+}
+
+impl Default for CsvSection {
+    fn default() -> Self {
+        Self {
+            date_formats: vec![String::from("%Y-%m-%d")],
+        }
+    }
+}
@@ -0,0 +1,983 @@
+use std::{fs, path::Path};
+
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::{
+    calendar_data::{string_to_end_date, Events},
+    config::Formats,
+    ical::ExportSelection,
+    recurrence::Recurrence,
+    storage::{Storage, StorageResult},
+    tags::{split_tags, tags_text},
+    to_do_data::{Priority, ToDo},
+};
+
+/// Times accepted in the time, end and date columns, after the date formats
+const TIME_FORMATS: [&str; 4] = ["%H:%M:%S", "%H:%M", "%I:%M %p", "%I:%M%p"];
+
+/// What the rows of a file are read as, or written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvKind {
+    Events,
+    Todos,
+}
+
+impl CsvKind {
+    /// Accepts `event`, `appointment`, `todo` and `task`, also in the plural
+    pub fn from_input(s: &str) -> Option<Self> {
+        match normalize(s).trim_end_matches('s') {
+            "event" | "appointment" => Some(CsvKind::Events),
+            "todo" | "task" => Some(CsvKind::Todos),
+            _ => None,
+        }
+    }
+}
+
+/// A column of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// `event` or `todo`, for files holding both
+    Type,
+    /// Start of an event, due date of a to-do; may carry the time as well
+    Date,
+    Time,
+    /// End of an event: a date, a time on the start day, a date and time or a duration
+    End,
+    Title,
+    Location,
+    /// Words separated by commas or spaces, `#` is optional
+    Tags,
+    Project,
+    Priority,
+    /// Yes, no or the day the to-do was done
+    Done,
+    /// Recurrence rule of an event, e.g. `FREQ=WEEKLY;BYDAY=MO`
+    Repeat,
+}
+
+impl Field {
+    const ALL: [Field; 11] = [
+        Field::Type,
+        Field::Date,
+        Field::Time,
+        Field::End,
+        Field::Title,
+        Field::Location,
+        Field::Tags,
+        Field::Project,
+        Field::Priority,
+        Field::Done,
+        Field::Repeat,
+    ];
+
+    /// Header written on export
+    pub fn header(&self) -> &'static str {
+        match self {
+            Field::Type => "type",
+            Field::Date => "date",
+            Field::Time => "time",
+            Field::End => "end",
+            Field::Title => "title",
+            Field::Location => "location",
+            Field::Tags => "tags",
+            Field::Project => "project",
+            Field::Priority => "priority",
+            Field::Done => "done",
+            Field::Repeat => "repeat",
+        }
+    }
+
+    /// Other headers recognized on import, without spaces and in lowercase
+    fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            Field::Type => &["kind", "itemtype"],
+            Field::Date => &[
+                "start",
+                "startdate",
+                "due",
+                "duedate",
+                "deadline",
+                "day",
+                "when",
+            ],
+            Field::Time => &["starttime", "duetime"],
+            Field::End => &["enddate", "endtime", "until", "finish"],
+            Field::Title => &["name", "summary", "subject", "task", "event", "item"],
+            Field::Location => &["place", "where", "room"],
+            Field::Tags => &["tag", "categories", "category", "labels"],
+            Field::Project => &["calendar"],
+            Field::Priority => &["prio", "importance"],
+            Field::Done => &["completed", "complete", "finished", "status"],
+            Field::Repeat => &["rrule", "recurrence", "repeats"],
+        }
+    }
+
+    /// The field a header stands for, ignoring case, spaces and punctuation (`Due Date` is `due`)
+    pub fn from_header(s: &str) -> Option<Self> {
+        let header = normalize(s);
+        Field::ALL
+            .into_iter()
+            .find(|field| field.header() == header || field.aliases().contains(&header.as_str()))
+    }
+}
+
+/// How a CSV file is read
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// What rows without a type become, guessed from the columns if not given
+    pub kind: Option<CsvKind>,
+    /// chrono formats of the dates, tried in order before YYYY-MM-DD, e.g. `%d.%m.%Y`
+    pub date_formats: Vec<String>,
+    /// Header of the column holding a field, for the columns not found by their header
+    pub columns: Vec<(Field, String)>,
+}
+
+/// Result of reading a CSV file: the new events and to-dos, and what was left out
+#[derive(Debug, Default)]
+pub struct CsvImport {
+    pub events: Vec<Events>,
+    pub todos: Vec<ToDo>,
+    /// Rows with the same title and date as an item in the calendar (or earlier in the file)
+    pub duplicates: usize,
+    /// Rows that could not be read, with their line and the reason
+    pub skipped: Vec<String>,
+    /// Headers that do not stand for any field
+    pub ignored_columns: Vec<String>,
+    /// Nothing was stored, the items are only shown
+    pub dry_run: bool,
+}
+
+impl CsvImport {
+    /// e.g. "Imported 3 event(s) and 2 to-do(s), 1 already imported", followed by the items of a
+    /// dry run, the ignored columns and the skipped rows
+    pub fn report(&self, formats: &Formats) -> Vec<String> {
+        let mut summary = format!(
+            "{} {} event(s) and {} to-do(s)",
+            if self.dry_run {
+                "Would import"
+            } else {
+                "Imported"
+            },
+            self.events.len(),
+            self.todos.len()
+        );
+        if self.duplicates > 0 {
+            summary.push_str(&format!(", {} already imported", self.duplicates));
+        }
+        let mut lines = vec![summary];
+        if self.dry_run {
+            lines.extend(
+                self.events
+                    .iter()
+                    .map(|ev| format!("  {}", event_preview(ev, formats))),
+            );
+            lines.extend(
+                self.todos
+                    .iter()
+                    .map(|t| format!("  {}", todo_preview(t, formats))),
+            );
+        }
+        lines.extend(
+            self.ignored_columns
+                .iter()
+                .map(|c| format!("Ignored column {:?}", c)),
+        );
+        lines.extend(self.skipped.iter().map(|s| format!("Skipped {}", s)));
+        lines
+    }
+}
+
+/// Reads a CSV file and stores its new events and to-dos, whose IDs are set in the result
+/// A dry run only reads the file
+pub fn import_csv_file(
+    path: &Path,
+    storage: &mut dyn Storage,
+    options: &CsvOptions,
+    dry_run: bool,
+) -> StorageResult<CsvImport> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+    let mut import = import_csv(
+        &data,
+        options,
+        &storage.load_events()?,
+        &storage.load_todos()?,
+        Local::now().naive_local(),
+    )
+    .map_err(|e| format!("Malformed {}: {}", path.display(), e))?;
+    import.dry_run = dry_run;
+    if dry_run {
+        return Ok(import);
+    }
+    let ids = storage.insert_events(import.events.clone())?;
+    for (event, id) in import.events.iter_mut().zip(ids) {
+        event.id = id;
+    }
+    for todo in import.todos.iter_mut() {
+        todo.id = storage.insert_todo(todo.clone())?;
+    }
+    Ok(import)
+}
+
+/// Reads the rows of a CSV file into events and to-dos, leaving out the ones already in
+/// `existing_events` and `existing_todos`
+/// The first row is the header, which tells the field of each column
+pub fn import_csv(
+    data: &str,
+    options: &CsvOptions,
+    existing_events: &[Events],
+    existing_todos: &[ToDo],
+    now: NaiveDateTime,
+) -> Result<CsvImport, String> {
+    let mut records = parse_records(data)?
+        .into_iter()
+        .filter(|record| record.fields.iter().any(|f| !f.trim().is_empty()));
+    let header = records.next().ok_or("the file is empty")?;
+    let (columns, ignored_columns) = map_columns(&header.fields, &options.columns)?;
+    let default_kind = options.kind.unwrap_or_else(|| guess_kind(&columns));
+
+    let mut import = CsvImport {
+        ignored_columns,
+        ..CsvImport::default()
+    };
+    for record in records {
+        let row = Row {
+            fields: &record.fields,
+            columns: &columns,
+        };
+        let kind = match row.get(Field::Type) {
+            "" => Ok(default_kind),
+            kind => CsvKind::from_input(kind)
+                .ok_or(format!("unknown type {:?}, use event or todo", kind)),
+        };
+        let result = match kind {
+            Ok(CsvKind::Events) => event_from_row(&row, &options.date_formats).map(|event| {
+                let duplicate = existing_events
+                    .iter()
+                    .chain(&import.events)
+                    .any(|ev| ev.date == event.date && ev.event_name == event.event_name);
+                if duplicate {
+                    import.duplicates += 1;
+                } else {
+                    import.events.push(event);
+                }
+            }),
+            Ok(CsvKind::Todos) => todo_from_row(&row, &options.date_formats, now).map(|todo| {
+                let duplicate = existing_todos
+                    .iter()
+                    .chain(&import.todos)
+                    .any(|t| t.due_date == todo.due_date && t.todo_name == todo.todo_name);
+                if duplicate {
+                    import.duplicates += 1;
+                } else {
+                    import.todos.push(todo);
+                }
+            }),
+            Err(reason) => Err(reason),
+        };
+        if let Err(reason) = result {
+            import
+                .skipped
+                .push(format!("line {}: {}", record.line, reason));
+        }
+    }
+    Ok(import)
+}
+
+/// A record of the file, with the line it starts on
+struct Record {
+    line: usize,
+    fields: Vec<String>,
+}
+
+/// The fields found in the header, with the index of their column
+type Columns = Vec<(Field, usize)>;
+
+/// A row with the column of each field
+struct Row<'a> {
+    fields: &'a [String],
+    columns: &'a [(Field, usize)],
+}
+
+impl Row<'_> {
+    /// The trimmed value of a field, empty if the file has no such column
+    fn get(&self, field: Field) -> &str {
+        self.columns
+            .iter()
+            .find(|(f, _)| *f == field)
+            .and_then(|(_, index)| self.fields.get(*index))
+            .map_or("", |value| value.trim())
+    }
+}
+
+/// Finds the column of each field, the given ones by their exact header and the others by
+/// `Field::from_header`; returns the columns along with the headers that were not used
+fn map_columns(
+    header: &[String],
+    given: &[(Field, String)],
+) -> Result<(Columns, Vec<String>), String> {
+    let mut columns: Columns = Vec::new();
+    for (field, name) in given {
+        let index = header
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name.trim()))
+            .ok_or(format!(
+                "no column {:?} for the {}, the header is {}",
+                name,
+                field.header(),
+                header.join(", ")
+            ))?;
+        columns.retain(|(f, _)| f != field);
+        columns.push((*field, index));
+    }
+
+    let mut ignored = Vec::new();
+    for (index, name) in header.iter().enumerate() {
+        if columns.iter().any(|(_, i)| *i == index) {
+            continue;
+        }
+        match Field::from_header(name) {
+            Some(field) if !columns.iter().any(|(f, _)| *f == field) => {
+                columns.push((field, index))
+            }
+            _ if name.trim().is_empty() => {}
+            _ => ignored.push(name.trim().to_string()),
+        }
+    }
+    if !columns.iter().any(|(f, _)| *f == Field::Title) {
+        return Err(format!(
+            "no title column, the header is {}",
+            header.join(", ")
+        ));
+    }
+    Ok((columns, ignored))
+}
+
+/// Rows are events if the file has columns that only events have, to-dos otherwise,
+/// e.g. a list of deadlines with a date and a title
+fn guess_kind(columns: &[(Field, usize)]) -> CsvKind {
+    let has_any = |fields: &[Field]| columns.iter().any(|(f, _)| fields.contains(f));
+    if has_any(&[Field::Time, Field::End, Field::Location, Field::Repeat])
+        && !has_any(&[Field::Priority, Field::Done])
+    {
+        CsvKind::Events
+    } else {
+        CsvKind::Todos
+    }
+}
+
+fn event_from_row(row: &Row, date_formats: &[String]) -> Result<Events, String> {
+    let (event_name, tags, project) = title_and_tags(row)?;
+    let date = row.get(Field::Date);
+    if date.is_empty() {
+        return Err(format!("{:?} has no date", event_name));
+    }
+    let (date, time) = parse_date(date, date_formats).ok_or(format!("unknown date {:?}", date))?;
+    let time = match row.get(Field::Time) {
+        "" => time,
+        t => Some(parse_time(t).ok_or(format!("unknown time {:?}", t))?),
+    };
+    let start = date.and_time(time.unwrap_or_default());
+    let end = match row.get(Field::End) {
+        "" => None,
+        end => Some(parse_end(start, end, date_formats).ok_or(format!("unknown end {:?}", end))?),
+    };
+    if end.is_some_and(|end| end < start) {
+        return Err(format!("{:?} ends before it starts", event_name));
+    }
+    let recurrence = match row.get(Field::Repeat) {
+        "" => None,
+        rule => Some(Recurrence::from_rrule(rule)?),
+    };
+    Ok(Events {
+        end,
+        all_day: time.is_none(),
+        recurrence,
+        tags,
+        project,
+        ..Events::new(start, event_name, row.get(Field::Location).to_string())
+    })
+}
+
+fn todo_from_row(row: &Row, date_formats: &[String], now: NaiveDateTime) -> Result<ToDo, String> {
+    let (todo_name, tags, project) = title_and_tags(row)?;
+    let priority = row.get(Field::Priority);
+    let priority =
+        Priority::from_input(priority).ok_or(format!("unknown priority {:?}", priority))?;
+    let (due_date, due_time) = match row.get(Field::Date) {
+        "" => (None, None),
+        date => {
+            let (date, time) =
+                parse_date(date, date_formats).ok_or(format!("unknown date {:?}", date))?;
+            (Some(date), time)
+        }
+    };
+    let due_time = match row.get(Field::Time) {
+        "" => due_time,
+        t => Some(parse_time(t).ok_or(format!("unknown time {:?}", t))?),
+    };
+    if due_time.is_some() && due_date.is_none() {
+        return Err(format!("{:?} has a due time but no date", todo_name));
+    }
+    Ok(ToDo {
+        completed: parse_done(row.get(Field::Done), date_formats, now)?,
+        due_date,
+        due_time,
+        tags,
+        project,
+        ..ToDo::new(priority, todo_name)
+    })
+}
+
+/// The title with the tags written in it or in the tags and project columns
+fn title_and_tags(row: &Row) -> Result<(String, Vec<String>, Option<String>), String> {
+    let (title, mut tags, mut project) = split_tags(row.get(Field::Title));
+    if title.is_empty() {
+        return Err(String::from("no title"));
+    }
+    let words = row
+        .get(Field::Tags)
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace());
+    for word in words {
+        if let Some(name) = word.strip_prefix('+').filter(|p| !p.is_empty()) {
+            project = Some(name.to_string());
+            continue;
+        }
+        let tag = word.trim_start_matches('#');
+        if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    match row.get(Field::Project).trim_start_matches('+') {
+        "" => {}
+        name => project = Some(name.to_string()),
+    }
+    Ok((title, tags, project))
+}
+
+/// A date in one of the formats (tried in order) or as YYYY-MM-DD, optionally followed by a time
+fn parse_date(s: &str, date_formats: &[String]) -> Option<(NaiveDate, Option<NaiveTime>)> {
+    let formats = date_formats.iter().map(String::as_str).chain(["%Y-%m-%d"]);
+    for format in formats {
+        if let Ok(date) = NaiveDate::parse_from_str(s, format) {
+            return Some((date, None));
+        }
+        for time_format in TIME_FORMATS {
+            for separator in [" ", "T"] {
+                let date_time_format = format!("{}{}{}", format, separator, time_format);
+                if let Ok(date_time) = NaiveDateTime::parse_from_str(s, &date_time_format) {
+                    return Some((date_time.date(), Some(date_time.time())));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn parse_time(s: &str) -> Option<NaiveTime> {
+    TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(s, format).ok())
+}
+
+/// A date (the end of that day), a date and time, a time on the start day or a duration
+fn parse_end(start: NaiveDateTime, s: &str, date_formats: &[String]) -> Option<NaiveDateTime> {
+    match parse_date(s, date_formats) {
+        Some((date, Some(time))) => Some(date.and_time(time)),
+        Some((date, None)) => date.and_hms_opt(23, 59, 59),
+        None => parse_time(s)
+            .map(|time| start.date().and_time(time))
+            .or_else(|| string_to_end_date(start, s)),
+    }
+}
+
+/// Yes and no in their usual spellings, or the day the to-do was done
+fn parse_done(
+    s: &str,
+    date_formats: &[String],
+    now: NaiveDateTime,
+) -> Result<Option<NaiveDateTime>, String> {
+    match s.to_lowercase().as_str() {
+        "" | "no" | "n" | "false" | "0" | "open" | "todo" | "to do" | "pending" | "[ ]" => Ok(None),
+        "x" | "yes" | "y" | "true" | "1" | "done" | "completed" | "complete" | "[x]" => {
+            Ok(Some(now))
+        }
+        _ => parse_date(s, date_formats)
+            .map(|(date, time)| Some(date.and_time(time.unwrap_or_default())))
+            .ok_or(format!("unknown done value {:?}, use yes, no or a date", s)),
+    }
+}
+
+/// e.g. "Event  2024-09-14  09:00–10:00  Team Meeting (Room A) #work"
+fn event_preview(event: &Events, formats: &Formats) -> String {
+    let mut line = format!(
+        "Event  {}  {}  {}",
+        event.date.format(&formats.date),
+        event.time_range_text(formats),
+        event.event_name
+    );
+    if !event.location.is_empty() {
+        line.push_str(&format!(" ({})", event.location));
+    }
+    if let Some(recurrence) = &event.recurrence {
+        line.push_str(&format!(", repeats {}", recurrence.to_rrule()));
+    }
+    push_tags(&mut line, &event.tags, event.project.as_deref());
+    line
+}
+
+/// e.g. "To-do  [ ] Send the report (B, due 2024-09-20) +q4"
+fn todo_preview(todo: &ToDo, formats: &Formats) -> String {
+    let mut line = format!(
+        "To-do  [{}] {}",
+        if todo.is_done() { "x" } else { " " },
+        todo.todo_name
+    );
+    let details: Vec<String> = todo
+        .priority
+        .to_letter()
+        .map(String::from)
+        .into_iter()
+        .chain(todo.due_text(formats).map(|due| format!("due {}", due)))
+        .collect();
+    if !details.is_empty() {
+        line.push_str(&format!(" ({})", details.join(", ")));
+    }
+    push_tags(&mut line, &todo.tags, todo.project.as_deref());
+    line
+}
+
+fn push_tags(line: &mut String, tags: &[String], project: Option<&str>) {
+    if !tags.is_empty() || project.is_some() {
+        line.push(' ');
+        line.push_str(&tags_text(tags, project));
+    }
+}
+
+/// The text of a CSV file, with the number of events and to-dos in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvExport {
+    pub text: String,
+    pub events: usize,
+    pub todos: usize,
+}
+
+/// Writes the selected events and to-dos of the storage as CSV, one row each
+/// Without a kind both are written, with a type column telling them apart
+/// Dates are written with `date_format`, times as HH:MM in the local time zone
+pub fn export_csv(
+    storage: &mut dyn Storage,
+    selection: &ExportSelection,
+    kind: Option<CsvKind>,
+    date_format: &str,
+) -> StorageResult<CsvExport> {
+    let (events, todos) = selection.select(storage)?;
+    Ok(csv_text(events, todos, kind, date_format))
+}
+
+/// Builds the file, the events are sorted by their start and the to-dos by their due date
+fn csv_text(
+    mut events: Vec<Events>,
+    mut todos: Vec<ToDo>,
+    kind: Option<CsvKind>,
+    date_format: &str,
+) -> CsvExport {
+    let fields: &[Field] = match kind {
+        Some(CsvKind::Events) => {
+            todos.clear();
+            &[
+                Field::Date,
+                Field::Time,
+                Field::End,
+                Field::Title,
+                Field::Location,
+                Field::Tags,
+                Field::Project,
+                Field::Repeat,
+            ]
+        }
+        Some(CsvKind::Todos) => {
+            events.clear();
+            &[
+                Field::Date,
+                Field::Time,
+                Field::Title,
+                Field::Tags,
+                Field::Project,
+                Field::Priority,
+                Field::Done,
+            ]
+        }
+        None => &Field::ALL,
+    };
+    events.sort_by_key(|ev| ev.to_local_time(ev.date));
+    todos.sort_by_key(|t| (t.due_date.is_none(), t.get_due_date_time()));
+
+    let mut text = csv_line(fields.iter().map(|f| f.header().to_string()));
+    for event in &events {
+        text.push_str(&csv_line(
+            fields.iter().map(|f| event_cell(event, *f, date_format)),
+        ));
+    }
+    for todo in &todos {
+        text.push_str(&csv_line(
+            fields.iter().map(|f| todo_cell(todo, *f, date_format)),
+        ));
+    }
+    CsvExport {
+        text,
+        events: events.len(),
+        todos: todos.len(),
+    }
+}
+
+fn event_cell(event: &Events, field: Field, date_format: &str) -> String {
+    let start = event.to_local_time(event.date);
+    match field {
+        Field::Type => String::from("event"),
+        Field::Date => start.format(date_format).to_string(),
+        Field::Time if event.all_day => String::new(),
+        Field::Time => start.format("%H:%M").to_string(),
+        Field::End => match event.end.map(|end| event.to_local_time(end)) {
+            None => String::new(),
+            // An all-day event only needs its last day, if it is another one
+            Some(end) if event.all_day && end.date() > start.date() => {
+                end.format(date_format).to_string()
+            }
+            Some(_) if event.all_day => String::new(),
+            Some(end) if end.date() == start.date() => end.format("%H:%M").to_string(),
+            Some(end) => format!("{} {}", end.format(date_format), end.format("%H:%M")),
+        },
+        Field::Title => event.event_name.clone(),
+        Field::Location => event.location.clone(),
+        Field::Tags => event.tags.join(", "),
+        Field::Project => event.project.clone().unwrap_or_default(),
+        Field::Repeat => event
+            .recurrence
+            .as_ref()
+            .map(Recurrence::to_rrule)
+            .unwrap_or_default(),
+        Field::Priority | Field::Done => String::new(),
+    }
+}
+
+fn todo_cell(todo: &ToDo, field: Field, date_format: &str) -> String {
+    match field {
+        Field::Type => String::from("todo"),
+        Field::Date => todo
+            .due_date
+            .map(|due| due.format(date_format).to_string())
+            .unwrap_or_default(),
+        Field::Time => todo
+            .due_time
+            .map(|due| due.format("%H:%M").to_string())
+            .unwrap_or_default(),
+        Field::Title => todo.todo_name.clone(),
+        Field::Tags => todo.tags.join(", "),
+        Field::Project => todo.project.clone().unwrap_or_default(),
+        Field::Priority => todo
+            .priority
+            .to_letter()
+            .map(String::from)
+            .unwrap_or_default(),
+        Field::Done => todo
+            .completed
+            .map(|done| done.format(date_format).to_string())
+            .unwrap_or_default(),
+        Field::End | Field::Location | Field::Repeat => String::new(),
+    }
+}
+
+/// A row of the file, ended with CRLF as in RFC 4180
+/// Fields holding a delimiter, a quote or a line break are quoted
+fn csv_line(fields: impl Iterator<Item = String>) -> String {
+    let fields: Vec<String> = fields
+        .map(|field| {
+            if field.contains([',', ';', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+/// Reads the records of a file; fields may be quoted, with `""` for a quote, and then hold
+/// delimiters and line breaks
+/// The delimiter is a semicolon or a tab if the first line holds more of it than of commas
+/// (outside quotes), and a comma otherwise
+fn parse_records(data: &str) -> Result<Vec<Record>, String> {
+    let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+    let first_line = data.lines().next().unwrap_or_default();
+    let commas = unquoted_count(first_line, ',');
+    let delimiter = [';', '\t']
+        .into_iter()
+        .filter(|d| unquoted_count(first_line, *d) > commas)
+        .max_by_key(|d| unquoted_count(first_line, *d))
+        .unwrap_or(',');
+
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                records.push(Record {
+                    line: record_line,
+                    fields: std::mem::take(&mut fields),
+                });
+                line += 1;
+                record_line = line;
+            }
+            c if c == delimiter => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("line {}: unterminated quoted field", record_line));
+    }
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push(Record {
+            line: record_line,
+            fields,
+        });
+    }
+    Ok(records)
+}
+
+/// Number of times a delimiter appears in a line, leaving out quoted fields
+fn unquoted_count(line: &str, delimiter: char) -> usize {
+    let mut quoted = false;
+    line.chars()
+        .filter(|c| {
+            if *c == '"' {
+                quoted = !quoted;
+            }
+            !quoted && *c == delimiter
+        })
+        .count()
+}
+
+/// Lowercase letters and digits only, e.g. `Due Date` becomes `duedate`
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    fn now() -> NaiveDateTime {
+        at(10, 1, 12, 0)
+    }
+
+    fn fields(data: &str) -> Vec<Vec<String>> {
+        parse_records(data)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.fields)
+            .collect()
+    }
+
+    fn import(data: &str, options: &CsvOptions) -> CsvImport {
+        import_csv(data, options, &[], &[], now()).unwrap()
+    }
+
+    #[test]
+    fn quoted_fields_with_line_breaks() {
+        let data = "\u{feff}title,notes\r\n\"Call \"\"Bob\"\"\",\"first\r\nsecond, third\"\r\nLast,\"a\nb\"";
+        let records = parse_records(data).unwrap();
+        assert_eq!(
+            records.iter().map(|r| r.line).collect::<Vec<_>>(),
+            [1, 2, 4]
+        );
+        assert_eq!(
+            records[1].fields,
+            ["Call \"Bob\"", "first\r\nsecond, third"]
+        );
+        assert_eq!(records[2].fields, ["Last", "a\nb"]);
+
+        // A quote inside an unquoted field is kept as it is
+        assert_eq!(fields("a 5\" disk,b\n"), [["a 5\" disk", "b"]]);
+        assert!(parse_records("title\n\"open").is_err());
+    }
+
+    #[test]
+    fn delimiter_of_the_first_line() {
+        assert_eq!(
+            fields("a;b;c\n1;2,5;3"),
+            [["a", "b", "c"], ["1", "2,5", "3"]]
+        );
+        assert_eq!(fields("a\tb\n1\t2"), [["a", "b"], ["1", "2"]]);
+        // Ties, quoted delimiters and a single column fall back to commas
+        assert_eq!(fields("a,b;c\n1,2"), [["a", "b;c"], ["1", "2"]]);
+        assert_eq!(fields("\"a;b;c\",d\n1,2"), [["a;b;c", "d"], ["1", "2"]]);
+        assert_eq!(fields("\"a,b\";c\n1;2"), [["a,b", "c"], ["1", "2"]]);
+        assert_eq!(fields("title\nx;y"), [["title"], ["x;y"]]);
+        assert!(fields("").is_empty());
+    }
+
+    #[test]
+    fn columns_by_header() {
+        let data = concat!(
+            "Subject;Due Date;Importance;Status;Owner\n",
+            "Send report;20.09.2024 17:30;high;no;Ann\n",
+            "Book room #ops +q4;21.09.2024;B;done;Bob\n",
+        );
+        let options = CsvOptions {
+            date_formats: vec![String::from("%d.%m.%Y")],
+            ..CsvOptions::default()
+        };
+        let import = import(data, &options);
+        assert_eq!(import.ignored_columns, ["Owner"]);
+        assert_eq!(import.skipped, ["line 2: unknown priority \"high\""]);
+        let [todo] = &import.todos[..] else {
+            panic!("one to-do expected, got {:?}", import.todos);
+        };
+        assert_eq!(todo.todo_name, "Book room");
+        assert_eq!(todo.tags, ["ops"]);
+        assert_eq!(todo.project.as_deref(), Some("q4"));
+        assert_eq!(todo.priority, Priority::B);
+        assert_eq!(todo.completed, Some(now()));
+        assert_eq!(todo.due_date, Some(at(9, 21, 0, 0).date()));
+
+        let options = CsvOptions {
+            columns: vec![(Field::Title, String::from("missing"))],
+            ..CsvOptions::default()
+        };
+        assert!(import_csv(data, &options, &[], &[], now()).is_err());
+        assert!(import_csv("date,place\n", &CsvOptions::default(), &[], &[], now()).is_err());
+        assert!(import_csv("", &CsvOptions::default(), &[], &[], now()).is_err());
+    }
+
+    #[test]
+    fn kind_of_the_rows() {
+        let events = import(
+            "date,time,title\n2024-09-14,9:00,Meeting\n",
+            &CsvOptions::default(),
+        );
+        assert_eq!(events.events.len(), 1);
+        assert_eq!(events.events[0].date, at(9, 14, 9, 0));
+        let todos = import("due,title\n2024-09-14,Report\n", &CsvOptions::default());
+        assert_eq!(todos.todos.len(), 1);
+
+        let data = concat!(
+            "type,date,title\n",
+            "event,2024-09-14,Trip\n",
+            "todo,,Pack\n",
+            "note,,Other\n",
+            "event,,No date\n",
+        );
+        let import = import(data, &CsvOptions::default());
+        assert!(import.events[0].all_day);
+        assert_eq!(import.todos[0].todo_name, "Pack");
+        assert_eq!(
+            import.skipped,
+            [
+                "line 4: unknown type \"note\", use event or todo",
+                "line 5: \"No date\" has no date"
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicates_are_left_out() {
+        let existing = Events::new(at(9, 14, 9, 0), String::from("Meeting"), String::new());
+        let data = concat!(
+            "date,time,end,title\n",
+            "2024-09-14,09:00,1h,Meeting\n",
+            "2024-09-15,09:00,10:30,Meeting\n",
+            "2024-09-15,09:00,,Meeting\n",
+            "2024-09-16,09:00,08:00,Meeting\n",
+        );
+        let import = import_csv(data, &CsvOptions::default(), &[existing], &[], now()).unwrap();
+        assert_eq!(import.duplicates, 2);
+        assert_eq!(import.events.len(), 1);
+        assert_eq!(import.events[0].end, Some(at(9, 15, 10, 30)));
+        assert_eq!(
+            import.skipped,
+            ["line 5: \"Meeting\" ends before it starts"]
+        );
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let mut meeting = Events::new(
+            at(9, 10, 9, 0),
+            String::from("Planning, \"part 1\""),
+            String::from("Room 4; 2nd floor\nnext to the lift"),
+        );
+        meeting.end = Some(at(9, 10, 10, 30));
+        meeting.tags = vec![String::from("work"), String::from("q4")];
+        meeting.project = Some(String::from("launch"));
+        meeting.recurrence = Some(Recurrence::from_rrule("FREQ=WEEKLY;BYDAY=TU,TH").unwrap());
+
+        let mut trip = Events::new(at(9, 20, 0, 0), String::from("Trip"), String::new());
+        trip.all_day = true;
+        trip.end = Some(at(9, 22, 23, 59) + chrono::Duration::seconds(59));
+
+        let mut overnight = Events::new(at(9, 12, 22, 0), String::from("Night"), String::new());
+        overnight.end = Some(at(9, 13, 6, 0));
+
+        let mut report = ToDo::new(Priority::B, String::from("Report"));
+        report.due_date = Some(at(9, 30, 0, 0).date());
+        report.due_time = Some(NaiveTime::from_hms_opt(17, 0, 0).unwrap());
+        report.tags = vec![String::from("work")];
+        let mut done = ToDo::new(Priority::None, String::from("Call, then \"write\""));
+        done.completed = Some(at(9, 2, 0, 0));
+
+        let events = vec![meeting, overnight, trip];
+        let todos = vec![report, done];
+        for (kind, date_format) in [(None, "%Y-%m-%d"), (Some(CsvKind::Events), "%d/%m/%Y")] {
+            let export = csv_text(events.clone(), todos.clone(), kind, date_format);
+            let options = CsvOptions {
+                kind,
+                date_formats: vec![date_format.to_string()],
+                ..CsvOptions::default()
+            };
+            let import = import(&export.text, &options);
+            assert!(import.skipped.is_empty(), "{:?}", import.skipped);
+            assert_eq!(import.events, events);
+            if kind.is_none() {
+                assert_eq!(import.todos, todos);
+            } else {
+                assert!(import.todos.is_empty());
+            }
+        }
+    }
+}
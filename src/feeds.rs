@@ -0,0 +1,248 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    backup::write_atomic, calendar_data::Events, config::FeedConfig, ical::import_ics,
+    paths::AppPaths, storage::StorageResult,
+};
+
+/// Minutes before a failed download is tried again, unless the feed is refreshed more rarely
+const RETRY_MINUTES: u64 = 15;
+
+/// A subscribed calendar, as last downloaded
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FeedCalendar {
+    pub name: String,
+    pub events: Vec<Events>,
+    /// When the cached copy was written, `None` if the feed was never downloaded
+    pub fetched: Option<SystemTime>,
+    /// Why the last download (or reading the cached copy) failed
+    pub error: Option<String>,
+}
+
+impl FeedCalendar {
+    /// e.g. "Holidays: 14 event(s), fetched 2024-09-14 10:02"
+    pub fn describe(&self) -> String {
+        let fetched = match self.fetched {
+            Some(time) => format!(
+                "fetched {}",
+                chrono::DateTime::<chrono::Local>::from(time).format("%Y-%m-%d %H:%M")
+            ),
+            None => String::from("never fetched"),
+        };
+        let mut line = format!("{}: {} event(s), {}", self.name, self.events.len(), fetched);
+        if let Some(error) = &self.error {
+            line.push_str(&format!(" ({})", error));
+        }
+        line
+    }
+}
+
+/// File name of the cached copy, from the feed's name: lowercase letters, digits and dashes
+pub fn cache_name(name: &str) -> String {
+    let mut cache_name = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            cache_name.push(c);
+        } else if !cache_name.is_empty() && !cache_name.ends_with('-') {
+            cache_name.push('-');
+        }
+    }
+    cache_name.trim_end_matches('-').to_string()
+}
+
+fn cache_path(feed: &FeedConfig, paths: &AppPaths) -> PathBuf {
+    paths
+        .feeds_dir()
+        .join(format!("{}.ics", cache_name(&feed.name)))
+}
+
+/// The feeds as cached on disk, without downloading them
+pub fn load_cached_feeds(feeds: &[FeedConfig], paths: &AppPaths) -> Vec<FeedCalendar> {
+    feeds.iter().map(|feed| load_cached(feed, paths)).collect()
+}
+
+fn load_cached(feed: &FeedConfig, paths: &AppPaths) -> FeedCalendar {
+    let path = cache_path(feed, paths);
+    let mut calendar = FeedCalendar {
+        name: feed.name.clone(),
+        ..FeedCalendar::default()
+    };
+    let Ok(data) = fs::read_to_string(&path) else {
+        return calendar;
+    };
+    calendar.fetched = fs::metadata(&path).and_then(|m| m.modified()).ok();
+    match parse_feed(&data) {
+        Ok(events) => calendar.events = events,
+        Err(e) => calendar.error = Some(format!("{}: {}", path.display(), e)),
+    }
+    calendar
+}
+
+/// Downloads a feed and replaces its cached copy
+/// If the download fails, the cached events are kept along with the error
+pub async fn fetch_feed(
+    client: &reqwest::Client,
+    feed: &FeedConfig,
+    paths: &AppPaths,
+) -> FeedCalendar {
+    match download(client, feed, paths).await {
+        Ok(events) => FeedCalendar {
+            name: feed.name.clone(),
+            events,
+            fetched: Some(SystemTime::now()),
+            error: None,
+        },
+        Err(e) => FeedCalendar {
+            error: Some(e.to_string()),
+            ..load_cached(feed, paths)
+        },
+    }
+}
+
+/// Downloads every feed, whether it is due or not
+pub async fn fetch_all_feeds(feeds: &[FeedConfig], paths: &AppPaths) -> Vec<FeedCalendar> {
+    let client = feed_client();
+    let mut calendars = Vec::new();
+    for feed in feeds {
+        calendars.push(fetch_feed(&client, feed, paths).await);
+    }
+    calendars
+}
+
+async fn download(
+    client: &reqwest::Client,
+    feed: &FeedConfig,
+    paths: &AppPaths,
+) -> StorageResult<Vec<Events>> {
+    let response = client
+        .get(feed.url.clone())
+        .send()
+        .await
+        .map_err(|e| format!("Could not reach {}: {}", feed.url, e))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("{} answered {}", feed.url, status).into());
+    }
+    let data = response.text().await?;
+    // Checked before the cached copy is replaced, e.g. by an error page
+    let events = parse_feed(&data).map_err(|e| format!("{}: {}", feed.url, e))?;
+    let path = cache_path(feed, paths);
+    fs::create_dir_all(paths.feeds_dir())
+        .map_err(|e| format!("Could not create {}: {}", paths.feeds_dir().display(), e))?;
+    write_atomic(&path, data.as_bytes())?;
+    Ok(events)
+}
+
+/// The events of a feed; what cannot be read (to-dos, unsupported rules) is left out
+fn parse_feed(data: &str) -> Result<Vec<Events>, String> {
+    Ok(import_ics(data, &[])?.events)
+}
+
+fn feed_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap_or_default()
+}
+
+/// Downloads the feeds in the background while the calendar is open, each one when its
+/// cached copy is older than `refresh_minutes`, and all of them when asked to
+pub struct FeedWorker {
+    requests: Sender<()>,
+    updates: Receiver<FeedCalendar>,
+}
+
+impl FeedWorker {
+    pub fn start(feeds: Vec<FeedConfig>, paths: AppPaths) -> Self {
+        let (requests, request_receiver) = channel();
+        let (update_sender, updates) = channel();
+        thread::spawn(move || run_worker(feeds, paths, request_receiver, update_sender));
+        Self { requests, updates }
+    }
+
+    pub fn request_refresh(&self) {
+        // The worker only stops with the app
+        let _ = self.requests.send(());
+    }
+
+    /// The feeds downloaded since the previous call
+    pub fn updates(&self) -> Vec<FeedCalendar> {
+        self.updates.try_iter().collect()
+    }
+}
+
+fn run_worker(
+    feeds: Vec<FeedConfig>,
+    paths: AppPaths,
+    requests: Receiver<()>,
+    updates: Sender<FeedCalendar>,
+) {
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    else {
+        return;
+    };
+    let client = feed_client();
+
+    // The cached copies tell when each feed is next due
+    let now = Instant::now();
+    let mut due: Vec<Option<Instant>> = feeds
+        .iter()
+        .map(|feed| {
+            let age = load_cached(feed, &paths)
+                .fetched
+                .and_then(|fetched| fetched.elapsed().ok());
+            match age {
+                None => Some(now),
+                Some(_) if feed.refresh_minutes == 0 => None,
+                Some(age) => Some(now + minutes(feed.refresh_minutes).saturating_sub(age)),
+            }
+        })
+        .collect();
+
+    let mut refresh_all = false;
+    loop {
+        for (feed, due) in feeds.iter().zip(due.iter_mut()) {
+            if !refresh_all && due.is_none_or(|due| due > Instant::now()) {
+                continue;
+            }
+            let calendar = runtime.block_on(fetch_feed(&client, feed, &paths));
+            let wait = match (&calendar.error, feed.refresh_minutes) {
+                (_, 0) => None,
+                (Some(_), refresh) => Some(minutes(refresh.min(RETRY_MINUTES))),
+                (None, refresh) => Some(minutes(refresh)),
+            };
+            *due = wait.map(|wait| Instant::now() + wait);
+            if updates.send(calendar).is_err() {
+                return;
+            }
+        }
+        refresh_all = false;
+
+        let next = due.iter().flatten().min().copied();
+        let request = match next {
+            Some(next) => requests.recv_timeout(next.saturating_duration_since(Instant::now())),
+            None => requests.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match request {
+            Ok(()) => {
+                // Requests made during the downloads are served by this refresh
+                while requests.try_recv().is_ok() {}
+                refresh_all = true;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+fn minutes(minutes: u64) -> Duration {
+    Duration::from_secs(minutes * 60)
+}
@@ -0,0 +1,64 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Notices changes to the data files made by other programs (scripts, synced folders, ...)
+/// The directories are watched rather than the files, since many editors and sync tools
+/// replace a file by renaming a new one over it
+pub struct FileWatcher {
+    // Kept alive for as long as the events are wanted
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    paths: Vec<PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new(paths: &[PathBuf]) -> notify::Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        let mut directories: Vec<PathBuf> = paths.iter().map(|p| parent_directory(p)).collect();
+        directories.sort();
+        directories.dedup();
+        for directory in &directories {
+            watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            paths: paths.iter().map(|p| absolute_path(p)).collect(),
+        })
+    }
+
+    /// Whether one of the files was written, created or removed since the last call
+    pub fn has_changed(&self) -> bool {
+        let mut changed = false;
+        // All the pending events are drained, a single reload covers them
+        for event in self.events.try_iter().flatten() {
+            let is_change = matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            );
+            if is_change && event.paths.iter().any(|p| self.paths.contains(p)) {
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn parent_directory(path: &Path) -> PathBuf {
+    match absolute_path(path).parent() {
+        Some(directory) => directory.to_path_buf(),
+        None => PathBuf::from("."),
+    }
+}
+
+/// The watcher reports absolute paths, so the watched files are compared in that form
+fn absolute_path(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or(path.to_path_buf())
+}
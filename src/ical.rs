@@ -0,0 +1,1124 @@
+use std::{fs, path::Path};
+
+use chrono::{
+    DateTime, Datelike, Days, Duration, FixedOffset, Local, Months, NaiveDate, NaiveDateTime,
+    NaiveTime, Offset, TimeZone, Utc, Weekday,
+};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+
+use crate::{
+    calendar_data::Events,
+    recurrence::{weekday_to_str, Recurrence},
+    storage::{Storage, StorageResult},
+    tags::Filter,
+    to_do_data::{Priority, ToDo},
+};
+
+/// Result of reading an .ics file: the new events, and what was left out
+#[derive(Debug, Default)]
+pub struct IcsImport {
+    pub events: Vec<Events>,
+    /// Events whose UID is already in the calendar (or earlier in the file)
+    pub duplicates: usize,
+    /// Components that were not imported, with the reason
+    pub skipped: Vec<String>,
+    /// Parts of imported events that could not be kept, e.g. an unknown time zone
+    pub warnings: Vec<String>,
+}
+
+impl IcsImport {
+    /// e.g. "Imported 3 event(s), 1 already imported", followed by the warnings and skipped components
+    pub fn report(&self) -> Vec<String> {
+        let mut summary = format!("Imported {} event(s)", self.events.len());
+        if self.duplicates > 0 {
+            summary.push_str(&format!(", {} already imported", self.duplicates));
+        }
+        let mut lines = vec![summary];
+        lines.extend(self.warnings.iter().map(|w| format!("Warning: {}", w)));
+        lines.extend(self.skipped.iter().map(|s| format!("Skipped {}", s)));
+        lines
+    }
+}
+
+/// One `NAME;PARAM=VALUE:value` line of an .ics file, after unfolding
+#[derive(Debug, Clone, PartialEq)]
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A `BEGIN:NAME` … `END:NAME` block, with its properties and nested components
+#[derive(Debug, Clone, PartialEq)]
+struct Component {
+    name: String,
+    properties: Vec<Property>,
+    components: Vec<Component>,
+}
+
+impl Component {
+    fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    fn properties<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Property> {
+        self.properties.iter().filter(move |p| p.name == name)
+    }
+
+    /// Value of a TEXT property, unescaped
+    fn text(&self, name: &str) -> Option<String> {
+        self.property(name).map(|p| unescape_text(&p.value))
+    }
+}
+
+/// A DTSTART, DTEND or EXDATE value
+struct IcsDateTime {
+    date_time: NaiveDateTime,
+    all_day: bool,
+    time_zone: Option<Tz>,
+}
+
+/// Reads an .ics file and stores its new events, whose IDs are set in the result
+pub fn import_ics_file(path: &Path, storage: &mut dyn Storage) -> StorageResult<IcsImport> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+    let mut import = import_ics(&data, &storage.load_events()?)
+        .map_err(|e| format!("Malformed {}: {}", path.display(), e))?;
+    let ids = storage.insert_events(import.events.clone())?;
+    for (event, id) in import.events.iter_mut().zip(ids) {
+        event.id = id;
+    }
+    Ok(import)
+}
+
+/// Reads the VEVENTs of an .ics file into events, leaving out the ones whose UID is in `existing`
+/// Other components (VTODO, VJOURNAL, …) and changed occurrences of a series are reported as skipped
+pub fn import_ics(data: &str, existing: &[Events]) -> Result<IcsImport, String> {
+    let calendars = parse_components(data)?;
+    if !calendars.iter().any(|c| c.name == "VCALENDAR") {
+        return Err(String::from("no VCALENDAR in the file"));
+    }
+
+    let mut import = IcsImport::default();
+    let mut seen_uids: Vec<String> = existing.iter().filter_map(|ev| ev.uid.clone()).collect();
+    for calendar in calendars.iter().filter(|c| c.name == "VCALENDAR") {
+        for component in &calendar.components {
+            let summary = component.text("SUMMARY").unwrap_or_default();
+            match component.name.as_str() {
+                "VEVENT" => {}
+                // Time zones are looked up by their TZID instead
+                "VTIMEZONE" => continue,
+                name => {
+                    import
+                        .skipped
+                        .push(format!("{} {:?}: not supported", name, summary));
+                    continue;
+                }
+            }
+            if component.property("RECURRENCE-ID").is_some() {
+                import.skipped.push(format!(
+                    "VEVENT {:?}: changed occurrences of a series are not supported",
+                    summary
+                ));
+                continue;
+            }
+            if component
+                .property("STATUS")
+                .is_some_and(|status| status.value.eq_ignore_ascii_case("CANCELLED"))
+            {
+                import
+                    .skipped
+                    .push(format!("VEVENT {:?}: cancelled", summary));
+                continue;
+            }
+            let uid = component.text("UID");
+            if uid.as_ref().is_some_and(|uid| seen_uids.contains(uid)) {
+                import.duplicates += 1;
+                continue;
+            }
+
+            match event_from_component(component) {
+                Ok((event, warnings)) => {
+                    import.warnings.extend(
+                        warnings
+                            .into_iter()
+                            .map(|w| format!("VEVENT {:?}: {}", summary, w)),
+                    );
+                    seen_uids.extend(uid);
+                    import.events.push(event);
+                }
+                Err(reason) => import
+                    .skipped
+                    .push(format!("VEVENT {:?}: {}", summary, reason)),
+            }
+        }
+    }
+    Ok(import)
+}
+
+/// Converts a VEVENT, returning the event and what could not be kept of it
+fn event_from_component(component: &Component) -> Result<(Events, Vec<String>), String> {
+    let mut warnings = Vec::new();
+    let start = parse_date_time(
+        component.property("DTSTART").ok_or("no DTSTART")?,
+        &mut warnings,
+    )?;
+    let mut event = Events::new(
+        start.date_time,
+        component.text("SUMMARY").unwrap_or_default(),
+        component.text("LOCATION").unwrap_or_default(),
+    );
+    event.all_day = start.all_day;
+    event.time_zone = start.time_zone;
+    event.uid = component.text("UID");
+    event.description = component.text("DESCRIPTION").unwrap_or_default();
+
+    // CATEGORIES become tags, which cannot contain spaces
+    for categories in component.properties("CATEGORIES") {
+        for category in split_text_list(&categories.value) {
+            let tag = category.trim().replace(' ', "-");
+            if !tag.is_empty() && !event.tags.contains(&tag) {
+                event.tags.push(tag);
+            }
+        }
+    }
+
+    // The end is kept in the time zone of the start
+    let end = if let Some(end) = component.property("DTEND") {
+        Some(in_time_zone(
+            &parse_date_time(end, &mut warnings)?,
+            start.time_zone,
+        ))
+    } else if let Some(duration) = component.property("DURATION") {
+        let duration = parse_duration(&duration.value)
+            .ok_or(format!("invalid DURATION {:?}", duration.value))?;
+        Some(start.date_time + duration)
+    } else {
+        None
+    };
+    event.end = if start.all_day {
+        // DTEND is the day after the last one, while an all-day event ends on its last day
+        end.and_then(|end| end.date().pred_opt())
+            .filter(|last_day| *last_day > start.date_time.date())
+            .map(|last_day| last_day.and_time(NaiveTime::MIN))
+    } else {
+        end.filter(|end| *end > start.date_time)
+    };
+
+    if let Some(rrule) = component.property("RRULE") {
+        // Weeks always start on Monday here, so the rule is read without its WKST
+        let rule: Vec<&str> = rrule
+            .value
+            .split(';')
+            .filter(|part| !part.trim().to_uppercase().starts_with("WKST="))
+            .collect();
+        // An UNTIL in UTC is kept in the time zone of the event, like the other times
+        let mut recurrence = Recurrence::from_rrule_in(&rule.join(";"), start.time_zone)?;
+        for exdate in component.properties("EXDATE") {
+            for value in exdate.value.split(',') {
+                let exception = Property {
+                    value: value.to_string(),
+                    ..exdate.clone()
+                };
+                let exception = parse_date_time(&exception, &mut warnings)?;
+                recurrence
+                    .exceptions
+                    .push(in_time_zone(&exception, start.time_zone).date());
+            }
+        }
+        event.recurrence = Some(recurrence);
+    }
+
+    if component.components.iter().any(|c| c.name == "VALARM") {
+        warnings.push(String::from("reminders (VALARM) are not imported"));
+    }
+    warnings.dedup();
+    Ok((event, warnings))
+}
+
+/// Reads `20240914`, `20240914T090000`, `20240914T090000Z` or a date-time with a TZID parameter
+/// A TZID that is not an IANA zone leaves the event at its wall-clock time
+fn parse_date_time(property: &Property, warnings: &mut Vec<String>) -> Result<IcsDateTime, String> {
+    let value = property.value.trim();
+    let invalid = || format!("invalid {} {:?}", property.name, value);
+    if property
+        .param("VALUE")
+        .is_some_and(|v| v.eq_ignore_ascii_case("DATE"))
+        || value.len() == 8
+    {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| invalid())?;
+        return Ok(IcsDateTime {
+            date_time: date.and_time(NaiveTime::MIN),
+            all_day: true,
+            time_zone: None,
+        });
+    }
+
+    let (value, utc) = match value.strip_suffix(['Z', 'z']) {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+    let date_time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+    let time_zone = if utc {
+        Some(Tz::UTC)
+    } else if let Some(tzid) = property.param("TZID") {
+        let time_zone = time_zone_from_tzid(tzid);
+        if time_zone.is_none() {
+            warnings.push(format!(
+                "unknown time zone {:?}, kept at its wall-clock time",
+                tzid
+            ));
+        }
+        time_zone
+    } else {
+        None
+    };
+    Ok(IcsDateTime {
+        date_time,
+        all_day: false,
+        time_zone,
+    })
+}
+
+/// IANA names are used as they are, some programs prefix them, e.g. `/mozilla.org/20050126_1/Europe/Berlin`
+fn time_zone_from_tzid(tzid: &str) -> Option<Tz> {
+    let tzid = tzid.trim();
+    std::iter::once(tzid)
+        .chain(tzid.match_indices('/').map(|(i, _)| &tzid[i + 1..]))
+        .find_map(|name| name.parse::<Tz>().ok())
+}
+
+/// Wall-clock time of `date_time` in the time zone of the event
+/// Floating times are taken as they are
+fn in_time_zone(date_time: &IcsDateTime, time_zone: Option<Tz>) -> NaiveDateTime {
+    match (date_time.time_zone, time_zone) {
+        (Some(from), Some(to)) if from != to => from
+            .from_local_datetime(&date_time.date_time)
+            .earliest()
+            .map(|dt| dt.with_timezone(&to).naive_local())
+            .unwrap_or(date_time.date_time),
+        _ => date_time.date_time,
+    }
+}
+
+/// A duration such as `PT1H30M`, `P1D` or `P2W`
+fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let mut duration = Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in s.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' if number.is_empty() => in_time = true,
+            _ => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                duration += match (c, in_time) {
+                    ('W', false) => Duration::weeks(n),
+                    ('D', false) => Duration::days(n),
+                    ('H', true) => Duration::hours(n),
+                    ('M', true) => Duration::minutes(n),
+                    ('S', true) => Duration::seconds(n),
+                    _ => return None,
+                };
+            }
+        }
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(if negative { -duration } else { duration })
+}
+
+/// Splits the file into its components, e.g. a VCALENDAR holding VEVENTs
+fn parse_components(data: &str) -> Result<Vec<Component>, String> {
+    let mut open: Vec<Component> = Vec::new();
+    let mut components = Vec::new();
+    for line in unfold_lines(data) {
+        let property = parse_property(&line).ok_or(format!("malformed line {:?}", line))?;
+        match property.name.as_str() {
+            "BEGIN" => open.push(Component {
+                name: property.value.trim().to_uppercase(),
+                properties: Vec::new(),
+                components: Vec::new(),
+            }),
+            "END" => {
+                let name = property.value.trim().to_uppercase();
+                let component = open
+                    .pop()
+                    .filter(|c| c.name == name)
+                    .ok_or(format!("unexpected END:{}", name))?;
+                match open.last_mut() {
+                    Some(parent) => parent.components.push(component),
+                    None => components.push(component),
+                }
+            }
+            // Properties outside of any component are ignored
+            _ => {
+                if let Some(component) = open.last_mut() {
+                    component.properties.push(property);
+                }
+            }
+        }
+    }
+    if let Some(component) = open.last() {
+        return Err(format!("END:{} is missing", component.name));
+    }
+    Ok(components)
+}
+
+/// Long lines are folded by starting the continuation with a space or a tab
+fn unfold_lines(data: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in data.trim_start_matches('\u{feff}').lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Reads `NAME;PARAM=VALUE;PARAM="quoted:value":value`, names are case-insensitive
+fn parse_property(line: &str) -> Option<Property> {
+    // The value starts at the first colon outside of quotes
+    let mut in_quotes = false;
+    let (colon, _) = line.char_indices().find(|&(_, c)| {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        c == ':' && !in_quotes
+    })?;
+
+    let mut parts = split_outside_quotes(&line[..colon], ';').into_iter();
+    let name = parts.next()?.trim().to_uppercase();
+    if name.is_empty() {
+        return None;
+    }
+    let params = parts
+        .filter_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            Some((
+                name.trim().to_uppercase(),
+                value.trim().trim_matches('"').to_string(),
+            ))
+        })
+        .collect();
+    Some(Property {
+        name,
+        params,
+        value: line[colon + 1..].to_string(),
+    })
+}
+
+fn split_outside_quotes(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == separator && !in_quotes {
+            parts.push(&s[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Reverses the escaping of TEXT values: `\n`, `\,`, `\;` and `\\`
+fn unescape_text(s: &str) -> String {
+    let mut text = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => text.push('\n'),
+            Some(escaped) => text.push(escaped),
+            None => text.push('\\'),
+        }
+    }
+    text
+}
+
+/// Splits a list of TEXT values (e.g. CATEGORIES) at the commas that are not escaped
+fn split_text_list(s: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == ',' {
+            values.push(unescape_text(&s[start..i]));
+            start = i + 1;
+        }
+    }
+    values.push(unescape_text(&s[start..]));
+    values
+}
+
+/// Events and to-dos to export, all of them by default
+/// The range keeps the events with an occurrence in it and the to-dos due in it
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExportSelection {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Only the items carrying these tags and project
+    pub filter: Filter,
+}
+
+impl ExportSelection {
+    /// The selected events and to-dos of the storage
+    pub fn select(&self, storage: &mut dyn Storage) -> StorageResult<(Vec<Events>, Vec<ToDo>)> {
+        let events = storage
+            .load_events()?
+            .into_iter()
+            .filter(|ev| self.includes_event(ev))
+            .collect();
+        let todos = storage
+            .load_todos()?
+            .into_iter()
+            .filter(|t| self.includes_todo(t))
+            .collect();
+        Ok((events, todos))
+    }
+
+    fn includes_event(&self, event: &Events) -> bool {
+        self.filter.matches(&event.tags, event.project.as_deref()) && self.in_range(event)
+    }
+
+    /// To-dos without a due date are left out when a range is given
+    fn includes_todo(&self, todo: &ToDo) -> bool {
+        let in_range = match todo.due_date {
+            Some(due) => {
+                self.from.is_none_or(|from| due >= from) && self.to.is_none_or(|to| due <= to)
+            }
+            None => self.from.is_none() && self.to.is_none(),
+        };
+        in_range && self.filter.matches(&todo.tags, todo.project.as_deref())
+    }
+
+    /// Whether an occurrence of the event covers a day of the range
+    fn in_range(&self, event: &Events) -> bool {
+        // Occurrences starting from this day still cover `from` (or a later day)
+        let earliest_start = self
+            .from
+            .map(|from| from - Days::new(event.span_days() - 1));
+        let Some(recurrence) = &event.recurrence else {
+            return earliest_start.is_none_or(|start| event.date.date() >= start)
+                && self.to.is_none_or(|to| event.date.date() <= to);
+        };
+        // Without an end, a year (of each interval) after the start of the range is enough to
+        // find an occurrence of a series that is still running
+        let last_day = self.to.unwrap_or_else(|| {
+            earliest_start.unwrap_or(event.date.date())
+                + Days::new(366 * u64::from(recurrence.interval.max(1)))
+        });
+        let first_day = earliest_start.unwrap_or(event.date.date());
+        !recurrence
+            .occurrences_between(event.date, first_day, last_day)
+            .is_empty()
+    }
+}
+
+/// The text of an .ics file, with the number of events and to-dos in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct IcsExport {
+    pub text: String,
+    pub events: usize,
+    pub todos: usize,
+}
+
+/// Writes the selected events (as VEVENTs) and to-dos (as VTODOs) of the storage as an iCalendar file
+pub fn export_ics(
+    storage: &mut dyn Storage,
+    selection: &ExportSelection,
+) -> StorageResult<IcsExport> {
+    let (events, todos) = selection.select(storage)?;
+    Ok(IcsExport {
+        text: ics_text(&events, &todos, Utc::now()),
+        events: events.len(),
+        todos: todos.len(),
+    })
+}
+
+/// A single event as an iCalendar file, e.g. to store it on a CalDAV server
+pub fn event_ics_text(event: &Events) -> String {
+    ics_text(std::slice::from_ref(event), &[], Utc::now())
+}
+
+/// Builds the file, `now` is the time stamp of every component (DTSTAMP)
+fn ics_text(events: &[Events], todos: &[ToDo], now: DateTime<Utc>) -> String {
+    let stamp = format!("DTSTAMP:{}", now.format("%Y%m%dT%H%M%SZ"));
+    let mut lines = vec![
+        String::from("BEGIN:VCALENDAR"),
+        String::from("VERSION:2.0"),
+        String::from("PRODID:-//RustyTUICalendar//EN"),
+        String::from("CALSCALE:GREGORIAN"),
+    ];
+
+    // Each time zone used by an event is described once, before the events
+    let mut time_zones: Vec<Tz> = Vec::new();
+    for event in events.iter().filter(|ev| !ev.all_day) {
+        if let Some(tz) = event.time_zone.filter(|tz| *tz != Tz::UTC) {
+            if !time_zones.contains(&tz) {
+                time_zones.push(tz);
+            }
+        }
+    }
+    for tz in time_zones {
+        lines.extend(vtimezone_lines(tz, now.year()));
+    }
+
+    for event in events {
+        lines.push(String::from("BEGIN:VEVENT"));
+        lines.push(text_line(
+            "UID",
+            &event
+                .uid
+                .clone()
+                .unwrap_or_else(|| format!("event-{}@rusty-calendar", event.id)),
+        ));
+        lines.push(stamp.clone());
+        lines.push(event_date_line("DTSTART", event.date, event));
+        if let Some(end) = event.end {
+            // An all-day event ends on its last day, DTEND is the day after it
+            let end = if event.all_day {
+                (end.date() + Days::new(1)).and_time(NaiveTime::MIN)
+            } else {
+                end
+            };
+            lines.push(event_date_line("DTEND", end, event));
+        }
+        if let Some(recurrence) = &event.recurrence {
+            lines.push(format!("RRULE:{}", rrule_text(recurrence, event)));
+            for exception in &recurrence.exceptions {
+                lines.push(event_date_line(
+                    "EXDATE",
+                    exception.and_time(event.date.time()),
+                    event,
+                ));
+            }
+        }
+        lines.push(text_line("SUMMARY", &event.event_name));
+        if !event.location.is_empty() {
+            lines.push(text_line("LOCATION", &event.location));
+        }
+        if !event.description.is_empty() {
+            lines.push(text_line("DESCRIPTION", &event.description));
+        }
+        if !event.tags.is_empty() {
+            lines.push(categories_line(&event.tags));
+        }
+        lines.push(String::from("END:VEVENT"));
+    }
+
+    for todo in todos {
+        push_vtodo_lines(
+            &mut lines,
+            todo,
+            &format!("todo-{}@rusty-calendar", todo.id),
+            None,
+            &stamp,
+        );
+    }
+
+    lines.push(String::from("END:VCALENDAR"));
+    lines.iter().map(|line| fold_line(line) + "\r\n").collect()
+}
+
+/// A to-do and its subtasks, which refer to their parent with RELATED-TO
+fn push_vtodo_lines(
+    lines: &mut Vec<String>,
+    todo: &ToDo,
+    uid: &str,
+    parent_uid: Option<&str>,
+    stamp: &str,
+) {
+    lines.push(String::from("BEGIN:VTODO"));
+    lines.push(text_line("UID", uid));
+    lines.push(stamp.to_string());
+    lines.push(text_line("SUMMARY", &todo.todo_name));
+    // 1 is the highest priority and 9 the lowest, 0 (or no property) means none
+    let priority = match todo.priority {
+        Priority::A => Some(1),
+        Priority::B => Some(3),
+        Priority::C => Some(5),
+        Priority::D => Some(7),
+        Priority::E => Some(9),
+        Priority::None => None,
+    };
+    if let Some(priority) = priority {
+        lines.push(format!("PRIORITY:{}", priority));
+    }
+    match (todo.due_date, todo.due_time) {
+        (Some(due_date), Some(due_time)) => lines.push(format!(
+            "DUE:{}",
+            due_date.and_time(due_time).format("%Y%m%dT%H%M%S")
+        )),
+        (Some(due_date), None) => {
+            lines.push(format!("DUE;VALUE=DATE:{}", due_date.format("%Y%m%d")))
+        }
+        _ => {}
+    }
+    match todo.completed {
+        Some(completed) => {
+            lines.push(String::from("STATUS:COMPLETED"));
+            // Completion times are stored in local time, the file needs them in UTC
+            if let Some(completed) = Local.from_local_datetime(&completed).earliest() {
+                lines.push(format!(
+                    "COMPLETED:{}",
+                    completed.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")
+                ));
+            }
+        }
+        None => lines.push(String::from("STATUS:NEEDS-ACTION")),
+    }
+    if !todo.tags.is_empty() {
+        lines.push(categories_line(&todo.tags));
+    }
+    if let Some(parent_uid) = parent_uid {
+        lines.push(text_line("RELATED-TO", parent_uid));
+    }
+    lines.push(String::from("END:VTODO"));
+
+    // Subtasks are numbered like in the To-Do pane, e.g. `todo-3.1@rusty-calendar`
+    let (name, domain) = uid.split_once('@').unwrap_or((uid, ""));
+    for (number, subtask) in todo.subtasks.iter().enumerate() {
+        let subtask_uid = format!("{}.{}@{}", name, number + 1, domain);
+        push_vtodo_lines(lines, subtask, &subtask_uid, Some(uid), stamp);
+    }
+}
+
+/// `DTSTART;TZID=Europe/Berlin:20240910T090000`, `DTSTART:20240911T150000Z`,
+/// `DTSTART;VALUE=DATE:20240912` for all-day events, or a floating time
+fn event_date_line(name: &str, date_time: NaiveDateTime, event: &Events) -> String {
+    if event.all_day {
+        return format!("{};VALUE=DATE:{}", name, date_time.format("%Y%m%d"));
+    }
+    let value = date_time.format("%Y%m%dT%H%M%S");
+    match event.time_zone {
+        Some(Tz::UTC) => format!("{}:{}Z", name, value),
+        Some(tz) => format!("{};TZID={}:{}", name, tz.name(), value),
+        None => format!("{}:{}", name, value),
+    }
+}
+
+/// UNTIL has to be a date for all-day events, and in UTC for events with a time zone
+fn rrule_text(recurrence: &Recurrence, event: &Events) -> String {
+    let mut rule = Recurrence {
+        until: None,
+        ..recurrence.clone()
+    }
+    .to_rrule();
+    if let Some(until) = recurrence.until {
+        let until = match event.time_zone {
+            _ if event.all_day => until.format("%Y%m%d").to_string(),
+            Some(tz) => tz
+                .from_local_datetime(&until)
+                .earliest()
+                .map(|until| {
+                    until
+                        .with_timezone(&Utc)
+                        .format("%Y%m%dT%H%M%SZ")
+                        .to_string()
+                })
+                .unwrap_or_else(|| until.format("%Y%m%dT%H%M%S").to_string()),
+            None => until.format("%Y%m%dT%H%M%S").to_string(),
+        };
+        rule.push_str(&format!(";UNTIL={}", until));
+    }
+    rule
+}
+
+/// The current rules of a time zone, as yearly transitions found in `year`
+/// The rules start in 1970, so that they apply to all the events
+fn vtimezone_lines(tz: Tz, year: i32) -> Vec<String> {
+    let mut lines = vec![
+        String::from("BEGIN:VTIMEZONE"),
+        format!("TZID:{}", tz.name()),
+    ];
+    let transitions = time_zone_transitions(tz, year);
+    if transitions.is_empty() {
+        let offset =
+            tz.offset_from_utc_datetime(&NaiveDate::from_ymd_opt(year, 1, 1).unwrap().into());
+        lines.extend([
+            String::from("BEGIN:STANDARD"),
+            String::from("DTSTART:19700101T000000"),
+            format!("TZOFFSETFROM:{}", offset_text(offset.fix())),
+            format!("TZOFFSETTO:{}", offset_text(offset.fix())),
+        ]);
+        if let Some(name) = offset.abbreviation() {
+            lines.push(text_line("TZNAME", name));
+        }
+        lines.push(String::from("END:STANDARD"));
+    }
+    for transition in transitions {
+        let kind = if transition.daylight {
+            "DAYLIGHT"
+        } else {
+            "STANDARD"
+        };
+        let local = transition.local_time;
+        // e.g. the last Sunday of March is -1SU, the second Sunday is 2SU
+        let days_in_month = (local.date() + Months::new(1)).with_day(1).unwrap() - Days::new(1);
+        let week = if local.day() + 7 > days_in_month.day() {
+            -1
+        } else {
+            (local.day() as i32 - 1) / 7 + 1
+        };
+        let start = nth_weekday_of_month(1970, local.month(), local.weekday(), week)
+            .unwrap_or(local.date())
+            .and_time(local.time());
+        lines.push(format!("BEGIN:{}", kind));
+        lines.push(format!("DTSTART:{}", start.format("%Y%m%dT%H%M%S")));
+        lines.push(format!(
+            "RRULE:FREQ=YEARLY;BYMONTH={};BYDAY={}{}",
+            local.month(),
+            week,
+            weekday_to_str(&local.weekday())
+        ));
+        lines.push(format!("TZOFFSETFROM:{}", offset_text(transition.from)));
+        lines.push(format!("TZOFFSETTO:{}", offset_text(transition.to)));
+        if let Some(name) = &transition.name {
+            lines.push(text_line("TZNAME", name));
+        }
+        lines.push(format!("END:{}", kind));
+    }
+    lines.push(String::from("END:VTIMEZONE"));
+    lines
+}
+
+/// A change of the UTC offset of a time zone
+struct Transition {
+    /// Wall-clock time at which the change happens, before it
+    local_time: NaiveDateTime,
+    from: FixedOffset,
+    to: FixedOffset,
+    daylight: bool,
+    name: Option<String>,
+}
+
+/// The changes of the UTC offset during `year`, found by comparing the offsets day by day
+/// and then minute by minute on the day of a change
+fn time_zone_transitions(tz: Tz, year: i32) -> Vec<Transition> {
+    let offset_at = |instant: NaiveDateTime| tz.offset_from_utc_datetime(&instant);
+    let mut transitions = Vec::new();
+    let first_day = NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+    for day in first_day.iter_days().take_while(|d| d.year() == year) {
+        let midnight = day.and_time(NaiveTime::MIN);
+        let before = offset_at(midnight);
+        if before.fix() == offset_at(midnight + Duration::days(1)).fix() {
+            continue;
+        }
+        let Some(instant) = (1..=24 * 60)
+            .map(|minute| midnight + Duration::minutes(minute))
+            .find(|instant| offset_at(*instant).fix() != before.fix())
+        else {
+            continue;
+        };
+        let after = offset_at(instant);
+        transitions.push(Transition {
+            local_time: instant + before.fix(),
+            from: before.fix(),
+            to: after.fix(),
+            daylight: !after.dst_offset().is_zero(),
+            name: after.abbreviation().map(String::from),
+        });
+    }
+    transitions
+}
+
+/// The `week`-th `weekday` of a month, counted from the end of the month when negative
+fn nth_weekday_of_month(year: i32, month: u32, weekday: Weekday, week: i32) -> Option<NaiveDate> {
+    if week > 0 {
+        return NaiveDate::from_weekday_of_month_opt(year, month, weekday, week as u8);
+    }
+    let last_day =
+        NaiveDate::from_ymd_opt(year, month, 1)?.checked_add_months(Months::new(1))? - Days::new(1);
+    let back = (last_day.weekday().num_days_from_monday() + 7 - weekday.num_days_from_monday()) % 7;
+    Some(last_day - Days::new(u64::from(back) + 7 * u64::from((-week - 1) as u32)))
+}
+
+/// e.g. `+0100` or `-0430`
+fn offset_text(offset: FixedOffset) -> String {
+    let seconds = offset.local_minus_utc();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.abs();
+    let mut text = format!("{}{:02}{:02}", sign, seconds / 3600, seconds % 3600 / 60);
+    if seconds % 60 != 0 {
+        text.push_str(&format!("{:02}", seconds % 60));
+    }
+    text
+}
+
+fn text_line(name: &str, value: &str) -> String {
+    format!("{}:{}", name, escape_text(value))
+}
+
+fn categories_line(tags: &[String]) -> String {
+    let categories: Vec<String> = tags.iter().map(|tag| escape_text(tag)).collect();
+    format!("CATEGORIES:{}", categories.join(","))
+}
+
+/// Escapes backslashes, commas, semicolons and line breaks in TEXT values
+fn escape_text(s: &str) -> String {
+    let mut text = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => text.push_str("\\\\"),
+            ',' => text.push_str("\\,"),
+            ';' => text.push_str("\\;"),
+            '\n' => text.push_str("\\n"),
+            '\r' => {}
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+/// Lines are folded after 75 octets, without splitting a UTF-8 character
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    fn calendar(lines: &[&str]) -> String {
+        let mut data = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n");
+        for line in lines {
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data + "END:VCALENDAR\r\n"
+    }
+
+    #[test]
+    fn folded_lines_are_joined() {
+        let lines = unfold_lines("\u{feff}SUMMARY:Lo\r\n ng\r\n\tname\r\n\r\nLOCATION:Here\n");
+        assert_eq!(lines, ["SUMMARY:Longname", "LOCATION:Here"]);
+    }
+
+    #[test]
+    fn long_lines_are_folded_at_75_octets() {
+        let line = format!("DESCRIPTION:{}", "Grüße, ".repeat(30));
+        let folded = fold_line(&line);
+        assert!(folded.split("\r\n").all(|part| part.len() <= 75));
+        assert_eq!(unfold_lines(&folded), [line]);
+    }
+
+    #[test]
+    fn text_escaping_round_trip() {
+        let text = "Bring: chairs, tables; a \\ backslash\nand a second line";
+        assert_eq!(
+            escape_text(text),
+            "Bring: chairs\\, tables\\; a \\\\ backslash\\nand a second line"
+        );
+        assert_eq!(unescape_text(&escape_text(text)), text);
+        assert_eq!(unescape_text("a\\Nb\\"), "a\nb\\");
+        assert_eq!(
+            split_text_list("work,big\\, team,home"),
+            ["work", "big, team", "home"]
+        );
+    }
+
+    #[test]
+    fn properties_with_quoted_parameters() {
+        let property =
+            parse_property("dtstart;TZID=\"/example.com:1/Europe/Berlin\":20240910T090000")
+                .unwrap();
+        assert_eq!(property.name, "DTSTART");
+        assert_eq!(property.param("TZID"), Some("/example.com:1/Europe/Berlin"));
+        assert_eq!(property.value, "20240910T090000");
+        assert!(parse_property("no colon").is_none());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1DT2H"), Some(Duration::hours(26)));
+        assert_eq!(parse_duration("-P2W"), Some(Duration::weeks(-2)));
+        assert_eq!(parse_duration("1H"), None);
+    }
+
+    #[test]
+    fn import_folded_and_escaped_event() {
+        let data = calendar(&[
+            "BEGIN:VEVENT",
+            "UID:abc@example.com",
+            "DTSTART;TZID=/mozilla.org/20050126_1/Europe/Berlin:20240910T090000",
+            "DURATION:PT1H30M",
+            "RRULE:FREQ=WEEKLY;WKST=SU;BYDAY=TU;UNTIL=20241001T070000Z",
+            "EXDATE;TZID=Europe/Berlin:20240917T090000,20240924T090000",
+            "SUMMARY:Team meeting\\, weekly\\; bring",
+            "  notes",
+            "DESCRIPTION:First line\\nSecond line",
+            "CATEGORIES:work,big\\, team",
+            "BEGIN:VALARM",
+            "ACTION:DISPLAY",
+            "END:VALARM",
+            "END:VEVENT",
+        ]);
+        let import = import_ics(&data, &[]).unwrap();
+        assert!(import.skipped.is_empty());
+        assert_eq!(import.warnings.len(), 1);
+        let [event] = &import.events[..] else {
+            panic!("one event expected, got {:?}", import.events);
+        };
+        assert_eq!(event.event_name, "Team meeting, weekly; bring notes");
+        assert_eq!(event.description, "First line\nSecond line");
+        assert_eq!(event.tags, ["work", "big,-team"]);
+        assert_eq!(event.time_zone, Some(chrono_tz::Europe::Berlin));
+        assert_eq!(event.date, at(2024, 9, 10, 9, 0));
+        assert_eq!(event.end, Some(at(2024, 9, 10, 10, 30)));
+        let recurrence = event.recurrence.as_ref().unwrap();
+        assert_eq!(recurrence.by_day, [Weekday::Tue]);
+        // 07:00 UTC is 09:00 in Berlin in summer
+        assert_eq!(recurrence.until, Some(at(2024, 10, 1, 9, 0)));
+        assert_eq!(
+            recurrence.exceptions,
+            [
+                NaiveDate::from_ymd_opt(2024, 9, 17).unwrap(),
+                NaiveDate::from_ymd_opt(2024, 9, 24).unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn import_skips_duplicates_and_unsupported_components() {
+        let data = calendar(&[
+            "BEGIN:VEVENT",
+            "UID:known",
+            "DTSTART:20240910T090000",
+            "SUMMARY:Known",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "UID:new",
+            "DTSTART;VALUE=DATE:20240911",
+            "DTEND;VALUE=DATE:20240913",
+            "SUMMARY:New",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "UID:new",
+            "DTSTART:20240912T090000",
+            "SUMMARY:Again",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "UID:new",
+            "RECURRENCE-ID:20240918T090000",
+            "DTSTART:20240919T090000",
+            "SUMMARY:Moved",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART:20240914T090000",
+            "STATUS:CANCELLED",
+            "SUMMARY:Off",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:No start",
+            "END:VEVENT",
+            "BEGIN:VTODO",
+            "SUMMARY:Task",
+            "END:VTODO",
+        ]);
+        let mut known = Events::new(at(2024, 9, 10, 9, 0), String::from("Known"), String::new());
+        known.uid = Some(String::from("known"));
+
+        let import = import_ics(&data, &[known]).unwrap();
+        assert_eq!(import.duplicates, 2);
+        assert_eq!(import.skipped.len(), 4);
+        let [event] = &import.events[..] else {
+            panic!("one event expected, got {:?}", import.events);
+        };
+        // DTEND is the day after the last day of an all-day event
+        assert!(event.all_day);
+        assert_eq!(event.end, Some(at(2024, 9, 12, 0, 0)));
+
+        assert!(import_ics("BEGIN:VEVENT\r\nEND:VEVENT\r\n", &[]).is_err());
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let mut meeting = Events::new(
+            at(2024, 9, 10, 9, 0),
+            String::from("Planning; part 1, with a name that is long enough to be folded twice"),
+            String::from("Room 4, 2nd floor"),
+        );
+        meeting.id = 3;
+        meeting.uid = Some(String::from("meeting@example.com"));
+        meeting.end = Some(at(2024, 9, 10, 10, 0));
+        meeting.time_zone = Some(chrono_tz::America::New_York);
+        meeting.description = String::from("Agenda:\nbudget\\costs");
+        meeting.tags = vec![String::from("work"), String::from("q4")];
+        let mut recurrence = Recurrence::from_rrule("FREQ=WEEKLY;BYDAY=TU,TH").unwrap();
+        recurrence.until = Some(at(2024, 12, 31, 9, 0));
+        recurrence.exceptions = vec![NaiveDate::from_ymd_opt(2024, 9, 12).unwrap()];
+        meeting.recurrence = Some(recurrence);
+
+        let mut holiday = Events::new(at(2024, 12, 24, 0, 0), String::from("Off"), String::new());
+        holiday.uid = Some(String::from("holiday@example.com"));
+        holiday.all_day = true;
+        holiday.end = Some(at(2024, 12, 26, 0, 0));
+        holiday.recurrence = Some(Recurrence::from_rrule("FREQ=YEARLY;COUNT=3").unwrap());
+
+        let mut utc = Events::new(at(2024, 9, 11, 15, 0), String::from("Call"), String::new());
+        utc.uid = Some(String::from("call@example.com"));
+        utc.time_zone = Some(Tz::UTC);
+
+        let events = vec![meeting, holiday, utc];
+        let todo = ToDo::new(Priority::B, String::from("Not an event"));
+        let text = ics_text(&events, &[todo], Utc::now());
+        assert!(text.lines().all(|line| line.len() <= 75));
+        assert_eq!(text.matches("BEGIN:VTIMEZONE").count(), 1);
+
+        let import = import_ics(&text, &[]).unwrap();
+        assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+        assert_eq!(import.skipped.len(), 1);
+        let expected: Vec<Events> = events
+            .into_iter()
+            .map(|event| Events { id: 0, ..event })
+            .collect();
+        assert_eq!(import.events, expected);
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Local, NaiveDate};
+use serde::Serialize;
+
+use crate::{
+    backup::{backup_file, list_backups, restore_backup, write_atomic, Backup},
+    calendar_data::{Calendar, Events},
+    paths::AppPaths,
+    schema::{
+        check_schema_version, date_time_to_json, records_from_json, AppointmentsFile, TodosFile,
+        SCHEMA_VERSION,
+    },
+    storage::{event_day_range, Storage, StorageResult},
+    to_do_data::{ToDo, ToDoList},
+};
+
+/// Keeps the appointments and to-dos in two JSON files, which are rewritten on every change
+/// The previous contents are kept in the `backups` directory next to the files
+#[derive(Debug, Clone)]
+pub struct JsonStorage {
+    pub appointments_path: PathBuf,
+    pub todos_path: PathBuf,
+}
+
+impl JsonStorage {
+    pub fn new(appointments_path: PathBuf, todos_path: PathBuf) -> Self {
+        Self {
+            appointments_path,
+            todos_path,
+        }
+    }
+
+    pub fn from_paths(paths: &AppPaths) -> Self {
+        Self::new(paths.appointments(), paths.todos())
+    }
+
+    /// Writes empty files for the ones that do not exist yet, e.g. on the first run
+    pub fn create_missing_files(&self) -> StorageResult<()> {
+        if !self.appointments_path.exists() {
+            let current_date = date_time_to_json(Local::now().naive_local());
+            self.write_appointments(&Appointments {
+                current_date,
+                calendar: Calendar::new(),
+                next_id: 1,
+            })?;
+        }
+        if !self.todos_path.exists() {
+            self.write_todos(&Todos {
+                todolist: ToDoList::new(),
+                next_id: 1,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reads `appointments.json`, assigning IDs to events from older files that lack one
+    fn read_appointments(&self) -> StorageResult<Appointments> {
+        let appointment_path = self.appointments_path.display().to_string();
+        let data = fs::read_to_string(&self.appointments_path)
+            .map_err(|e| format!("Could not open {}: {}", appointment_path, e))?;
+        let app_json: AppointmentsFile = serde_json::from_str(&data)
+            .map_err(|e| format!("Malformed {}: {}", appointment_path, e))?;
+        check_schema_version(app_json.version, &appointment_path)?;
+
+        let mut calendar = Calendar::new();
+        calendar.all_events = records_from_json(
+            app_json.all_events,
+            "event",
+            "event_name",
+            &appointment_path,
+        )?;
+        let mut next_id = app_json.next_id.max(calendar.next_event_id());
+        // Events from older files without an ID get one here, it is kept on the next write
+        for event in calendar.all_events.iter_mut().filter(|ev| ev.id == 0) {
+            event.id = next_id;
+            next_id += 1;
+        }
+        Ok(Appointments {
+            current_date: app_json.current_date,
+            calendar,
+            next_id,
+        })
+    }
+
+    fn write_appointments(&self, appointments: &Appointments) -> StorageResult<()> {
+        let app_json = AppointmentsFile {
+            version: SCHEMA_VERSION,
+            current_date: appointments.current_date.clone(),
+            next_id: appointments.next_id,
+            all_events: appointments.calendar.all_events.iter().collect(),
+        };
+        write_json(&self.appointments_path, &app_json)
+    }
+
+    fn read_todos(&self) -> StorageResult<Todos> {
+        let todo_path = self.todos_path.display().to_string();
+        let data = fs::read_to_string(&self.todos_path)
+            .map_err(|e| format!("Could not open {}: {}", todo_path, e))?;
+        let todo_json: TodosFile =
+            serde_json::from_str(&data).map_err(|e| format!("Malformed {}: {}", todo_path, e))?;
+        check_schema_version(todo_json.version, &todo_path)?;
+
+        let mut todolist = ToDoList::new();
+        todolist.all_todos =
+            records_from_json(todo_json.all_todos, "to-do", "todo_name", &todo_path)?;
+        let mut next_id = todo_json.next_id.max(todolist.next_todo_id());
+        // Same as for the events, items from older files get their ID here
+        for todo in todolist.all_todos.iter_mut().filter(|t| t.id == 0) {
+            todo.id = next_id;
+            next_id += 1;
+        }
+        Ok(Todos { todolist, next_id })
+    }
+
+    fn write_todos(&self, todos: &Todos) -> StorageResult<()> {
+        let todo_json = TodosFile {
+            version: SCHEMA_VERSION,
+            next_id: todos.next_id,
+            all_todos: todos.todolist.all_todos.iter().collect(),
+        };
+        write_json(&self.todos_path, &todo_json)
+    }
+}
+
+/// Contents of `appointments.json`
+struct Appointments {
+    current_date: String,
+    calendar: Calendar,
+    /// IDs are never handed out twice, even after the event holding one was deleted
+    next_id: u32,
+}
+
+impl Appointments {
+    fn add_event(&mut self, mut event: Events) -> u32 {
+        if event.id == 0 {
+            event.id = self.next_id;
+        }
+        self.next_id = self.next_id.max(event.id + 1);
+        let id = event.id;
+        self.calendar.add_event_to_calendar(event);
+        id
+    }
+}
+
+/// Contents of `todos.json`
+struct Todos {
+    todolist: ToDoList,
+    next_id: u32,
+}
+
+impl Todos {
+    fn add_todo(&mut self, mut todo: ToDo) -> u32 {
+        if todo.id == 0 {
+            todo.id = self.next_id;
+        }
+        self.next_id = self.next_id.max(todo.id + 1);
+        let id = todo.id;
+        self.todolist.add_todo_to_list(todo);
+        id
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load_events(&mut self) -> StorageResult<Vec<Events>> {
+        Ok(self.read_appointments()?.calendar.all_events)
+    }
+
+    fn events_between(&mut self, from: NaiveDate, to: NaiveDate) -> StorageResult<Vec<Events>> {
+        // The whole file is read anyway, the range only trims the result
+        let mut events = self.load_events()?;
+        events.retain(|ev| {
+            let (first_day, last_day) = event_day_range(ev);
+            first_day <= to && last_day >= from
+        });
+        Ok(events)
+    }
+
+    fn insert_event(&mut self, event: Events) -> StorageResult<u32> {
+        let mut appointments = self.read_appointments()?;
+        let id = appointments.add_event(event);
+        self.write_appointments(&appointments)?;
+        Ok(id)
+    }
+
+    /// Written (and backed up) once for all the events
+    fn insert_events(&mut self, events: Vec<Events>) -> StorageResult<Vec<u32>> {
+        let mut appointments = self.read_appointments()?;
+        let ids = events
+            .into_iter()
+            .map(|event| appointments.add_event(event))
+            .collect();
+        self.write_appointments(&appointments)?;
+        Ok(ids)
+    }
+
+    fn update_event(&mut self, event: &Events) -> StorageResult<()> {
+        let mut appointments = self.read_appointments()?;
+        let stored_event = appointments
+            .calendar
+            .get_event_by_id_mut(event.id)
+            .ok_or(format!("No event with id {}", event.id))?;
+        *stored_event = event.clone();
+        self.write_appointments(&appointments)
+    }
+
+    fn delete_event(&mut self, id: u32) -> StorageResult<Option<Events>> {
+        let mut appointments = self.read_appointments()?;
+        let deleted = appointments.calendar.delete_event_from_calendar(id);
+        if deleted.is_some() {
+            self.write_appointments(&appointments)?;
+        }
+        Ok(deleted)
+    }
+
+    fn load_todos(&mut self) -> StorageResult<Vec<ToDo>> {
+        Ok(self.read_todos()?.todolist.all_todos)
+    }
+
+    fn todos_due_between(&mut self, from: NaiveDate, to: NaiveDate) -> StorageResult<Vec<ToDo>> {
+        let mut todos = self.load_todos()?;
+        todos.retain(|t| t.due_date.is_some_and(|due| due >= from && due <= to));
+        Ok(todos)
+    }
+
+    fn insert_todo(&mut self, todo: ToDo) -> StorageResult<u32> {
+        let mut todos = self.read_todos()?;
+        let id = todos.add_todo(todo);
+        self.write_todos(&todos)?;
+        Ok(id)
+    }
+
+    fn update_todo(&mut self, todo: &ToDo) -> StorageResult<()> {
+        let mut todos = self.read_todos()?;
+        let stored_todo = todos
+            .todolist
+            .all_todos
+            .iter_mut()
+            .find(|t| t.id == todo.id)
+            .ok_or(format!("No to-do with id {}", todo.id))?;
+        *stored_todo = todo.clone();
+        self.write_todos(&todos)
+    }
+
+    fn delete_todo(&mut self, id: u32) -> StorageResult<Option<ToDo>> {
+        let mut todos = self.read_todos()?;
+        let Some(index) = todos.todolist.all_todos.iter().position(|t| t.id == id) else {
+            return Ok(None);
+        };
+        let deleted = todos.todolist.remove_todo_from_list(index);
+        self.write_todos(&todos)?;
+        Ok(Some(deleted))
+    }
+
+    fn watched_paths(&self) -> Vec<PathBuf> {
+        vec![self.appointments_path.clone(), self.todos_path.clone()]
+    }
+
+    fn backups(&self) -> StorageResult<Vec<Backup>> {
+        let mut backups = list_backups(&self.appointments_path)?;
+        backups.extend(list_backups(&self.todos_path)?);
+        backups.sort_by_key(|backup| std::cmp::Reverse(backup.time));
+        Ok(backups)
+    }
+
+    fn restore_backup(&mut self, backup: &Backup) -> StorageResult<()> {
+        // A backup that cannot be read is refused, rather than replacing a working file with it
+        let mut backup_storage = self.clone();
+        if backup.original == self.appointments_path {
+            backup_storage.appointments_path = backup.path.clone();
+            backup_storage.read_appointments()?;
+        } else {
+            backup_storage.todos_path = backup.path.clone();
+            backup_storage.read_todos()?;
+        }
+        restore_backup(backup)
+    }
+}
+
+/// Writes a value as pretty-printed JSON, replacing the file after backing it up
+fn write_json<T: Serialize>(path: &Path, value: &T) -> StorageResult<()> {
+    let mut updated_data = serde_json::to_string_pretty(value)?;
+    updated_data.push('\n');
+
+    backup_file(path)?;
+    write_atomic(path, updated_data.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_do_data::Priority;
+
+    #[test]
+    fn ids_of_deleted_items_are_not_reused() {
+        let dir = std::env::temp_dir().join(format!("json-storage-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut storage = JsonStorage::new(dir.join("appointments.json"), dir.join("todos.json"));
+        storage.create_missing_files().unwrap();
+
+        let date = NaiveDate::from_ymd_opt(2024, 9, 2)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap();
+        let event = Events::new(date, "Standup".to_string(), String::new());
+        assert_eq!(storage.insert_event(event.clone()).unwrap(), 1);
+        assert_eq!(storage.insert_event(event.clone()).unwrap(), 2);
+        storage.delete_event(2).unwrap();
+        assert_eq!(storage.insert_event(event).unwrap(), 3);
+
+        let todo = ToDo::new(Priority::A, "Report".to_string());
+        assert_eq!(storage.insert_todo(todo.clone()).unwrap(), 1);
+        storage.delete_todo(1).unwrap();
+        assert_eq!(storage.insert_todo(todo).unwrap(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
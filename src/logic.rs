@@ -15,6 +15,7 @@ impl CalendarRender {
             calendar: Calendar {
                 current_date: chrono::Local::now().date_naive().into(),
                 all_events: Vec::new(),
+                feeds: Vec::new(),
             },
         }
     }
@@ -28,6 +29,7 @@ impl CalendarRender {
                     .checked_add_months(Months::new(1))
                     .unwrap(),
                 all_events: Vec::new(),
+                feeds: Vec::new(),
             },
         })
     }
@@ -41,6 +43,7 @@ impl CalendarRender {
                     .checked_sub_months(Months::new(1))
                     .unwrap(),
                 all_events: Vec::new(),
+                feeds: Vec::new(),
             },
         }
     }
@@ -1,8 +1,16 @@
 // use cursive::{event::Key, views::Dialog};
 use std::io::{self, stdout};
 
+use app_state::AppState;
+use caldav::SyncWorker;
 use calendar_data::Calendar;
 use chrono::{Days, Months, NaiveDateTime};
+use clap::Parser;
+use cli::Cli;
+use commands::run_command;
+use config::Config;
+use feeds::{load_cached_feeds, FeedWorker};
+use paths::{config_dir, has_legacy_data, AppPaths, CONFIG_FILE, LEGACY_DATA_DIR};
 use ratatui::{
     backend::CrosstermBackend,
     crossterm::{
@@ -14,20 +22,113 @@ use ratatui::{
     Terminal,
 };
 
+use storage::{open_storage, StorageKind, StorageLock};
+use tags::Filter;
 use tui_textarea::{Input, TextArea};
 use weather::Weather;
 use widgets::app_layout;
 
+pub mod app_state;
+pub mod backup;
+pub mod cal_text;
+pub mod caldav;
 pub mod calendar_data;
 pub mod calendar_widget;
+pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod csv;
+pub mod feeds;
+pub mod file_watcher;
+pub mod ical;
+pub mod json_storage;
 pub mod logic;
+pub mod paths;
+pub mod recurrence;
+pub mod schema;
+pub mod sqlite_storage;
+pub mod storage;
+pub mod tags;
 pub mod to_do_data;
 pub mod to_do_widget;
+pub mod todo_txt;
 pub mod weather;
 pub mod widgets;
+pub mod xml;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let config_path = match cli.config {
+        Some(config_path) => config_path,
+        None => config_dir()?.join(CONFIG_FILE),
+    };
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            // Printed as is, since the list of invalid settings spans several lines
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let paths = AppPaths {
+        todo_txt: config.todo_txt.clone(),
+        ..AppPaths::resolve(cli.data_dir, config.data_dir.clone())?
+    };
+
+    // The data files of older versions, kept in `assets/`, are only copied when asked to
+    let status = if let Some(old_dir) = &cli.migrate {
+        let copied = paths.migrate_from(old_dir)?;
+        Some(format!(
+            "Copied {} from {} to {}",
+            copied.join(", "),
+            old_dir.display(),
+            paths.data_dir.display()
+        ))
+    } else if !paths.has_data() && has_legacy_data() {
+        Some(format!(
+            "Found data in {}/, start with --migrate to use it",
+            LEGACY_DATA_DIR
+        ))
+    } else {
+        None
+    };
+
+    // Opened before the terminal is taken over, so that errors are readable
+    let kind = StorageKind::from_env_or(config.storage.unwrap_or_default())?;
+    let mut storage = open_storage(kind, &paths)?;
+
+    // Commands print their result and exit, leaving the terminal as it is
+    if let Some(command) = cli.command {
+        if let Some(status) = &status {
+            eprintln!("{}", status);
+        }
+        // A sync running in the calendar meanwhile is waited for
+        let result = match StorageLock::acquire(&paths) {
+            Ok(_lock) => run_command(command, storage.as_mut(), &config, &paths, cli.json).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut app_state = AppState::new(storage, paths.clone());
+    app_state.status = status;
+    // The calendar is synced in the background, with a storage of its own that is locked
+    // against the commands during a sync
+    app_state.sync = config
+        .caldav
+        .clone()
+        .map(|caldav| SyncWorker::start(caldav, kind, paths.clone()));
+    // The cached copies are shown until the feeds are downloaded again
+    app_state.calendar.feeds = load_cached_feeds(&config.feeds, &paths);
+    if !config.feeds.is_empty() {
+        app_state.feeds = Some(FeedWorker::start(config.feeds.clone(), paths.clone()));
+    }
+
     enable_raw_mode()?;
     stdout().execute(EnterAlternateScreen)?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
@@ -43,10 +144,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut calendar_date = calendar.current_date;
     let mut is_writing_mode = false;
     let mut should_quit = false;
+    let mut filter = Filter::default();
 
-    let city_name = "Guatemala";
     let weather = Weather::default();
-    let weather_text = weather.generate_weather_text(city_name).await?;
+    let weather_text = weather
+        .generate_weather_text(&config.city, config.units)
+        .await?;
 
     while !should_quit {
         terminal.draw(|f| {
@@ -56,7 +159,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 &mut calendar_date,
                 is_writing_mode,
                 &weather_text,
-                city_name,
+                &mut filter,
+                &mut app_state,
+                &config,
             );
         })?;
 
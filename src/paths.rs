@@ -0,0 +1,161 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backup::{backup_file, write_atomic},
+    storage::StorageResult,
+};
+
+/// Environment variables overriding the data and configuration directories
+pub const DATA_DIR_ENV: &str = "RUSTY_CALENDAR_DATA_DIR";
+pub const CONFIG_DIR_ENV: &str = "RUSTY_CALENDAR_CONFIG_DIR";
+
+/// Name of the application's directory inside the data and configuration directories
+const APP_DIRECTORY: &str = "rusty-calendar";
+/// Where older versions kept their data, relative to the working directory
+pub const LEGACY_DATA_DIR: &str = "assets";
+
+pub const APPOINTMENTS_FILE: &str = "appointments.json";
+pub const TODOS_FILE: &str = "todos.json";
+pub const DATABASE_FILE: &str = "calendar.db";
+pub const CONFIG_FILE: &str = "config.toml";
+pub const CALDAV_STATE_FILE: &str = "caldav-state.json";
+pub const FEEDS_DIR: &str = "feeds";
+pub const LOCK_FILE: &str = "storage.lock";
+
+/// Directories holding the data files and the configuration
+/// Unless overridden, they follow the XDG base directories (`~/.local/share/rusty-calendar` and
+/// `~/.config/rusty-calendar` on Linux) or their equivalents on other systems
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppPaths {
+    pub data_dir: PathBuf,
+    pub config_dir: PathBuf,
+    /// todo.txt file holding the to-dos instead of the data directory, if one is configured
+    pub todo_txt: Option<PathBuf>,
+}
+
+impl AppPaths {
+    /// `data_dir` is the one given on the command line, which wins over the environment,
+    /// which wins over `configured_data_dir` from the configuration file
+    pub fn resolve(
+        data_dir: Option<PathBuf>,
+        configured_data_dir: Option<PathBuf>,
+    ) -> StorageResult<Self> {
+        let data_dir = match data_dir
+            .or_else(|| path_from_env(DATA_DIR_ENV))
+            .or(configured_data_dir)
+        {
+            Some(data_dir) => data_dir,
+            None => dirs::data_dir()
+                .ok_or(format!(
+                    "Could not find a data directory, set {} or use --data-dir",
+                    DATA_DIR_ENV
+                ))?
+                .join(APP_DIRECTORY),
+        };
+        Ok(Self {
+            data_dir,
+            config_dir: config_dir()?,
+            todo_txt: None,
+        })
+    }
+
+    pub fn appointments(&self) -> PathBuf {
+        self.data_dir.join(APPOINTMENTS_FILE)
+    }
+
+    pub fn todos(&self) -> PathBuf {
+        self.data_dir.join(TODOS_FILE)
+    }
+
+    pub fn database(&self) -> PathBuf {
+        self.data_dir.join(DATABASE_FILE)
+    }
+
+    /// What is known of the CalDAV calendar since the last sync
+    pub fn caldav_state(&self) -> PathBuf {
+        self.data_dir.join(CALDAV_STATE_FILE)
+    }
+
+    /// Locked while the data files are being changed, see `StorageLock`
+    pub fn lock_file(&self) -> PathBuf {
+        self.data_dir.join(LOCK_FILE)
+    }
+
+    /// Cached copies of the subscribed feeds
+    pub fn feeds_dir(&self) -> PathBuf {
+        self.data_dir.join(FEEDS_DIR)
+    }
+
+    /// Whether any data file has been stored yet
+    pub fn has_data(&self) -> bool {
+        [self.appointments(), self.todos(), self.database()]
+            .iter()
+            .any(|path| path.exists())
+    }
+
+    /// Copies the data files found in `old_dir` (e.g. `assets/`) into the data directory
+    /// Files already there are backed up before being replaced
+    /// Returns the names of the copied files
+    pub fn migrate_from(&self, old_dir: &Path) -> StorageResult<Vec<String>> {
+        fs::create_dir_all(&self.data_dir)
+            .map_err(|e| format!("Could not create {}: {}", self.data_dir.display(), e))?;
+
+        let mut copied = Vec::new();
+        for file_name in [APPOINTMENTS_FILE, TODOS_FILE, DATABASE_FILE] {
+            let old_path = old_dir.join(file_name);
+            if !old_path.exists() {
+                continue;
+            }
+            let new_path = self.data_dir.join(file_name);
+            // Migrating twice from the same directory would back up the file onto itself
+            if same_file(&old_path, &new_path) {
+                continue;
+            }
+            let data = fs::read(&old_path)
+                .map_err(|e| format!("Could not open {}: {}", old_path.display(), e))?;
+            backup_file(&new_path)?;
+            write_atomic(&new_path, &data)?;
+            copied.push(file_name.to_string());
+        }
+        if copied.is_empty() {
+            return Err(format!("No data files to copy from {}", old_dir.display()).into());
+        }
+        Ok(copied)
+    }
+}
+
+pub fn config_dir() -> StorageResult<PathBuf> {
+    match path_from_env(CONFIG_DIR_ENV) {
+        Some(config_dir) => Ok(config_dir),
+        None => Ok(dirs::config_dir()
+            .ok_or(format!(
+                "Could not find a configuration directory, set {}",
+                CONFIG_DIR_ENV
+            ))?
+            .join(APP_DIRECTORY)),
+    }
+}
+
+/// Whether `assets/` in the working directory holds data from an older version
+pub fn has_legacy_data() -> bool {
+    let legacy_dir = Path::new(LEGACY_DATA_DIR);
+    [APPOINTMENTS_FILE, TODOS_FILE, DATABASE_FILE]
+        .iter()
+        .any(|file_name| legacy_dir.join(file_name).exists())
+}
+
+fn path_from_env(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
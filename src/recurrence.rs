@@ -0,0 +1,566 @@
+use chrono::{Datelike, Days, Local, Months, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    #[default]
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Frequency {
+    pub fn from_rrule_str(s: &str) -> Option<Self> {
+        match s.trim().to_uppercase().as_str() {
+            "DAILY" => Some(Frequency::Daily),
+            "WEEKLY" => Some(Frequency::Weekly),
+            "MONTHLY" => Some(Frequency::Monthly),
+            "YEARLY" => Some(Frequency::Yearly),
+            _ => None,
+        }
+    }
+
+    pub fn to_rrule_str(&self) -> &'static str {
+        match self {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        }
+    }
+}
+
+/// A repetition rule modelled on the iCalendar RRULE, e.g.
+/// `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE;COUNT=10`
+/// BYDAY limits a daily rule to those weekdays and gives every matching weekday of the period
+/// for weekly and monthly rules, it is not supported for yearly ones. Exceptions are the dates on which an occurrence is skipped (EXDATE)
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub by_day: Vec<Weekday>,
+    pub count: Option<u32>,
+    pub until: Option<NaiveDateTime>,
+    pub exceptions: Vec<NaiveDate>,
+}
+
+impl Recurrence {
+    pub fn new(frequency: Frequency) -> Self {
+        Self {
+            frequency,
+            interval: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Parses a rule of the form `FREQ=...;INTERVAL=...;BYDAY=...;COUNT=...;UNTIL=...`
+    /// The `RRULE:` prefix used in .ics files is accepted as well
+    /// An UNTIL in UTC (ending in `Z`) is converted to local time, as for a floating event
+    pub fn from_rrule(rule: &str) -> Result<Self, String> {
+        Self::from_rrule_in(rule, None)
+    }
+
+    /// Same as `from_rrule`, for an event in `time_zone`: an UNTIL in UTC is converted to the
+    /// wall-clock time of that zone, like the start of the event
+    pub fn from_rrule_in(rule: &str, time_zone: Option<Tz>) -> Result<Self, String> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+
+        let mut frequency = None;
+        let mut recurrence = Recurrence::new(Frequency::Daily);
+
+        for part in rule.split(';').filter(|p| !p.trim().is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or(format!("Malformed recurrence part: {}", part))?;
+            match key.trim().to_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(
+                        Frequency::from_rrule_str(value)
+                            .ok_or(format!("Unsupported frequency: {}", value))?,
+                    )
+                }
+                "INTERVAL" => {
+                    recurrence.interval = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("Invalid interval: {}", value))?;
+                    if recurrence.interval == 0 {
+                        return Err("Interval must be at least 1".to_string());
+                    }
+                }
+                "BYDAY" => {
+                    for day in value.split(',').filter(|d| !d.trim().is_empty()) {
+                        recurrence.by_day.push(
+                            weekday_from_str(day).ok_or(format!("Invalid weekday: {}", day))?,
+                        );
+                    }
+                }
+                "COUNT" => {
+                    recurrence.count = Some(
+                        value
+                            .trim()
+                            .parse()
+                            .map_err(|_| format!("Invalid count: {}", value))?,
+                    )
+                }
+                "UNTIL" => {
+                    let until = rrule_str_to_naive_date(value)
+                        .ok_or(format!("Invalid until date: {}", value))?;
+                    recurrence.until = Some(if value.trim().ends_with(['Z', 'z']) {
+                        utc_to_wall_clock(until, time_zone)
+                    } else {
+                        until
+                    });
+                }
+                _ => return Err(format!("Unsupported recurrence part: {}", part)),
+            }
+        }
+
+        recurrence.frequency = frequency.ok_or("Recurrence rule needs a FREQ".to_string())?;
+        if recurrence.frequency == Frequency::Yearly && !recurrence.by_day.is_empty() {
+            return Err(format!("Unsupported recurrence rule: {}", rule));
+        }
+        Ok(recurrence)
+    }
+
+    /// Inverse of `from_rrule`, without the exceptions which are stored separately
+    pub fn to_rrule(&self) -> String {
+        let mut rule = format!("FREQ={}", self.frequency.to_rrule_str());
+        if self.interval > 1 {
+            rule.push_str(&format!(";INTERVAL={}", self.interval));
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<&str> = self.by_day.iter().map(weekday_to_str).collect();
+            rule.push_str(&format!(";BYDAY={}", days.join(",")));
+        }
+        if let Some(count) = self.count {
+            rule.push_str(&format!(";COUNT={}", count));
+        }
+        if let Some(until) = self.until {
+            rule.push_str(&format!(";UNTIL={}", until.format("%Y%m%dT%H%M%S")));
+        }
+        rule
+    }
+
+    /// Whether the series starting at `start` has an occurrence on `date`
+    pub fn occurs_on(&self, start: NaiveDateTime, date: NaiveDate) -> bool {
+        !self.occurrences_between(start, date, date).is_empty()
+    }
+
+    /// All the occurrences of the series starting at `start` up to and including `end_date`
+    pub fn occurrences_until(
+        &self,
+        start: NaiveDateTime,
+        end_date: NaiveDate,
+    ) -> Vec<NaiveDateTime> {
+        self.occurrences_between(start, start.date(), end_date)
+    }
+
+    /// The occurrences of the series starting at `start` between the two dates (inclusive)
+    /// COUNT is applied before the exceptions are removed, as in RFC 5545
+    pub fn occurrences_between(
+        &self,
+        start: NaiveDateTime,
+        from: NaiveDate,
+        end_date: NaiveDate,
+    ) -> Vec<NaiveDateTime> {
+        let mut occurrences = Vec::new();
+        let mut seen = 0;
+
+        // The periods before the one holding `from` are skipped, unless COUNT needs the
+        // occurrences in them
+        let first_period = if self.count.is_none() {
+            self.periods_before(start.date(), from)
+        } else {
+            0
+        };
+        for period in first_period.. {
+            let Some(period_start) = self.period_start(start.date(), period) else {
+                break;
+            };
+            if period_start > end_date {
+                break;
+            }
+
+            for candidate in self.period_candidates(start.date(), period_start) {
+                if candidate < start.date() {
+                    continue;
+                }
+                if candidate > end_date {
+                    return occurrences;
+                }
+                let occurrence = candidate.and_time(start.time());
+                if self.until.is_some_and(|until| occurrence > until) {
+                    return occurrences;
+                }
+                seen += 1;
+                if self.count.is_some_and(|count| seen > count) {
+                    return occurrences;
+                }
+                if candidate >= from && !self.exceptions.contains(&candidate) {
+                    occurrences.push(occurrence);
+                }
+            }
+        }
+
+        occurrences
+    }
+
+    /// Number of whole periods of the series that end before `date`
+    fn periods_before(&self, start: NaiveDate, date: NaiveDate) -> u32 {
+        let elapsed = match self.frequency {
+            Frequency::Daily => (date - start).num_days(),
+            Frequency::Weekly => {
+                let week_start = start - Days::new(start.weekday().num_days_from_monday().into());
+                (date - week_start).num_days() / 7
+            }
+            Frequency::Monthly => {
+                i64::from(date.year() - start.year()) * 12 + i64::from(date.month())
+                    - i64::from(start.month())
+            }
+            Frequency::Yearly => i64::from(date.year() - start.year()),
+        };
+        u32::try_from(elapsed.max(0) / i64::from(self.interval.max(1))).unwrap_or(u32::MAX)
+    }
+
+    /// First day of the n-th period of the series
+    fn period_start(&self, start: NaiveDate, period: u32) -> Option<NaiveDate> {
+        let step = self.interval.max(1) * period;
+        match self.frequency {
+            Frequency::Daily => start.checked_add_days(Days::new(step.into())),
+            Frequency::Weekly => {
+                // Weeks start on Monday (WKST=MO)
+                let week_start = start - Days::new(start.weekday().num_days_from_monday().into());
+                week_start.checked_add_days(Days::new((step * 7).into()))
+            }
+            Frequency::Monthly => start.with_day(1)?.checked_add_months(Months::new(step)),
+            Frequency::Yearly => start
+                .with_day(1)?
+                .with_month(1)?
+                .with_year(start.year() + step as i32),
+        }
+    }
+
+    /// The dates of a period that match the rule, in chronological order
+    fn period_candidates(&self, start: NaiveDate, period_start: NaiveDate) -> Vec<NaiveDate> {
+        match self.frequency {
+            Frequency::Daily => {
+                if self.by_day.is_empty() || self.by_day.contains(&period_start.weekday()) {
+                    vec![period_start]
+                } else {
+                    Vec::new()
+                }
+            }
+            Frequency::Weekly => {
+                let mut days = if self.by_day.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    self.by_day.clone()
+                };
+                days.sort_by_key(|d| d.num_days_from_monday());
+                days.dedup();
+                days.iter()
+                    .filter_map(|d| {
+                        period_start.checked_add_days(Days::new(d.num_days_from_monday().into()))
+                    })
+                    .collect()
+            }
+            Frequency::Monthly => {
+                if self.by_day.is_empty() {
+                    period_start.with_day(start.day()).into_iter().collect()
+                } else {
+                    period_start
+                        .iter_days()
+                        .take_while(|d| d.month() == period_start.month())
+                        .filter(|d| self.by_day.contains(&d.weekday()))
+                        .collect()
+                }
+            }
+            Frequency::Yearly => {
+                NaiveDate::from_ymd_opt(period_start.year(), start.month(), start.day())
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+}
+
+pub fn weekday_from_str(s: &str) -> Option<Weekday> {
+    match s.trim().to_uppercase().as_str() {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+pub fn weekday_to_str(day: &Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// Wall-clock time in `time_zone` (or in the local time zone) of a time in UTC
+fn utc_to_wall_clock(date_time: NaiveDateTime, time_zone: Option<Tz>) -> NaiveDateTime {
+    let utc = Utc.from_utc_datetime(&date_time);
+    match time_zone {
+        Some(time_zone) => utc.with_timezone(&time_zone).naive_local(),
+        None => utc.with_timezone(&Local).naive_local(),
+    }
+}
+
+/// UNTIL may be a date (`20241231`) or a date-time (`20241231T235959`, optionally with a trailing `Z`)
+fn rrule_str_to_naive_date(s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim().trim_end_matches(['Z', 'z']);
+    if let Ok(date_time) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%S") {
+        Some(date_time)
+    } else {
+        NaiveDate::parse_from_str(s, "%Y%m%d")
+            .ok()
+            .and_then(|d| d.and_hms_opt(23, 59, 59))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn at_nine(y: i32, m: u32, d: u32) -> NaiveDateTime {
+        date(y, m, d).and_hms_opt(9, 0, 0).unwrap()
+    }
+
+    fn dates(rule: &str, start: NaiveDateTime, end: NaiveDate) -> Vec<NaiveDate> {
+        Recurrence::from_rrule(rule)
+            .unwrap()
+            .occurrences_until(start, end)
+            .iter()
+            .map(|occurrence| occurrence.date())
+            .collect()
+    }
+
+    #[test]
+    fn rrule_round_trip() {
+        let rule = "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE;COUNT=10";
+        assert_eq!(Recurrence::from_rrule(rule).unwrap().to_rrule(), rule);
+        let rule = "FREQ=MONTHLY;UNTIL=20241231T235959";
+        assert_eq!(Recurrence::from_rrule(rule).unwrap().to_rrule(), rule);
+        assert_eq!(
+            Recurrence::from_rrule("RRULE:freq=daily;interval=1").unwrap(),
+            Recurrence::new(Frequency::Daily)
+        );
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(Recurrence::from_rrule("INTERVAL=2").is_err());
+        assert!(Recurrence::from_rrule("FREQ=HOURLY").is_err());
+        assert!(Recurrence::from_rrule("FREQ=DAILY;INTERVAL=0").is_err());
+        assert!(Recurrence::from_rrule("FREQ=WEEKLY;BYDAY=XX").is_err());
+        assert!(Recurrence::from_rrule("FREQ=DAILY;BYMONTH=3").is_err());
+        assert!(Recurrence::from_rrule("FREQ=DAILY;COUNT").is_err());
+    }
+
+    #[test]
+    fn daily_by_day_limits_the_days() {
+        // 2024-09-02 is a Monday
+        assert_eq!(
+            dates(
+                "FREQ=DAILY;BYDAY=MO,WE,FR",
+                at_nine(2024, 9, 2),
+                date(2024, 9, 9)
+            ),
+            [
+                date(2024, 9, 2),
+                date(2024, 9, 4),
+                date(2024, 9, 6),
+                date(2024, 9, 9)
+            ]
+        );
+        // Every other day, of which only the Saturdays and Sundays are kept
+        assert_eq!(
+            dates(
+                "FREQ=DAILY;INTERVAL=2;BYDAY=SA,SU",
+                at_nine(2024, 9, 2),
+                date(2024, 9, 15)
+            ),
+            [date(2024, 9, 8), date(2024, 9, 14)]
+        );
+    }
+
+    #[test]
+    fn weekly_by_day_gives_every_matching_weekday() {
+        assert_eq!(
+            dates("FREQ=WEEKLY", at_nine(2024, 9, 4), date(2024, 9, 18)),
+            [date(2024, 9, 4), date(2024, 9, 11), date(2024, 9, 18)]
+        );
+        // The start week is counted from its Monday, the days before the start are skipped
+        assert_eq!(
+            dates(
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH",
+                at_nine(2024, 9, 4),
+                date(2024, 9, 23)
+            ),
+            [date(2024, 9, 5), date(2024, 9, 16), date(2024, 9, 19)]
+        );
+    }
+
+    #[test]
+    fn monthly_by_day_gives_every_matching_weekday() {
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY;BYDAY=FR",
+                at_nine(2024, 9, 10),
+                date(2024, 10, 5)
+            ),
+            [
+                date(2024, 9, 13),
+                date(2024, 9, 20),
+                date(2024, 9, 27),
+                date(2024, 10, 4)
+            ]
+        );
+        // Months without the day of the start are skipped
+        assert_eq!(
+            dates("FREQ=MONTHLY", at_nine(2024, 1, 31), date(2024, 5, 31)),
+            [date(2024, 1, 31), date(2024, 3, 31), date(2024, 5, 31)]
+        );
+    }
+
+    #[test]
+    fn yearly_rules() {
+        assert!(Recurrence::from_rrule("FREQ=YEARLY;BYDAY=MO").is_err());
+        assert_eq!(
+            dates("FREQ=YEARLY", at_nine(2024, 2, 29), date(2032, 12, 31)),
+            [date(2024, 2, 29), date(2028, 2, 29), date(2032, 2, 29)]
+        );
+    }
+
+    #[test]
+    fn count_includes_the_exceptions() {
+        let mut recurrence = Recurrence::from_rrule("FREQ=DAILY;COUNT=3").unwrap();
+        recurrence.exceptions.push(date(2024, 9, 3));
+        let occurrences = recurrence.occurrences_until(at_nine(2024, 9, 2), date(2024, 12, 31));
+        assert_eq!(occurrences, [at_nine(2024, 9, 2), at_nine(2024, 9, 4)]);
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        // A date-only UNTIL covers the whole day
+        assert_eq!(
+            dates(
+                "FREQ=DAILY;UNTIL=20240904",
+                at_nine(2024, 9, 2),
+                date(2024, 12, 31)
+            ),
+            [date(2024, 9, 2), date(2024, 9, 3), date(2024, 9, 4)]
+        );
+        // A date-time UNTIL before the time of the day's occurrence leaves it out
+        assert_eq!(
+            dates(
+                "FREQ=DAILY;UNTIL=20240904T080000",
+                at_nine(2024, 9, 2),
+                date(2024, 12, 31)
+            ),
+            [date(2024, 9, 2), date(2024, 9, 3)]
+        );
+    }
+
+    #[test]
+    fn until_in_utc_is_converted() {
+        // 07:00 UTC is 09:00 in Berlin in summer, so the occurrence at 09:00 is the last one
+        let rule = "FREQ=DAILY;UNTIL=20240904T070000Z";
+        let berlin = Recurrence::from_rrule_in(rule, Some(chrono_tz::Europe::Berlin)).unwrap();
+        assert_eq!(berlin.until, Some(at_nine(2024, 9, 4)));
+        assert_eq!(
+            berlin
+                .occurrences_until(at_nine(2024, 9, 2), date(2024, 12, 31))
+                .len(),
+            3
+        );
+        let new_york = Recurrence::from_rrule_in(rule, Some(chrono_tz::America::New_York));
+        assert_eq!(
+            new_york.unwrap().until,
+            date(2024, 9, 4).and_hms_opt(3, 0, 0)
+        );
+        // Without a time zone it is the local time of the computer
+        let local = Utc.from_utc_datetime(&date(2024, 9, 4).and_hms_opt(7, 0, 0).unwrap());
+        assert_eq!(
+            Recurrence::from_rrule(rule).unwrap().until,
+            Some(local.with_timezone(&Local).naive_local())
+        );
+    }
+
+    #[test]
+    fn until_and_count_stop_at_the_earlier() {
+        let start = at_nine(2024, 9, 2);
+        let end = date(2024, 12, 31);
+        assert_eq!(
+            dates("FREQ=WEEKLY;COUNT=2;UNTIL=20241231", start, end).len(),
+            2
+        );
+        assert_eq!(
+            dates("FREQ=WEEKLY;COUNT=20;UNTIL=20240916", start, end).len(),
+            3
+        );
+    }
+
+    #[test]
+    fn periods_before_the_window_are_skipped() {
+        let start = at_nine(2020, 1, 31);
+        for rule in [
+            "FREQ=DAILY;INTERVAL=3",
+            "FREQ=DAILY;BYDAY=MO,FR",
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,SU",
+            "FREQ=MONTHLY",
+            "FREQ=MONTHLY;INTERVAL=5;BYDAY=TU",
+            "FREQ=YEARLY;INTERVAL=2",
+            "FREQ=WEEKLY;COUNT=150",
+        ] {
+            let recurrence = Recurrence::from_rrule(rule).unwrap();
+            for (from, end) in [
+                (date(2019, 6, 1), date(2020, 3, 1)),
+                (date(2022, 2, 27), date(2022, 3, 31)),
+                (date(2024, 2, 29), date(2025, 1, 31)),
+            ] {
+                let expected: Vec<NaiveDateTime> = recurrence
+                    .occurrences_until(start, end)
+                    .into_iter()
+                    .filter(|occurrence| occurrence.date() >= from)
+                    .collect();
+                assert_eq!(
+                    recurrence.occurrences_between(start, from, end),
+                    expected,
+                    "{} from {}",
+                    rule,
+                    from
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn occurs_on() {
+        let recurrence = Recurrence::from_rrule("FREQ=WEEKLY;BYDAY=TU;COUNT=2").unwrap();
+        let start = at_nine(2024, 9, 3);
+        assert!(recurrence.occurs_on(start, date(2024, 9, 10)));
+        assert!(!recurrence.occurs_on(start, date(2024, 9, 11)));
+        assert!(!recurrence.occurs_on(start, date(2024, 9, 17)));
+        assert!(!recurrence.occurs_on(start, date(2024, 8, 27)));
+    }
+}
@@ -0,0 +1,274 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    calendar_data::{try_string_to_naive_date, Events},
+    recurrence::Recurrence,
+    to_do_data::{string_to_due_date, Priority, ToDo},
+};
+
+/// Version of the layout of `appointments.json` and `todos.json`
+/// Files without a `version` field were written before it existed and are read as version 1
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Date-times are stored as `%Y-%m-%d %H:%M:%S`
+const DATE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+fn schema_version_1() -> u32 {
+    1
+}
+
+fn is_zero(id: &u32) -> bool {
+    *id == 0
+}
+
+/// Layout of `appointments.json`
+/// The events are read as plain JSON values first, so that a malformed one can be reported on its own
+#[derive(Serialize, Deserialize)]
+pub struct AppointmentsFile<E = Value> {
+    #[serde(default = "schema_version_1")]
+    pub version: u32,
+    pub current_date: String,
+    /// One more than the highest ID given out so far, so that a deleted event's ID is not reused
+    /// Missing in older files, where it is taken from the stored events
+    #[serde(default)]
+    pub next_id: u32,
+    #[serde(default)]
+    pub all_events: Vec<E>,
+}
+
+/// Layout of `todos.json`
+#[derive(Serialize, Deserialize)]
+pub struct TodosFile<T = Value> {
+    #[serde(default = "schema_version_1")]
+    pub version: u32,
+    /// Same as for the events
+    #[serde(default)]
+    pub next_id: u32,
+    #[serde(default)]
+    pub all_todos: Vec<T>,
+}
+
+/// Layout of one event in `appointments.json`; optional fields are only written when they are set
+#[derive(Serialize, Deserialize)]
+pub struct EventRecord {
+    /// Missing in files written before events had an ID, one is assigned when loading
+    #[serde(default)]
+    pub id: u32,
+    pub date: String,
+    pub event_name: String,
+    pub location: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub all_day: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// RRULE without the exceptions, e.g. `FREQ=WEEKLY;BYDAY=MO,TH`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<String>,
+    /// Skipped occurrences of the recurrence, as %Y-%m-%d
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exceptions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+}
+
+impl TryFrom<EventRecord> for Events {
+    type Error = String;
+
+    fn try_from(record: EventRecord) -> Result<Self, Self::Error> {
+        let date = try_string_to_naive_date(&record.date)
+            .map_err(|_| format!("invalid date {:?}", record.date))?;
+        let end = record
+            .end
+            .map(|end| try_string_to_naive_date(&end).map_err(|_| format!("invalid end {:?}", end)))
+            .transpose()?;
+        let time_zone = record
+            .time_zone
+            .map(|tz| {
+                tz.parse::<Tz>()
+                    .map_err(|_| format!("unknown time zone {:?}", tz))
+            })
+            .transpose()?;
+        let recurrence = match record.recurrence {
+            Some(rule) => {
+                let mut recurrence = Recurrence::from_rrule(&rule)?;
+                recurrence.exceptions = record
+                    .exceptions
+                    .iter()
+                    .map(|d| {
+                        NaiveDate::parse_from_str(d, "%Y-%m-%d")
+                            .map_err(|_| format!("invalid exception date {:?}", d))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Some(recurrence)
+            }
+            None => None,
+        };
+
+        Ok(Events {
+            id: record.id,
+            date,
+            event_name: record.event_name,
+            location: record.location,
+            end,
+            all_day: record.all_day,
+            time_zone,
+            recurrence,
+            tags: record.tags,
+            project: record.project,
+            uid: record.uid,
+            description: record.description,
+        })
+    }
+}
+
+impl From<Events> for EventRecord {
+    fn from(event: Events) -> Self {
+        Self {
+            id: event.id,
+            date: date_time_to_json(event.date),
+            event_name: event.event_name,
+            location: event.location,
+            end: event.end.map(date_time_to_json),
+            all_day: event.all_day,
+            time_zone: event.time_zone.map(|tz| tz.to_string()),
+            tags: event.tags,
+            project: event.project,
+            recurrence: event.recurrence.as_ref().map(|r| r.to_rrule()),
+            exceptions: event
+                .recurrence
+                .map(|r| r.exceptions.iter().map(|d| d.to_string()).collect())
+                .unwrap_or_default(),
+            uid: event.uid,
+            description: event.description,
+        }
+    }
+}
+
+/// Layout of one to-do (and its subtasks) in `todos.json`
+#[derive(Serialize, Deserialize)]
+pub struct TodoRecord {
+    /// Only top-level items have an ID
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub id: u32,
+    pub todo_name: String,
+    /// Letter A to E, missing for items without priority
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+    /// Files written before the priority scale only have this boolean, it is never written back
+    #[serde(default, skip_serializing)]
+    pub high_prio: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<String>,
+    /// %Y-%m-%d, optionally followed by the time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtasks: Vec<ToDo>,
+}
+
+impl TryFrom<TodoRecord> for ToDo {
+    type Error = String;
+
+    fn try_from(record: TodoRecord) -> Result<Self, Self::Error> {
+        let priority = match record.priority {
+            Some(priority) => {
+                Priority::from_input(&priority).ok_or(format!("invalid priority {:?}", priority))?
+            }
+            None if record.high_prio => Priority::A,
+            None => Priority::None,
+        };
+        let completed = record
+            .completed
+            .map(|completed| {
+                try_string_to_naive_date(&completed)
+                    .map_err(|_| format!("invalid completion date {:?}", completed))
+            })
+            .transpose()?;
+        let (due_date, due_time) = match record.due {
+            Some(due) => string_to_due_date(&due).ok_or(format!("invalid due date {:?}", due))?,
+            None => (None, None),
+        };
+
+        Ok(ToDo {
+            id: record.id,
+            priority,
+            todo_name: record.todo_name,
+            completed,
+            due_date,
+            due_time,
+            subtasks: record.subtasks,
+            tags: record.tags,
+            project: record.project,
+        })
+    }
+}
+
+impl From<ToDo> for TodoRecord {
+    fn from(todo: ToDo) -> Self {
+        let due = todo.due_date.map(|due_date| match todo.due_time {
+            Some(due_time) => format!("{} {}", due_date, due_time.format("%H:%M:%S")),
+            None => due_date.to_string(),
+        });
+        Self {
+            id: todo.id,
+            todo_name: todo.todo_name,
+            priority: todo.priority.to_letter().map(String::from),
+            high_prio: false,
+            completed: todo.completed.map(date_time_to_json),
+            due,
+            tags: todo.tags,
+            project: todo.project,
+            subtasks: todo.subtasks,
+        }
+    }
+}
+
+/// Fails for files written by a newer version of the application
+pub fn check_schema_version(version: u32, path: &str) -> Result<(), String> {
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "{} uses schema version {}, but only versions up to {} are supported",
+            path, version, SCHEMA_VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Converts the records of a list one at a time, so that the error names the malformed one,
+/// e.g. `event 3 ("Team Meeting") in assets/appointments.json: invalid date "2024-13-01"`
+pub fn records_from_json<T: DeserializeOwned>(
+    records: Vec<Value>,
+    kind: &str,
+    name_field: &str,
+    path: &str,
+) -> Result<Vec<T>, String> {
+    records
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let name = record[name_field].as_str().unwrap_or_default().to_string();
+            serde_json::from_value(record)
+                .map_err(|e| format!("{} {} ({:?}) in {}: {}", kind, i + 1, name, path, e))
+        })
+        .collect()
+}
+
+/// Formats a date-time the way it is stored in the files
+pub fn date_time_to_json(date_time: NaiveDateTime) -> String {
+    date_time.format(DATE_TIME_FORMAT).to_string()
+}
@@ -0,0 +1,326 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::NaiveDate;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    calendar_data::Events,
+    json_storage::JsonStorage,
+    schema::{check_schema_version, SCHEMA_VERSION},
+    storage::{event_day_range, Storage, StorageResult},
+    to_do_data::ToDo,
+};
+
+/// Tables of the database and their columns
+const TABLES: [(&str, &str); 2] = [
+    (
+        "events",
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,
+        first_day TEXT NOT NULL,
+        last_day TEXT NOT NULL,
+        data TEXT NOT NULL",
+    ),
+    (
+        "todos",
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,
+        due_day TEXT,
+        data TEXT NOT NULL",
+    ),
+];
+
+/// Keeps the appointments and to-dos in an embedded SQLite database
+/// Each row holds the record as it is stored in the JSON files, next to the indexed days
+/// used by the range queries, so only the rows of the requested days are read
+pub struct SqliteStorage {
+    connection: Connection,
+    path: PathBuf,
+}
+
+impl SqliteStorage {
+    /// Opens (or creates) the database at `path`
+    /// A new database is filled with the contents of the JSON files, if they exist
+    pub fn open(path: &Path, import_from: Option<&JsonStorage>) -> StorageResult<Self> {
+        let connection = Connection::open(path)
+            .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+        // The background sync writes through a connection of its own
+        connection.busy_timeout(Duration::from_secs(10))?;
+        let version: u32 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        check_schema_version(version, &path.display().to_string())?;
+
+        // AUTOINCREMENT keeps SQLite from handing out the ID of the newest row again once it
+        // was deleted; tables created before it was used are copied into new ones
+        for (table, columns) in TABLES {
+            let sql: Option<String> = connection
+                .query_row(
+                    "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                    [table],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match sql {
+                None => {
+                    connection.execute_batch(&format!("CREATE TABLE {} ({});", table, columns))?
+                }
+                Some(sql) if !sql.contains("AUTOINCREMENT") => {
+                    connection.execute_batch(&format!(
+                        "BEGIN;
+                        ALTER TABLE {table} RENAME TO {table}_old;
+                        CREATE TABLE {table} ({columns});
+                        INSERT INTO {table} SELECT * FROM {table}_old;
+                        DROP TABLE {table}_old;
+                        COMMIT;"
+                    ))?
+                }
+                Some(_) => {}
+            }
+        }
+        connection.execute_batch(
+            "CREATE INDEX IF NOT EXISTS events_days ON events (first_day, last_day);
+            CREATE INDEX IF NOT EXISTS todos_due_day ON todos (due_day);",
+        )?;
+        let mut storage = Self {
+            connection,
+            path: path.to_path_buf(),
+        };
+
+        if version == 0 {
+            // The version is only set along with the imported rows, so that a failed import is
+            // tried again on the next start rather than leaving an empty database behind
+            storage.connection.execute_batch("BEGIN")?;
+            let result = storage.initialize(import_from);
+            storage
+                .connection
+                .execute_batch(if result.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+            result?;
+        }
+        Ok(storage)
+    }
+
+    /// Fills a new database from the JSON files, if given, and sets its schema version
+    fn initialize(&mut self, import_from: Option<&JsonStorage>) -> StorageResult<()> {
+        if let Some(json) = import_from {
+            self.import_from_json(json)?;
+        }
+        self.connection
+            .pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(())
+    }
+
+    /// Copies the events and to-dos of the JSON files, keeping their IDs
+    fn import_from_json(&mut self, json: &JsonStorage) -> StorageResult<()> {
+        let mut json = json.clone();
+        if json.appointments_path.exists() {
+            for event in json.load_events()? {
+                self.insert_event(event)?;
+            }
+        }
+        if json.todos_path.exists() {
+            for todo in json.load_todos()? {
+                self.insert_todo(todo)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn query_events(&self, sql: &str, params: impl rusqlite::Params) -> StorageResult<Vec<Events>> {
+        let mut statement = self.connection.prepare(sql)?;
+        let rows = statement.query_map(params, |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut events = Vec::new();
+        for row in rows {
+            let (id, data) = row?;
+            let mut event: Events = serde_json::from_str(&data)
+                .map_err(|e| format!("Malformed event {} in the database: {}", id, e))?;
+            event.id = id;
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    fn query_todos(&self, sql: &str, params: impl rusqlite::Params) -> StorageResult<Vec<ToDo>> {
+        let mut statement = self.connection.prepare(sql)?;
+        let rows = statement.query_map(params, |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut todos = Vec::new();
+        for row in rows {
+            let (id, data) = row?;
+            let mut todo: ToDo = serde_json::from_str(&data)
+                .map_err(|e| format!("Malformed to-do {} in the database: {}", id, e))?;
+            todo.id = id;
+            todos.push(todo);
+        }
+        Ok(todos)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load_events(&mut self) -> StorageResult<Vec<Events>> {
+        self.query_events("SELECT id, data FROM events ORDER BY id", [])
+    }
+
+    fn events_between(&mut self, from: NaiveDate, to: NaiveDate) -> StorageResult<Vec<Events>> {
+        self.query_events(
+            "SELECT id, data FROM events WHERE first_day <= ?2 AND last_day >= ?1 ORDER BY id",
+            params![from.to_string(), to.to_string()],
+        )
+    }
+
+    fn insert_event(&mut self, event: Events) -> StorageResult<u32> {
+        let (first_day, last_day) = event_day_range(&event);
+        // Without an ID, SQLite picks one that was never used before
+        let id = (event.id != 0).then_some(event.id);
+        self.connection.execute(
+            "INSERT INTO events (id, first_day, last_day, data) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                id,
+                first_day.to_string(),
+                last_day.to_string(),
+                serde_json::to_string(&event)?
+            ],
+        )?;
+        Ok(self.connection.last_insert_rowid() as u32)
+    }
+
+    fn insert_events(&mut self, events: Vec<Events>) -> StorageResult<Vec<u32>> {
+        // All or none of the events are stored
+        self.connection.execute_batch("BEGIN")?;
+        let ids: StorageResult<Vec<u32>> = events
+            .into_iter()
+            .map(|event| self.insert_event(event))
+            .collect();
+        self.connection
+            .execute_batch(if ids.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+        ids
+    }
+
+    fn update_event(&mut self, event: &Events) -> StorageResult<()> {
+        let (first_day, last_day) = event_day_range(event);
+        let changed = self.connection.execute(
+            "UPDATE events SET first_day = ?2, last_day = ?3, data = ?4 WHERE id = ?1",
+            params![
+                event.id,
+                first_day.to_string(),
+                last_day.to_string(),
+                serde_json::to_string(event)?
+            ],
+        )?;
+        if changed == 0 {
+            return Err(format!("No event with id {}", event.id).into());
+        }
+        Ok(())
+    }
+
+    fn delete_event(&mut self, id: u32) -> StorageResult<Option<Events>> {
+        let deleted = self
+            .query_events("SELECT id, data FROM events WHERE id = ?1", [id])?
+            .pop();
+        self.connection
+            .execute("DELETE FROM events WHERE id = ?1", [id])?;
+        Ok(deleted)
+    }
+
+    fn load_todos(&mut self) -> StorageResult<Vec<ToDo>> {
+        self.query_todos("SELECT id, data FROM todos ORDER BY id", [])
+    }
+
+    fn todos_due_between(&mut self, from: NaiveDate, to: NaiveDate) -> StorageResult<Vec<ToDo>> {
+        self.query_todos(
+            "SELECT id, data FROM todos WHERE due_day BETWEEN ?1 AND ?2 ORDER BY id",
+            params![from.to_string(), to.to_string()],
+        )
+    }
+
+    fn insert_todo(&mut self, todo: ToDo) -> StorageResult<u32> {
+        let id = (todo.id != 0).then_some(todo.id);
+        self.connection.execute(
+            "INSERT INTO todos (id, due_day, data) VALUES (?1, ?2, ?3)",
+            params![
+                id,
+                todo.due_date.map(|d| d.to_string()),
+                serde_json::to_string(&todo)?
+            ],
+        )?;
+        Ok(self.connection.last_insert_rowid() as u32)
+    }
+
+    fn update_todo(&mut self, todo: &ToDo) -> StorageResult<()> {
+        let changed = self.connection.execute(
+            "UPDATE todos SET due_day = ?2, data = ?3 WHERE id = ?1",
+            params![
+                todo.id,
+                todo.due_date.map(|d| d.to_string()),
+                serde_json::to_string(todo)?
+            ],
+        )?;
+        if changed == 0 {
+            return Err(format!("No to-do with id {}", todo.id).into());
+        }
+        Ok(())
+    }
+
+    fn delete_todo(&mut self, id: u32) -> StorageResult<Option<ToDo>> {
+        let deleted = self
+            .query_todos("SELECT id, data FROM todos WHERE id = ?1", [id])?
+            .pop();
+        self.connection
+            .execute("DELETE FROM todos WHERE id = ?1", [id])?;
+        Ok(deleted)
+    }
+
+    fn watched_paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_do_data::Priority;
+
+    #[test]
+    fn ids_of_deleted_items_are_not_reused() {
+        let path = std::env::temp_dir().join(format!("sqlite-storage-{}.db", std::process::id()));
+        let todo = ToDo::new(Priority::A, "Report".to_string());
+        // Tables as created before AUTOINCREMENT was used, holding one to-do
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE events (id INTEGER PRIMARY KEY, first_day TEXT NOT NULL,
+                    last_day TEXT NOT NULL, data TEXT NOT NULL);
+                CREATE INDEX events_days ON events (first_day, last_day);
+                CREATE TABLE todos (id INTEGER PRIMARY KEY, due_day TEXT, data TEXT NOT NULL);
+                CREATE INDEX todos_due_day ON todos (due_day);
+                PRAGMA user_version = 2;",
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO todos (id, data) VALUES (1, ?1)",
+                [serde_json::to_string(&todo).unwrap()],
+            )
+            .unwrap();
+        drop(connection);
+
+        let mut storage = SqliteStorage::open(&path, None).unwrap();
+        assert_eq!(storage.load_todos().unwrap().len(), 1);
+        assert_eq!(storage.insert_todo(todo.clone()).unwrap(), 2);
+        storage.delete_todo(2).unwrap();
+        assert_eq!(storage.insert_todo(todo).unwrap(), 3);
+
+        let date = NaiveDate::from_ymd_opt(2024, 9, 2)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap();
+        let event = Events::new(date, "Standup".to_string(), String::new());
+        assert_eq!(storage.insert_event(event.clone()).unwrap(), 1);
+        storage.delete_event(1).unwrap();
+        assert_eq!(storage.insert_event(event).unwrap(), 2);
+        drop(storage);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    path::PathBuf,
+};
+
+use chrono::{Days, NaiveDate};
+
+use crate::{
+    backup::Backup, calendar_data::Events, json_storage::JsonStorage, paths::AppPaths,
+    sqlite_storage::SqliteStorage, to_do_data::ToDo, todo_txt::TodoTxtStorage,
+};
+
+pub type StorageResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Environment variable selecting the storage backend, `json` (the default) or `sqlite`
+pub const STORAGE_ENV: &str = "RUSTY_CALENDAR_STORAGE";
+
+/// Where the appointments and to-dos are kept
+/// Events and to-dos are identified by their `id`, which the storage assigns on insert
+pub trait Storage {
+    fn load_events(&mut self) -> StorageResult<Vec<Events>>;
+
+    /// Events that may have an occurrence between the two dates (inclusive)
+    /// Recurring series are returned as a whole, `Events::occurs_on` tells the actual days
+    fn events_between(&mut self, from: NaiveDate, to: NaiveDate) -> StorageResult<Vec<Events>>;
+
+    /// Stores a new event and returns its ID
+    fn insert_event(&mut self, event: Events) -> StorageResult<u32>;
+
+    /// Stores several new events at once, e.g. when importing, and returns their IDs
+    fn insert_events(&mut self, events: Vec<Events>) -> StorageResult<Vec<u32>> {
+        events
+            .into_iter()
+            .map(|event| self.insert_event(event))
+            .collect()
+    }
+
+    fn update_event(&mut self, event: &Events) -> StorageResult<()>;
+
+    fn delete_event(&mut self, id: u32) -> StorageResult<Option<Events>>;
+
+    fn load_todos(&mut self) -> StorageResult<Vec<ToDo>>;
+
+    /// To-dos due between the two dates (inclusive)
+    fn todos_due_between(&mut self, from: NaiveDate, to: NaiveDate) -> StorageResult<Vec<ToDo>>;
+
+    /// Stores a new to-do and returns its ID
+    fn insert_todo(&mut self, todo: ToDo) -> StorageResult<u32>;
+
+    fn update_todo(&mut self, todo: &ToDo) -> StorageResult<()>;
+
+    fn delete_todo(&mut self, id: u32) -> StorageResult<Option<ToDo>>;
+
+    /// Files holding the data, checked for changes made by other programs
+    fn watched_paths(&self) -> Vec<PathBuf>;
+
+    /// Copies of the data taken before it was overwritten, newest first
+    fn backups(&self) -> StorageResult<Vec<Backup>> {
+        Err("This storage does not keep backups".into())
+    }
+
+    fn restore_backup(&mut self, _backup: &Backup) -> StorageResult<()> {
+        Err("This storage does not keep backups".into())
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+    #[default]
+    Json,
+    Sqlite,
+}
+
+impl StorageKind {
+    pub fn from_input(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Some(StorageKind::Json),
+            "sqlite" | "sqlite3" | "db" => Some(StorageKind::Sqlite),
+            _ => None,
+        }
+    }
+
+    /// Reads the backend from `RUSTY_CALENDAR_STORAGE`, `default` if it is not set
+    pub fn from_env_or(default: Self) -> StorageResult<Self> {
+        match std::env::var(STORAGE_ENV) {
+            Ok(kind) => Self::from_input(&kind).ok_or(
+                format!(
+                    "Unknown storage {:?} in {}, use json or sqlite",
+                    kind, STORAGE_ENV
+                )
+                .into(),
+            ),
+            Err(_) => Ok(default),
+        }
+    }
+}
+
+/// Advisory lock on the data files, held for a whole sync and around each command, so that
+/// the sync and the app (or a command run meanwhile) never read and write them at the same time
+/// It is released when dropped
+pub struct StorageLock {
+    _file: File,
+}
+
+impl StorageLock {
+    /// Waits until no one else holds the lock
+    pub fn acquire(paths: &AppPaths) -> StorageResult<Self> {
+        fs::create_dir_all(&paths.data_dir)
+            .map_err(|e| format!("Could not create {}: {}", paths.data_dir.display(), e))?;
+        let path = paths.lock_file();
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+        file.lock()
+            .map_err(|e| format!("Could not lock {}: {}", path.display(), e))?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// Opens the configured storage backend in the data directory, creating empty stores on the first run
+/// With a todo.txt file configured, the to-dos are kept in it instead
+pub fn open_storage(kind: StorageKind, paths: &AppPaths) -> StorageResult<Box<dyn Storage>> {
+    fs::create_dir_all(&paths.data_dir)
+        .map_err(|e| format!("Could not create {}: {}", paths.data_dir.display(), e))?;
+    let json = JsonStorage::from_paths(paths);
+    let storage: Box<dyn Storage> = match kind {
+        StorageKind::Json => {
+            json.create_missing_files()?;
+            Box::new(json)
+        }
+        // A new database is filled from the JSON files, if there are any
+        StorageKind::Sqlite => Box::new(SqliteStorage::open(&paths.database(), Some(&json))?),
+    };
+    Ok(match &paths.todo_txt {
+        Some(todo_txt) => Box::new(TodoTxtStorage::open(todo_txt, storage)?),
+        None => storage,
+    })
+}
+
+/// Days between which an event (or recurring series) may have an occurrence, used for the range queries
+/// One day of margin is kept on both sides, since events in another time zone may move to the next
+/// or previous day once converted to local time
+pub fn event_day_range(event: &Events) -> (NaiveDate, NaiveDate) {
+    let first_day = event.date.date() - Days::new(1);
+    let last_day = match &event.recurrence {
+        Some(recurrence) => match recurrence.until {
+            Some(until) => until.date() + Days::new(event.span_days()),
+            // Kept as a date that still sorts correctly as text
+            None => NaiveDate::from_ymd_opt(9999, 12, 31).unwrap(),
+        },
+        None => event.last_day() + Days::new(1),
+    };
+    (first_day, last_day)
+}
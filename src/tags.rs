@@ -0,0 +1,66 @@
+/// Splits the `#tag` and `+project` tokens off a title, e.g. `Stand-up #work #team +backend`
+/// Returns the title without them, the tags and the project (the last one given wins)
+pub fn split_tags(s: &str) -> (String, Vec<String>, Option<String>) {
+    let mut title_words = Vec::new();
+    let mut tags = Vec::new();
+    let mut project = None;
+
+    for word in s.split_whitespace() {
+        if let Some(tag) = word.strip_prefix('#').filter(|t| !t.is_empty()) {
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        } else if let Some(name) = word.strip_prefix('+').filter(|p| !p.is_empty()) {
+            project = Some(name.to_string());
+        } else {
+            title_words.push(word);
+        }
+    }
+
+    (title_words.join(" "), tags, project)
+}
+
+/// Tags and project as shown in the panes, e.g. `#work #team +backend`
+pub fn tags_text(tags: &[String], project: Option<&str>) -> String {
+    let mut words: Vec<String> = tags.iter().map(|t| format!("#{}", t)).collect();
+    if let Some(project) = project {
+        words.push(format!("+{}", project));
+    }
+    words.join(" ")
+}
+
+/// Restricts the panes to the items carrying all the given tags (and the project, if given)
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub tags: Vec<String>,
+    pub project: Option<String>,
+}
+
+impl Filter {
+    /// Reads the tokens after the `filter` command, e.g. `#work +backend`
+    /// Without any tokens (or with `off`) the filter is cleared
+    pub fn from_input(s: &str) -> Self {
+        let (_, tags, project) = split_tags(s);
+        Self { tags, project }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.project.is_none()
+    }
+
+    pub fn matches(&self, tags: &[String], project: Option<&str>) -> bool {
+        let tags_match = self
+            .tags
+            .iter()
+            .all(|tag| tags.iter().any(|t| t.eq_ignore_ascii_case(tag)));
+        let project_match = match &self.project {
+            Some(filter_project) => project.is_some_and(|p| p.eq_ignore_ascii_case(filter_project)),
+            None => true,
+        };
+        tags_match && project_match
+    }
+
+    pub fn describe(&self) -> String {
+        tags_text(&self.tags, self.project.as_deref())
+    }
+}
@@ -1,21 +1,155 @@
-use std::{
-    fs::{self, OpenOptions},
-    io::Write,
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Formats,
+    schema::TodoRecord,
+    storage::{Storage, StorageResult},
+    tags::{tags_text, Filter},
 };
 
-#[derive(Default, Debug, Clone)]
+/// Priority of a to-do, from A (highest) to E (lowest), or none at all
+/// The derived order sorts the highest priority first
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    A,
+    B,
+    C,
+    D,
+    E,
+    #[default]
+    None,
+}
+
+impl Priority {
+    /// All the levels, from the highest to none
+    pub fn all() -> [Priority; 6] {
+        [
+            Priority::A,
+            Priority::B,
+            Priority::C,
+            Priority::D,
+            Priority::E,
+            Priority::None,
+        ]
+    }
+
+    /// Accepts the letters A–E, the numbers 1–5 and `none`
+    /// `true` and `false` of the old boolean priority map onto A and none
+    pub fn from_input(s: &str) -> Option<Self> {
+        match s.trim().to_uppercase().as_str() {
+            "A" | "1" | "TRUE" => Some(Priority::A),
+            "B" | "2" => Some(Priority::B),
+            "C" | "3" => Some(Priority::C),
+            "D" | "4" => Some(Priority::D),
+            "E" | "5" => Some(Priority::E),
+            "NONE" | "" | "FALSE" => Some(Priority::None),
+            _ => None,
+        }
+    }
+
+    pub fn to_letter(&self) -> Option<char> {
+        match self {
+            Priority::A => Some('A'),
+            Priority::B => Some('B'),
+            Priority::C => Some('C'),
+            Priority::D => Some('D'),
+            Priority::E => Some('E'),
+            Priority::None => None,
+        }
+    }
+
+    /// Title of the section of the To-Do pane listing this level
+    pub fn section_title(&self) -> String {
+        match self.to_letter() {
+            Some(letter) => format!("Priority {} items:  ", letter),
+            None => String::from("Items without priority:  "),
+        }
+    }
+}
+
+/// Stored through `TodoRecord`, which checks the priority and dates when loading
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "TodoRecord", into = "TodoRecord")]
 pub struct ToDo {
-    pub high_prio: bool,
+    /// Stable identifier of a top-level item, 0 until the item is stored (and for subtasks)
+    pub id: u32,
+    pub priority: Priority,
     pub todo_name: String,
+    /// When the item was marked as done, `None` while it is still open
+    pub completed: Option<NaiveDateTime>,
+    pub due_date: Option<NaiveDate>,
+    /// Optional time on the due date, without it the item is due by the end of the day
+    pub due_time: Option<NaiveTime>,
+    /// Checklist of smaller items, which may have subtasks of their own
+    pub subtasks: Vec<ToDo>,
+    pub tags: Vec<String>,
+    pub project: Option<String>,
 }
 
 impl ToDo {
-    pub fn new(high_prio: bool, todo_name: String) -> Self {
+    pub fn new(priority: Priority, todo_name: String) -> Self {
         Self {
-            high_prio,
+            id: 0,
+            priority,
             todo_name,
+            completed: None,
+            due_date: None,
+            due_time: None,
+            subtasks: Vec::new(),
+            tags: Vec::new(),
+            project: None,
+        }
+    }
+
+    /// Completed and total number of direct subtasks, `None` if there are no subtasks
+    pub fn get_progress(&self) -> Option<(usize, usize)> {
+        if self.subtasks.is_empty() {
+            return None;
         }
+        let done = self.subtasks.iter().filter(|t| t.is_done()).count();
+        Some((done, self.subtasks.len()))
     }
+
+    pub fn is_done(&self) -> bool {
+        self.completed.is_some()
+    }
+
+    /// The moment the item is due, the end of the due date if no time is given
+    pub fn get_due_date_time(&self) -> Option<NaiveDateTime> {
+        let due_time = self
+            .due_time
+            .unwrap_or(NaiveTime::from_hms_opt(23, 59, 59).unwrap());
+        self.due_date.map(|d| d.and_time(due_time))
+    }
+
+    pub fn is_overdue(&self, now: NaiveDateTime) -> bool {
+        !self.is_done() && self.get_due_date_time().is_some_and(|due| due < now)
+    }
+
+    /// Due date shown in the panes, e.g. "2024-09-14" or "2024-09-14 17:00"
+    pub fn due_text(&self, formats: &Formats) -> Option<String> {
+        let due_date = self.due_date?.format(&formats.date);
+        Some(match self.due_time {
+            Some(due_time) => format!("{} {}", due_date, due_time.format(&formats.time)),
+            None => due_date.to_string(),
+        })
+    }
+}
+
+/// How a line of the To-Do pane is highlighted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TodoLineKind {
+    Title(Priority),
+    Item(Priority),
+    Overdue,
+    Completed,
+    /// Changes made on disk while a command was being typed
+    Conflict,
+    /// Answer to a command, such as the list of backups
+    Notice,
+    Empty,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -34,120 +168,287 @@ impl ToDoList {
         self.all_todos.clone()
     }
 
-    pub fn add_todo_to_list(&mut self, todo_item: ToDo) {
+    /// Next free to-do ID, IDs start at 1
+    pub fn next_todo_id(&self) -> u32 {
+        self.all_todos.iter().map(|t| t.id).max().unwrap_or(0) + 1
+    }
+
+    pub fn add_todo_to_list(&mut self, mut todo_item: ToDo) {
+        if todo_item.id == 0 {
+            todo_item.id = self.next_todo_id();
+        }
         self.all_todos.push(todo_item);
     }
 
+    /// Open items grouped by priority, in the order of `Priority::all`; completed items are left out
+    /// Within each priority the items are sorted by due date, items without one come last
     pub fn get_todos_sorted_by_prio(&self) -> Vec<Vec<ToDo>> {
-        let mut high_prio_todos = Vec::new();
-        let mut low_prio_todos = Vec::new();
+        let mut sorted_todos = vec![Vec::new(); Priority::all().len()];
 
-        for items in self.all_todos.iter() {
-            if items.high_prio {
-                high_prio_todos.push(items.clone());
-            } else {
-                low_prio_todos.push(items.clone())
+        for index in self.get_todo_display_order() {
+            let items = &self.all_todos[index];
+            if items.is_done() {
+                continue;
             }
+            sorted_todos[items.priority as usize].push(items.clone());
         }
 
-        vec![high_prio_todos, low_prio_todos]
+        sorted_todos
     }
 
-    pub fn generate_todo_text(&mut self) -> String {
-        let mut todo_text = String::new();
+    pub fn get_completed_todos(&self) -> Vec<ToDo> {
+        self.all_todos
+            .iter()
+            .filter(|t| t.is_done())
+            .cloned()
+            .collect()
+    }
 
-        self.add_todos_from_json();
+    /// Open items that are due on the given day
+    pub fn get_todos_due_on(&self, date: NaiveDate) -> Vec<ToDo> {
+        let mut due_todos: Vec<ToDo> = self
+            .all_todos
+            .iter()
+            .filter(|t| !t.is_done() && t.due_date == Some(date))
+            .cloned()
+            .collect();
+        due_todos.sort_by_key(|t| t.get_due_date_time());
+        due_todos
+    }
 
-        let sorted_todos = self.get_todos_sorted_by_prio();
-        let high_prio_list = &sorted_todos[0];
-        let low_prio_list = &sorted_todos[1];
-
-        if !high_prio_list.is_empty() {
-            let high_prio_title =
-                String::from("High priority items:  ") + &high_prio_list.len().to_string();
-            todo_text.push_str(&high_prio_title);
-            todo_text.push('\n');
-            todo_text.push('\n');
-            for (count, items) in high_prio_list.iter().enumerate() {
-                todo_text.push_str(&(count.to_string() + &String::from(". ")));
-                todo_text.push_str(&items.todo_name);
-                todo_text.push('\n');
+    /// Indices into `all_todos` in the order the items are numbered in the To-Do pane:
+    /// by priority (and by due date within it) and finally the completed items
+    pub fn get_todo_display_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.all_todos.len()).collect();
+        // The sort is stable, so items without a due date keep the order they were added in
+        order.sort_by_key(|&i| {
+            let todo = &self.all_todos[i];
+            if todo.is_done() {
+                return (true, Priority::None, false, None);
             }
-            todo_text.push('\n');
-            todo_text.push('\n');
-            todo_text.push('\n');
+            let due = todo.get_due_date_time();
+            (false, todo.priority, due.is_none(), due)
+        });
+        order
+    }
+
+    /// Index into `all_todos` of the item shown with the given number in the To-Do pane
+    pub fn get_todo_index_by_number(&self, number: usize) -> Option<usize> {
+        self.get_todo_display_order().get(number).copied()
+    }
+
+    pub fn remove_todo_from_list(&mut self, index: usize) -> ToDo {
+        self.all_todos.remove(index)
+    }
+
+    /// Finds the item shown with the given number in the To-Do pane, e.g. `3`, or `3.1` for the
+    /// first subtask of item 3. Returns the index into `all_todos` and the (0-based) subtask indices
+    pub fn get_todo_path_by_number(&self, number: &str) -> Option<(usize, Vec<usize>)> {
+        let mut parts = number.trim().split('.');
+        let index = self.get_todo_index_by_number(parts.next()?.parse().ok()?)?;
+
+        let mut subtask_path = Vec::new();
+        let mut todo = &self.all_todos[index];
+        for part in parts {
+            let subtask_index = part.parse::<usize>().ok()?.checked_sub(1)?;
+            todo = todo.subtasks.get(subtask_index)?;
+            subtask_path.push(subtask_index);
+        }
+        Some((index, subtask_path))
+    }
+
+    pub fn get_todo_by_path_mut(
+        &mut self,
+        index: usize,
+        subtask_path: &[usize],
+    ) -> Option<&mut ToDo> {
+        let mut todo = self.all_todos.get_mut(index)?;
+        for &subtask_index in subtask_path {
+            todo = todo.subtasks.get_mut(subtask_index)?;
         }
+        Some(todo)
+    }
+
+    pub fn remove_todo_by_path(&mut self, index: usize, subtask_path: &[usize]) -> Option<ToDo> {
+        match subtask_path.split_last() {
+            Some((&last, parent_path)) => {
+                let parent = self.get_todo_by_path_mut(index, parent_path)?;
+                (last < parent.subtasks.len()).then(|| parent.subtasks.remove(last))
+            }
+            None => (index < self.all_todos.len()).then(|| self.remove_todo_from_list(index)),
+        }
+    }
+
+    /// Lines of the To-Do pane together with how each of them is highlighted
+    /// Only the items matching the filter are shown, but they keep their number
+    pub fn generate_todo_lines(
+        &self,
+        filter: &Filter,
+        formats: &Formats,
+    ) -> Vec<(TodoLineKind, String)> {
+        let mut todo_lines = Vec::new();
+
+        let now = Local::now().naive_local();
+        let sorted_todos = self.get_todos_sorted_by_prio();
+        let completed_list = self.get_completed_todos();
+        let mut sections: Vec<(String, Priority, &Vec<ToDo>)> = Priority::all()
+            .into_iter()
+            .zip(sorted_todos.iter())
+            .map(|(priority, list)| (priority.section_title(), priority, list))
+            .collect();
+        sections.push((
+            String::from("Completed items:  "),
+            Priority::None,
+            &completed_list,
+        ));
+
+        // The numbering continues across the sections, so that each number refers to one item
+        let mut count = 0;
 
-        if !low_prio_list.is_empty() {
-            let low_prio_title =
-                String::from("Low priority items:  ") + &low_prio_list.len().to_string();
-            todo_text.push_str(&low_prio_title);
-            todo_text.push('\n');
-            todo_text.push('\n');
-            for (count, items) in low_prio_list.iter().enumerate() {
-                todo_text.push_str(&(count.to_string() + &String::from(". ")));
-                todo_text.push_str(&items.todo_name);
-                todo_text.push('\n');
+        for (title, priority, list) in sections {
+            let shown = list
+                .iter()
+                .filter(|t| filter.matches(&t.tags, t.project.as_deref()))
+                .count();
+            if shown == 0 {
+                count += list.len();
+                continue;
+            }
+            if !todo_lines.is_empty() {
+                todo_lines.push((TodoLineKind::Empty, String::new()));
+                todo_lines.push((TodoLineKind::Empty, String::new()));
+            }
+            todo_lines.push((TodoLineKind::Title(priority), title + &shown.to_string()));
+            todo_lines.push((TodoLineKind::Empty, String::new()));
+
+            for items in list.iter() {
+                if !filter.matches(&items.tags, items.project.as_deref()) {
+                    count += 1;
+                    continue;
+                }
+                let mut line = count.to_string() + &String::from(". ") + &items.todo_name;
+                let kind = if let Some(completed) = items.completed {
+                    // Completed items are collapsed into a short list at the end
+                    line = count.to_string() + &String::from(". ✓ ") + &items.todo_name;
+                    line.push_str(&format!(" ({})", completed.format(&formats.date_time())));
+                    TodoLineKind::Completed
+                } else if items.is_overdue(now) {
+                    line.push_str(&format!(" (overdue {})", items.due_text(formats).unwrap()));
+                    TodoLineKind::Overdue
+                } else {
+                    if let Some(due_text) = items.due_text(formats) {
+                        line.push_str(&format!(" (due {})", due_text));
+                    }
+                    TodoLineKind::Item(items.priority)
+                };
+                if let Some((done, total)) = items.get_progress() {
+                    line.push_str(&format!(" [{}/{}]", done, total));
+                }
+                if !items.tags.is_empty() || items.project.is_some() {
+                    line.push(' ');
+                    line.push_str(&tags_text(&items.tags, items.project.as_deref()));
+                }
+                todo_lines.push((kind, line));
+                push_subtask_lines(&mut todo_lines, items, &count.to_string(), 1);
+                count += 1;
             }
         }
 
-        if todo_text.is_empty() {
-            todo_text.push_str("You do not have any to-do items!")
+        if todo_lines.is_empty() && filter.is_empty() {
+            todo_lines.push((
+                TodoLineKind::Empty,
+                String::from("You do not have any to-do items!"),
+            ));
+        } else if todo_lines.is_empty() {
+            todo_lines.push((
+                TodoLineKind::Empty,
+                format!(
+                    "You do not have any to-do items matching {}!",
+                    filter.describe()
+                ),
+            ));
         }
 
-        todo_text
+        todo_lines
     }
 
-    pub fn add_todos_from_json(&mut self) {
-        let todo_path = "assets/todos.json";
-        let data = fs::read_to_string(todo_path).expect("Could not open file");
-        let todo_json: serde_json::Value =
-            serde_json::from_str(&data).expect("Serde error in reading data from JSON");
+    pub fn generate_todo_text(&self, filter: &Filter, formats: &Formats) -> String {
+        let todo_lines: Vec<String> = self
+            .generate_todo_lines(filter, formats)
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect();
+        todo_lines.join("\n")
+    }
 
-        // Manually add each element to the struct
-        let all_todos_json = todo_json["all_todos"]
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|todo_item| ToDo {
-                high_prio: todo_item["high_prio"].as_bool().unwrap(),
-                todo_name: todo_item["todo_name"].as_str().unwrap().to_string(),
-            })
-            .collect::<Vec<ToDo>>();
-
-        self.all_todos.clear();
-        self.all_todos = all_todos_json;
-    }
-
-    pub fn add_back_todos_to_json(&self) {
-        // Manually construct the updated JSON string
-        let mut updated_data = String::new();
-        updated_data.push_str("{\n    \"all_todos\": [\n");
-
-        for (i, todo) in self.all_todos.iter().enumerate() {
-            updated_data.push_str("        {\n");
-            updated_data.push_str(&format!("            \"high_prio\": {},\n", todo.high_prio));
-            updated_data.push_str(&format!(
-                "            \"todo_name\": \"{}\"\n",
-                todo.todo_name
-            ));
-            updated_data.push_str("        }");
+    /// Lines listing the to-dos due on the given day, for the appointment pane
+    pub fn generate_due_todo_text(
+        &self,
+        date: NaiveDate,
+        filter: &Filter,
+        formats: &Formats,
+    ) -> String {
+        let mut due_text = String::new();
 
-            if i < self.all_todos.len() - 1 {
-                updated_data.push(',');
+        for items in self.get_todos_due_on(date) {
+            if !filter.matches(&items.tags, items.project.as_deref()) {
+                continue;
             }
-
-            updated_data.push('\n');
+            due_text.push_str(&(String::from("- To-do: ") + &items.todo_name));
+            due_text.push('\n');
+            if let Some(due_time) = items.due_time {
+                due_text.push_str(&format!("Due: {}", due_time.format(&formats.time)));
+            } else {
+                due_text.push_str("Due: today");
+            }
+            due_text.push('\n');
+            due_text.push('\n');
         }
 
-        updated_data.push_str("    ]\n}");
+        due_text
+    }
+
+    pub fn load_todos(&mut self, storage: &mut dyn Storage) -> StorageResult<()> {
+        self.all_todos = storage.load_todos()?;
+        Ok(())
+    }
+}
+
+/// Adds the subtasks of an item, indented under it and numbered like `3.1`, `3.2`
+fn push_subtask_lines(
+    todo_lines: &mut Vec<(TodoLineKind, String)>,
+    todo: &ToDo,
+    number: &str,
+    depth: usize,
+) {
+    for (i, subtask) in todo.subtasks.iter().enumerate() {
+        let subtask_number = format!("{}.{}", number, i + 1);
+        // Leading spaces are trimmed while rendering the frame, so non-breaking spaces are used instead
+        let mut line = "\u{00A0}".repeat(4 * depth) + &subtask_number + " ";
+        let kind = if subtask.is_done() {
+            line.push_str("✓ ");
+            TodoLineKind::Completed
+        } else {
+            TodoLineKind::Item(todo.priority)
+        };
+        line.push_str(&subtask.todo_name);
+        if let Some((done, total)) = subtask.get_progress() {
+            line.push_str(&format!(" [{}/{}]", done, total));
+        }
+        todo_lines.push((kind, line));
+        push_subtask_lines(todo_lines, subtask, &subtask_number, depth + 1);
+    }
+}
 
-        // Write the updated JSON back to the file
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open("assets/todos.json")
-            .unwrap();
-        file.write_all(updated_data.as_bytes()).unwrap();
+/// Converts a due date string into a date and an optional time
+/// Supported inputs are %Y-%m-%d, %Y-%m-%d %H:%M and %Y-%m-%d %H:%M:%S
+pub fn string_to_due_date(s: &str) -> Option<(Option<NaiveDate>, Option<NaiveTime>)> {
+    let s = s.trim().replace("T", " ");
+    if let Ok(due_date) = NaiveDate::parse_from_str(&s, "%Y-%m-%d") {
+        return Some((Some(due_date), None));
     }
+    let due = NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M"))
+        .ok()?;
+    Some((Some(due.date()), Some(due.time())))
 }
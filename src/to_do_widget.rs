@@ -1,91 +1,320 @@
-use std::rc::Rc;
+use std::{path::Path, rc::Rc};
 
 use chrono::{Local, NaiveDateTime};
 use ratatui::{
     crossterm::event::{self, Event, KeyCode},
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Stylize},
+    text::{Line, Text},
     widgets::{Block, Borders, Padding, Paragraph, Wrap},
     Frame,
 };
 use tui_textarea::TextArea;
 
 use crate::{
-    calendar_data::{string_to_naive_date, Calendar, Events},
-    to_do_data::{ToDo, ToDoList},
+    app_state::AppState,
+    backup::write_atomic,
+    caldav::SyncWorker,
+    calendar_data::{
+        parse_event_start, split_time_zone, string_to_end_date, try_string_to_naive_date, Calendar,
+        Events,
+    },
+    calendar_widget::get_pane_padding,
+    config::{expand_path, Config, Theme},
+    csv::{export_csv, import_csv_file, CsvOptions},
+    feeds::FeedWorker,
+    ical::{export_ics, import_ics_file, ExportSelection},
+    recurrence::Recurrence,
+    storage::{Storage, StorageLock, StorageResult},
+    tags::{split_tags, Filter},
+    to_do_data::{string_to_due_date, Priority, ToDo, ToDoList, TodoLineKind},
 };
 
-fn get_todo_title_block() -> Block<'static> {
+fn get_todo_title_block(filter: &Filter, color: Color) -> Block<'static> {
+    let title = if filter.is_empty() {
+        " To-Do ".to_string()
+    } else {
+        format!(" To-Do - {} ", filter.describe())
+    };
     Block::default()
         .borders(Borders::ALL)
-        .fg(Color::Blue)
+        .fg(color)
         .add_modifier(Modifier::BOLD)
-        .title(" To-Do ".to_string())
+        .title(title)
 }
 
-fn get_todo_user_input_block() -> Block<'static> {
-    Block::default().borders(Borders::ALL).fg(Color::DarkGray)
+fn get_todo_user_input_block(
+    status: Option<&str>,
+    sync_status: Option<&str>,
+    color: Color,
+) -> Block<'static> {
+    let mut block = Block::default().borders(Borders::ALL).fg(color);
+    if let Some(status) = status {
+        block = block.title_bottom(format!(" {} ", status));
+    }
+    if let Some(sync_status) = sync_status {
+        block = block.title_bottom(Line::from(format!(" {} ", sync_status)).right_aligned());
+    }
+    block
+}
+
+fn get_priority_color(priority: Priority) -> Color {
+    match priority {
+        Priority::A => Color::LightMagenta,
+        Priority::B => Color::LightYellow,
+        Priority::C => Color::LightGreen,
+        Priority::D => Color::LightCyan,
+        Priority::E => Color::Gray,
+        Priority::None => Color::Blue,
+    }
 }
 
-fn get_todo_list_text(todo_list_text: String) -> Paragraph<'static> {
-    Paragraph::new(todo_list_text)
-        .fg(Color::Blue)
-        .block(Block::new().padding(Padding::new(5, 2, 2, 2)))
+fn get_todo_list_text(
+    todo_lines: Vec<(TodoLineKind, String)>,
+    theme: &Theme,
+    padding: Padding,
+) -> Paragraph<'static> {
+    // Each priority has its own colour, overdue items are highlighted in red
+    // and completed items are struck through
+    let lines: Vec<Line> = todo_lines
+        .into_iter()
+        .map(|(kind, line)| match kind {
+            TodoLineKind::Title(priority) => Line::from(line)
+                .fg(get_priority_color(priority))
+                .add_modifier(Modifier::BOLD),
+            TodoLineKind::Item(priority) => Line::from(line).fg(get_priority_color(priority)),
+            TodoLineKind::Overdue => Line::from(line).fg(theme.overdue),
+            TodoLineKind::Completed => Line::from(line)
+                .fg(theme.completed)
+                .add_modifier(Modifier::CROSSED_OUT),
+            TodoLineKind::Conflict => Line::from(line).fg(Color::Yellow),
+            TodoLineKind::Notice => Line::from(line).fg(Color::Cyan),
+            _ => Line::from(line),
+        })
+        .collect();
+    Paragraph::new(Text::from(lines))
+        .fg(theme.todo)
+        .block(Block::new().padding(padding))
         .alignment(Alignment::Left)
         .wrap(Wrap { trim: true })
 }
 
-fn write_user_input_to_json(
+/// Files ending in `.csv` are read and written as CSV, the others as iCalendar
+fn is_csv(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_user_input_to_storage(
     input_todo_content: String,
-    todolist: &mut Option<ToDoList>,
-    calendar_list: &mut Option<Calendar>,
+    todolist: &mut ToDoList,
+    calendar: &mut Calendar,
     calendar_date: &mut NaiveDateTime,
-) {
+    filter: &mut Filter,
+    storage: &mut dyn Storage,
+    notice: &mut Option<Vec<String>>,
+    sync: Option<&SyncWorker>,
+    feeds: Option<&FeedWorker>,
+    config: &Config,
+) -> StorageResult<()> {
     let parts_input: Vec<String> = input_todo_content
         .split(',')
         .map(|s| s.trim().to_string())
         .collect();
 
+    // Restrict both panes to the given tags and project, `filter` on its own clears it
+    let mut words = input_todo_content.trim().splitn(2, char::is_whitespace);
+    if words.next().unwrap_or_default().to_lowercase() == "filter" {
+        *filter = Filter::from_input(words.next().unwrap_or_default());
+        return Ok(());
+    }
+
+    // `restore` lists the backups of the data files, `restore <n>` rolls back to one of them
+    let mut words = input_todo_content.split_whitespace();
+    if words.next().unwrap_or_default().to_lowercase() == "restore" {
+        *notice = Some(restore_from_input(words.next(), storage)?);
+        return Ok(());
+    }
+
+    // `import <file.ics>` adds the appointments of an iCalendar file,
+    // `import <file.csv>` the appointments or to-dos of a spreadsheet
+    let mut words = input_todo_content.trim().splitn(2, char::is_whitespace);
+    if words.next().unwrap_or_default().to_lowercase() == "import" {
+        let path = words.next().unwrap_or_default().trim();
+        if path.is_empty() {
+            return Err("import needs the path of an .ics or .csv file".into());
+        }
+        let path = expand_path(path, Path::new("."));
+        *notice = Some(if is_csv(&path) {
+            let options = CsvOptions {
+                date_formats: config.csv_date_formats.clone(),
+                ..CsvOptions::default()
+            };
+            import_csv_file(&path, storage, &options, false)?.report(&config.formats)
+        } else {
+            import_ics_file(&path, storage)?.report()
+        });
+        return Ok(());
+    }
+
+    // `export <file.ics>` (or .csv) writes what the panes show, with the current filter
+    let mut words = input_todo_content.trim().splitn(2, char::is_whitespace);
+    if words.next().unwrap_or_default().to_lowercase() == "export" {
+        let path = words.next().unwrap_or_default().trim();
+        if path.is_empty() {
+            return Err("export needs the path of the .ics or .csv file to write".into());
+        }
+        let path = expand_path(path, Path::new("."));
+        let selection = ExportSelection {
+            filter: filter.clone(),
+            ..ExportSelection::default()
+        };
+        let (text, events, todos) = if is_csv(&path) {
+            let date_format = config
+                .csv_date_formats
+                .first()
+                .map_or("%Y-%m-%d", String::as_str);
+            let export = export_csv(storage, &selection, None, date_format)?;
+            (export.text, export.events, export.todos)
+        } else {
+            let export = export_ics(storage, &selection)?;
+            (export.text, export.events, export.todos)
+        };
+        write_atomic(&path, text.as_bytes())?;
+        *notice = Some(vec![format!(
+            "Exported {} event(s) and {} to-do(s) to {}",
+            events,
+            todos,
+            path.display()
+        )]);
+        return Ok(());
+    }
+
+    // `sync` syncs with the CalDAV server now, rather than at the next interval
+    if input_todo_content.trim().to_lowercase() == "sync" {
+        let sync = sync.ok_or("sync needs a [caldav] section in the configuration")?;
+        sync.request_sync();
+        return Ok(());
+    }
+
+    // `refresh` downloads the subscribed feeds now
+    if input_todo_content.trim().to_lowercase() == "refresh" {
+        let feeds = feeds.ok_or("refresh needs [[feeds]] in the configuration")?;
+        feeds.request_refresh();
+        return Ok(());
+    }
+
+    // Mark to-dos done, undo, remove or edit them by their number in the To-Do pane
+    if update_todo_from_input(&input_todo_content, todolist, storage)? {
+        return Ok(());
+    }
+
+    // Delete or edit an appointment, by ID or by date and position in the appointment pane
+    let command = parts_input[0].trim().to_lowercase();
+    if command == "del" || command == "delete" || command == "edit" {
+        calendar.load_events(storage)?;
+        let changed_id = if command == "edit" {
+            edit_event_from_input(&parts_input, calendar)?
+        } else {
+            delete_event_from_input(&parts_input, calendar)?
+        };
+        // Only the changed event is written back
+        match calendar.get_event_by_id_mut(changed_id) {
+            Some(event) => storage.update_event(event)?,
+            None => {
+                storage.delete_event(changed_id)?;
+            }
+        }
+        return Ok(());
+    }
+
     if parts_input.len() >= 3 {
         // Add events to ToDo or Calendar
         if parts_input[0].trim().to_lowercase() == "todo" {
+            // An optional due date (and time) may follow the title
+            let (due_date, due_time) = match parts_input.get(3).filter(|due| !due.is_empty()) {
+                Some(due) => {
+                    string_to_due_date(due).ok_or(format!("Invalid due date {:?}", due))?
+                }
+                None => (None, None),
+            };
+            let priority = Priority::from_input(&parts_input[1])
+                .ok_or(format!("Invalid priority {:?}", parts_input[1]))?;
+            // #tags and a +project can be given in the title
+            let (todo_name, tags, project) = split_tags(&parts_input[2]);
             let new_todo = ToDo {
-                high_prio: parts_input[1].parse().unwrap(),
-                todo_name: parts_input[2].clone(),
+                id: 0,
+                priority,
+                todo_name,
+                completed: None,
+                due_date,
+                due_time,
+                subtasks: Vec::new(),
+                tags,
+                project,
             };
 
-            todolist.as_mut().unwrap().add_todos_from_json();
-            todolist.as_mut().unwrap().all_todos.push(new_todo);
-
-            // Manually contruct the json
-            todolist.as_mut().unwrap().add_back_todos_to_json();
+            storage.insert_todo(new_todo)?;
         }
         if parts_input[0].trim().to_lowercase().contains("app") {
+            let (date, all_day, time_zone) = parse_event_start(&parts_input[1])
+                .ok_or(format!("Invalid start {:?}", parts_input[1]))?;
+
+            // An optional end time and an optional recurrence rule may follow the location.
+            // Since BYDAY uses commas as well (BYDAY=MO,WE), the parts after FREQ are joined back together.
+            let mut end = None;
+            let mut recurrence = None;
+            for (i, part) in parts_input.iter().enumerate().skip(4) {
+                if part.to_uppercase().starts_with("FREQ")
+                    || part.to_uppercase().starts_with("RRULE")
+                {
+                    recurrence = Some(Recurrence::from_rrule_in(
+                        &parts_input[i..].join(","),
+                        time_zone,
+                    )?);
+                    break;
+                } else if end.is_none() && !part.is_empty() {
+                    // The end is in the same time zone as the start
+                    end = Some(
+                        string_to_end_date(date, &split_time_zone(part).0)
+                            .ok_or(format!("Invalid end {:?}", part))?,
+                    );
+                }
+            }
+
+            // #tags and a +project can be given in the title
+            let (event_name, tags, project) = split_tags(&parts_input[2]);
             let new_event = Events {
-                date: string_to_naive_date(&parts_input[1].clone()),
-                event_name: parts_input[2].clone(),
-                location: parts_input[3].clone(),
+                id: 0,
+                date,
+                event_name,
+                location: parts_input.get(3).cloned().unwrap_or_default(),
+                end,
+                all_day,
+                time_zone,
+                recurrence,
+                tags,
+                project,
+                uid: None,
+                description: String::new(),
             };
 
-            calendar_list.as_mut().unwrap().add_appointments_from_json();
-            calendar_list.as_mut().unwrap().all_events.push(new_event);
-
-            // Manually contruct the json
-            calendar_list.as_mut().unwrap().add_back_events_to_json();
+            storage.insert_event(new_event)?;
         }
     } else if parts_input.len() == 2 {
         // Search for an appointment
         if (parts_input[0].trim().to_lowercase().contains("find"))
             || parts_input[0].trim().to_lowercase().contains("search")
         {
-            if parts_input[1].clone().len() < 19 {
+            let date_time = if parts_input[1].trim().len() < 19 {
                 // If less than 19, then it is in NaiveDate format => need to change it to NaiveDateTime
-                let date_time_formated =
-                    parts_input[1].clone() + &String::from(" 00:00:00"); // converting string to NaiveDateTime format
-                *calendar_date = string_to_naive_date(&date_time_formated);
+                parts_input[1].trim().to_string() + " 00:00:00"
             } else {
-                *calendar_date = string_to_naive_date(&parts_input[1].clone());
-            }
+                parts_input[1].clone()
+            };
+            *calendar_date = try_string_to_naive_date(&date_time)
+                .map_err(|_| format!("Invalid date {:?}", parts_input[1]))?;
         }
     } else if parts_input.len() == 1 {
         // Jump to today
@@ -93,19 +322,271 @@ fn write_user_input_to_json(
             *calendar_date = Local::now().naive_local();
         }
     }
+    Ok(())
+}
+
+/// Lists the backups, numbered from the newest, or restores the one with the given number
+/// Returns the lines to show in the To-Do pane
+fn restore_from_input(
+    number: Option<&str>,
+    storage: &mut dyn Storage,
+) -> StorageResult<Vec<String>> {
+    let backups = storage.backups()?;
+    let Some(number) = number else {
+        if backups.is_empty() {
+            return Ok(vec![String::from("No backups yet")]);
+        }
+        let mut lines = vec![String::from("Backups, use restore <n> to roll back:")];
+        for (count, backup) in backups.iter().enumerate() {
+            lines.push(format!("{}. {}", count + 1, backup.describe()));
+        }
+        return Ok(lines);
+    };
+
+    let backup = number
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| backups.get(n.checked_sub(1)?))
+        .ok_or(format!("No backup number {}", number))?;
+    storage.restore_backup(backup)?;
+    Ok(vec![format!("Restored {}", backup.describe())])
+}
+
+/// To-do commands are separated by spaces: `done <n>`, `undo <n>`, `rm <n>`, `edit <n> <new name>`
+/// and `sub <n> <name>` to add a subtask. Subtasks are numbered like `3.1`
+/// Returns false if the input is not a to-do command
+fn update_todo_from_input(
+    input: &str,
+    todolist: &mut ToDoList,
+    storage: &mut dyn Storage,
+) -> StorageResult<bool> {
+    let mut words = input.trim().splitn(3, char::is_whitespace);
+    let command = words.next().unwrap_or_default().to_lowercase();
+    if !["done", "undo", "rm", "edit", "sub"].contains(&command.as_str()) {
+        return Ok(false);
+    }
+    let Some(number) = words.next().map(|n| n.trim().to_string()) else {
+        return Ok(false);
+    };
+    // Anything else, such as `edit 3, title, …`, is left to the other commands
+    let is_number = number
+        .split('.')
+        .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+    if !is_number {
+        return Ok(false);
+    }
+
+    todolist.load_todos(storage)?;
+    let (index, subtask_path) = todolist
+        .get_todo_path_by_number(&number)
+        .ok_or(format!("No to-do number {}", number))?;
+    let name = words
+        .next()
+        .map(|n| n.trim().to_string())
+        .unwrap_or_default();
+
+    if command == "rm" && subtask_path.is_empty() {
+        storage.delete_todo(todolist.all_todos[index].id)?;
+        return Ok(true);
+    } else if command == "rm" {
+        todolist.remove_todo_by_path(index, &subtask_path);
+    } else {
+        let todo = todolist.get_todo_by_path_mut(index, &subtask_path).unwrap();
+        match command.as_str() {
+            "done" => todo.completed = Some(Local::now().naive_local()),
+            "undo" => todo.completed = None,
+            "edit" if name.is_empty() => return Err("edit needs the new name".into()),
+            _ if name.is_empty() => return Err("sub needs the name of the subtask".into()),
+            "edit" => {
+                // Tags and project are only replaced when new ones are given
+                let (todo_name, tags, project) = split_tags(&name);
+                todo.todo_name = todo_name;
+                if !tags.is_empty() || project.is_some() {
+                    todo.tags = tags;
+                    todo.project = project;
+                }
+            }
+            _ => todo.subtasks.push(ToDo::new(Priority::None, name)),
+        }
+    }
+
+    // Subtasks are stored with their top-level item
+    storage.update_todo(&todolist.all_todos[index])?;
+    Ok(true)
+}
+
+/// Finds the event targeted by `del`/`edit`: either `<id>` or `<date>, <position>`
+/// Returns the event ID, the day given (if any) and the index of the next input part
+fn event_target_from_input(
+    parts_input: &[String],
+    calendar: &Calendar,
+) -> Result<(u32, Option<NaiveDateTime>, usize), String> {
+    let target = parts_input
+        .get(1)
+        .filter(|target| !target.is_empty())
+        .ok_or(format!(
+            "{} needs the ID of an appointment, or its date and position",
+            parts_input[0]
+        ))?;
+    if let Ok(id) = target.parse::<u32>() {
+        if !calendar.all_events.iter().any(|ev| ev.id == id) {
+            return Err(format!("No appointment with id {}", id));
+        }
+        return Ok((id, None, 2));
+    }
+
+    let date = parse_event_start(target)
+        .ok_or(format!("Invalid date {:?}", target))?
+        .0;
+    let position = parts_input.get(2).map_or("", |p| p.as_str());
+    let id = position
+        .parse::<usize>()
+        .ok()
+        .and_then(|index| calendar.get_event_id_by_index(date, index))
+        .ok_or(format!(
+            "No appointment number {:?} on {}",
+            position,
+            date.format("%Y-%m-%d")
+        ))?;
+    Ok((id, Some(date), 3))
+}
+
+/// `del, <id>` removes an event (or a whole series), while `del, <date>, <position>`
+/// only removes that day's occurrence of a recurring event
+/// Returns the ID of the changed (or removed) event
+fn delete_event_from_input(parts_input: &[String], calendar: &mut Calendar) -> Result<u32, String> {
+    let (id, date, _) = event_target_from_input(parts_input, calendar)?;
+
+    if let Some(date) = date {
+        let event = calendar.get_event_by_id_mut(id).unwrap();
+        // The exception is stored in the wall-clock time of the event
+        let occurrence = event.occurrence_covering(date.date()).unwrap();
+        let exception_date = event.from_local_time(occurrence.date).date();
+        if let Some(recurrence) = &mut event.recurrence {
+            recurrence.exceptions.push(exception_date);
+            return Ok(id);
+        }
+    }
+
+    calendar
+        .delete_event_from_calendar(id)
+        .map(|ev| ev.id)
+        .ok_or(format!("No appointment with id {}", id))
+}
+
+/// `edit, <id>, <field>, <value>` or `edit, <date>, <position>, <field>, <value>`
+/// The fields are title, location, start, end, recurrence and tags; `none` clears the end or recurrence
+/// Returns the ID of the changed event, or why the input cannot be applied
+fn edit_event_from_input(parts_input: &[String], calendar: &mut Calendar) -> Result<u32, String> {
+    let (id, _, next) = event_target_from_input(parts_input, calendar)?;
+    let (Some(field), Some(value)) = (parts_input.get(next), parts_input.get(next + 1)) else {
+        return Err(String::from("edit needs a field and its new value"));
+    };
+    let event = calendar.get_event_by_id_mut(id).unwrap();
+
+    match field.to_lowercase().as_str() {
+        "title" | "name" => {
+            // Tags and project are only replaced when new ones are given
+            let (event_name, tags, project) = split_tags(value);
+            event.event_name = event_name;
+            if !tags.is_empty() || project.is_some() {
+                event.tags = tags;
+                event.project = project;
+            }
+        }
+        "tags" => (_, event.tags, event.project) = split_tags(value),
+        "location" => event.location = value.clone(),
+        "start" | "date" => {
+            // The event keeps its duration
+            let duration = event.duration();
+            let (date, all_day, time_zone) =
+                parse_event_start(value).ok_or(format!("Invalid start {:?}", value))?;
+            event.date = date;
+            event.all_day = all_day;
+            event.time_zone = time_zone;
+            event.end = duration.map(|d| date + d);
+        }
+        "end" => {
+            if value.to_lowercase() == "none" {
+                event.end = None;
+            } else {
+                let end = string_to_end_date(event.date, &split_time_zone(value).0)
+                    .ok_or(format!("Invalid end {:?}", value))?;
+                event.end = Some(end);
+            }
+        }
+        "recurrence" | "rrule" => {
+            if value.to_lowercase() == "none" {
+                event.recurrence = None;
+            } else {
+                // BYDAY uses commas as well, so the remaining parts are joined back together
+                let mut recurrence =
+                    Recurrence::from_rrule_in(&parts_input[next + 1..].join(","), event.time_zone)?;
+                if let Some(old) = &event.recurrence {
+                    recurrence.exceptions = old.exceptions.clone();
+                }
+                event.recurrence = Some(recurrence);
+            }
+        }
+        _ => {
+            return Err(format!(
+                "Unknown field {:?}, use title, location, start, end, recurrence or tags",
+                field
+            ))
+        }
+    }
+    Ok(id)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn main_todo_layout(
     frame: &mut Frame,
     main_layout: &Rc<[Rect]>,
     input_todo_textarea: &mut TextArea,
     is_writing_mode: bool,
     calendar_date: &mut NaiveDateTime,
+    filter: &mut Filter,
+    app_state: &mut AppState,
+    config: &Config,
 ) {
-    let mut todolist = ToDoList::new();
-    let todo_list_text = todolist.generate_todo_text();
+    let mut todo_list_lines = match &app_state.todos_error {
+        Some(e) => vec![(
+            TodoLineKind::Overdue,
+            format!("Could not load the to-do items: {}", e),
+        )],
+        None => app_state
+            .todolist
+            .generate_todo_lines(filter, &config.formats),
+    };
 
-    let calendar = Calendar::new();
+    // The command being typed may refer to items that another program just changed,
+    // so the changes are listed above the reloaded items until it is run or discarded
+    if input_todo_textarea.is_empty() {
+        app_state.conflict = None;
+    }
+    if let Some(changes) = &app_state.conflict {
+        let mut conflict_lines = vec![(
+            TodoLineKind::Conflict,
+            String::from("Changed on disk while you were typing:"),
+        )];
+        for change in changes {
+            conflict_lines.push((TodoLineKind::Conflict, change.clone()));
+        }
+        conflict_lines.push((
+            TodoLineKind::Conflict,
+            String::from("Enter runs your command on the reloaded items, Delete discards it"),
+        ));
+        conflict_lines.push((TodoLineKind::Empty, String::new()));
+        todo_list_lines.splice(0..0, conflict_lines);
+    }
+    if let Some(notice) = &app_state.notice {
+        let mut notice_lines: Vec<(TodoLineKind, String)> = notice
+            .iter()
+            .map(|line| (TodoLineKind::Notice, line.clone()))
+            .collect();
+        notice_lines.push((TodoLineKind::Empty, String::new()));
+        todo_list_lines.splice(0..0, notice_lines);
+    }
 
     // Check for Enter key and process input
     if is_writing_mode {
@@ -115,16 +596,36 @@ pub fn main_todo_layout(
                 if key.code == KeyCode::Delete {
                     // Clear the textarea
                     *input_todo_textarea = TextArea::default();
+                    app_state.conflict = None;
                 } else if key.code == KeyCode::Enter {
                     let input_todo_content = input_todo_textarea.lines().join("\n");
-                    write_user_input_to_json(
-                        input_todo_content,
-                        &mut Some(todolist),
-                        &mut Some(calendar),
-                        calendar_date,
-                    );
+                    app_state.notice = None;
+                    // The commands read what they change from the storage, since the state only
+                    // holds the events of the shown month
+                    // Waits for a sync running in the background, which writes the same files
+                    let result = StorageLock::acquire(&app_state.paths).and_then(|_lock| {
+                        write_user_input_to_storage(
+                            input_todo_content,
+                            &mut app_state.todolist,
+                            &mut app_state.calendar,
+                            calendar_date,
+                            filter,
+                            app_state.storage.as_mut(),
+                            &mut app_state.notice,
+                            app_state.sync.as_ref(),
+                            app_state.feeds.as_ref(),
+                            config,
+                        )
+                    });
+                    app_state.mark_changed();
+                    app_state.conflict = None;
+                    app_state.status = None;
                     // Clear the textarea after processing
                     *input_todo_textarea = TextArea::default();
+                    if let Err(e) = result {
+                        // Nothing was written, the error stays in the input box until the next command
+                        input_todo_textarea.set_placeholder_text(format!("Error: {}", e));
+                    }
                 } else {
                     input_todo_textarea.input(tui_textarea::Input::from(key));
                 }
@@ -134,14 +635,31 @@ pub fn main_todo_layout(
 
     let layout = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Percentage(80), Constraint::Percentage(20)].to_vec())
+        .constraints(
+            [
+                Constraint::Percentage(config.layout.todo_height),
+                Constraint::Percentage(100 - config.layout.todo_height),
+            ]
+            .to_vec(),
+        )
         .split(main_layout[1]);
 
-    let todo_block = get_todo_title_block();
+    let todo_block = get_todo_title_block(filter, config.theme.todo);
     frame.render_widget(todo_block.clone(), layout[0]);
-    frame.render_widget(get_todo_list_text(todo_list_text), layout[0]);
+    frame.render_widget(
+        get_todo_list_text(
+            todo_list_lines,
+            &config.theme,
+            get_pane_padding(&config.layout),
+        ),
+        layout[0],
+    );
 
-    let user_input_block = get_todo_user_input_block();
+    let user_input_block = get_todo_user_input_block(
+        app_state.status.as_deref(),
+        app_state.sync_status.as_deref(),
+        config.theme.input,
+    );
     frame.render_widget(user_input_block, layout[1]);
     frame.render_widget(&*input_todo_textarea, layout[1]);
 }
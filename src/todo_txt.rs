@@ -0,0 +1,488 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Local, NaiveDate, NaiveTime};
+
+use crate::{
+    backup::{write_atomic, Backup},
+    calendar_data::Events,
+    paths::TODOS_FILE,
+    storage::{Storage, StorageResult},
+    to_do_data::{string_to_due_date, Priority, ToDo},
+};
+
+/// Keeps the to-dos in a todo.txt file (one item per line, see todo.txt on GitHub) and the
+/// appointments in another storage
+/// As in todo.sh, the ID of a to-do is its line number, and a deleted item leaves an empty line
+/// so that the ones below keep theirs. Lines that are not changed here are left as they are
+pub struct TodoTxtStorage {
+    path: PathBuf,
+    events: Box<dyn Storage>,
+}
+
+impl TodoTxtStorage {
+    /// Uses the file at `path`, which is created empty if it does not exist
+    pub fn open(path: &Path, events: Box<dyn Storage>) -> StorageResult<Self> {
+        if !path.exists() {
+            write_atomic(path, b"")?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            events,
+        })
+    }
+
+    fn read_lines(&self) -> StorageResult<Vec<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(data) => Ok(data.lines().map(String::from).collect()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(format!("Could not open {}: {}", self.path.display(), e).into()),
+        }
+    }
+
+    /// Empty lines at the end are kept as well, so that a new item does not get the number of a
+    /// deleted one
+    fn write_lines(&self, lines: &[String]) -> StorageResult<()> {
+        let data: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        write_atomic(&self.path, data.as_bytes())
+    }
+
+    /// The item on the line of an ID, with the index of that line
+    fn find(&self, lines: &[String], id: u32) -> Option<(usize, TodoTxtItem)> {
+        let index = (id as usize).checked_sub(1)?;
+        let item = parse_line(lines.get(index)?, id)?;
+        Some((index, item))
+    }
+}
+
+impl Storage for TodoTxtStorage {
+    fn load_events(&mut self) -> StorageResult<Vec<Events>> {
+        self.events.load_events()
+    }
+
+    fn events_between(&mut self, from: NaiveDate, to: NaiveDate) -> StorageResult<Vec<Events>> {
+        self.events.events_between(from, to)
+    }
+
+    fn insert_event(&mut self, event: Events) -> StorageResult<u32> {
+        self.events.insert_event(event)
+    }
+
+    fn insert_events(&mut self, events: Vec<Events>) -> StorageResult<Vec<u32>> {
+        self.events.insert_events(events)
+    }
+
+    fn update_event(&mut self, event: &Events) -> StorageResult<()> {
+        self.events.update_event(event)
+    }
+
+    fn delete_event(&mut self, id: u32) -> StorageResult<Option<Events>> {
+        self.events.delete_event(id)
+    }
+
+    fn load_todos(&mut self) -> StorageResult<Vec<ToDo>> {
+        Ok((1..)
+            .zip(self.read_lines()?)
+            .filter_map(|(id, line)| parse_line(&line, id))
+            .map(|item| item.todo)
+            .collect())
+    }
+
+    fn todos_due_between(&mut self, from: NaiveDate, to: NaiveDate) -> StorageResult<Vec<ToDo>> {
+        let mut todos = self.load_todos()?;
+        todos.retain(|t| t.due_date.is_some_and(|due| due >= from && due <= to));
+        Ok(todos)
+    }
+
+    /// Appended with today as its creation date
+    fn insert_todo(&mut self, todo: ToDo) -> StorageResult<u32> {
+        check_subtasks(&todo)?;
+        let mut lines = self.read_lines()?;
+        let created = TodoTxtItem {
+            created: Some(Local::now().date_naive()),
+            ..TodoTxtItem::default()
+        };
+        lines.push(line_text(&todo, &created));
+        self.write_lines(&lines)?;
+        Ok(lines.len() as u32)
+    }
+
+    /// The creation date, other priorities and unknown key:value pairs of the line are kept
+    fn update_todo(&mut self, todo: &ToDo) -> StorageResult<()> {
+        check_subtasks(todo)?;
+        let mut lines = self.read_lines()?;
+        let (index, item) = self
+            .find(&lines, todo.id)
+            .ok_or(format!("No to-do with id {}", todo.id))?;
+        if item.todo == *todo {
+            return Ok(());
+        }
+        lines[index] = line_text(todo, &item);
+        self.write_lines(&lines)
+    }
+
+    fn delete_todo(&mut self, id: u32) -> StorageResult<Option<ToDo>> {
+        let mut lines = self.read_lines()?;
+        let Some((index, item)) = self.find(&lines, id) else {
+            return Ok(None);
+        };
+        lines[index] = String::new();
+        self.write_lines(&lines)?;
+        Ok(Some(item.todo))
+    }
+
+    /// The file of the to-dos of the other storage is left out, it is not used
+    fn watched_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self
+            .events
+            .watched_paths()
+            .into_iter()
+            .filter(|path| !path.ends_with(TODOS_FILE))
+            .collect();
+        paths.push(self.path.clone());
+        paths
+    }
+
+    /// Only the appointments are backed up, todo.txt is left to the tools that share it
+    fn backups(&self) -> StorageResult<Vec<Backup>> {
+        let mut backups = self.events.backups()?;
+        backups.retain(|backup| !backup.original.ends_with(TODOS_FILE));
+        Ok(backups)
+    }
+
+    fn restore_backup(&mut self, backup: &Backup) -> StorageResult<()> {
+        self.events.restore_backup(backup)
+    }
+}
+
+fn check_subtasks(todo: &ToDo) -> StorageResult<()> {
+    if !todo.subtasks.is_empty() {
+        return Err("todo.txt items cannot have subtasks".into());
+    }
+    Ok(())
+}
+
+/// A line of todo.txt: the to-do, and what the calendar has no place for
+#[derive(Debug, Default, Clone, PartialEq)]
+struct TodoTxtItem {
+    todo: ToDo,
+    created: Option<NaiveDate>,
+    /// Marked done without a completion date
+    undated_completion: bool,
+    /// A priority below E, the to-do has none
+    other_priority: Option<char>,
+    /// key:value pairs other than `due:` and `pri:`, in their order
+    extras: Vec<String>,
+}
+
+/// Reads a line such as `x 2024-09-15 2024-09-01 (A) Call Mom +family @phone due:2024-09-14`
+/// An empty line holds no item
+fn parse_line(line: &str, id: u32) -> Option<TodoTxtItem> {
+    let mut words = line.split_whitespace().peekable();
+    words.peek()?;
+    let mut item = TodoTxtItem::default();
+
+    let done = words.next_if_eq(&"x").is_some();
+    let completed = if done {
+        words.next_if(|word| parse_date(word).is_some())
+    } else {
+        None
+    };
+    if let Some(letter) = words.next_if(|word| priority_letter(word).is_some()) {
+        set_priority(&mut item, priority_letter(letter).unwrap());
+    }
+    item.created = words
+        .next_if(|word| parse_date(word).is_some())
+        .and_then(parse_date);
+    item.undated_completion = done && completed.is_none();
+    if done {
+        // Without a completion date, the day it was created (if known) stands in for it
+        let date = completed.and_then(parse_date).or(item.created);
+        item.todo.completed = Some(date.unwrap_or_default().and_time(NaiveTime::MIN));
+    }
+
+    let mut name_words = Vec::new();
+    for word in words {
+        if let Some(project) = word.strip_prefix('+').filter(|p| !p.is_empty()) {
+            // The calendar has one project per item, the others stay in the title
+            if item.todo.project.is_none() {
+                item.todo.project = Some(project.to_string());
+                continue;
+            }
+        } else if let Some(context) = word.strip_prefix('@').filter(|c| !c.is_empty()) {
+            if !item.todo.tags.iter().any(|t| t == context) {
+                item.todo.tags.push(context.to_string());
+            }
+            continue;
+        } else if let Some((key, value)) = key_value(word) {
+            match (key, string_to_due_date(value)) {
+                ("due", Some((due_date, due_time))) if item.todo.due_date.is_none() => {
+                    item.todo.due_date = due_date;
+                    item.todo.due_time = due_time;
+                }
+                ("pri", _) if done && letter(value).is_some() => {
+                    set_priority(&mut item, letter(value).unwrap());
+                }
+                _ => item.extras.push(word.to_string()),
+            }
+            continue;
+        }
+        name_words.push(word);
+    }
+    item.todo.todo_name = name_words.join(" ");
+    item.todo.id = id;
+    Some(item)
+}
+
+/// The line of a to-do, with the creation date, other priority and key:value pairs of `item`
+fn line_text(todo: &ToDo, item: &TodoTxtItem) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let done = todo.completed.is_some();
+    if let Some(completed) = todo.completed {
+        words.push(String::from("x"));
+        // A completion date is needed before a creation date, or it would be read as one
+        if !item.undated_completion || item.created.is_some() {
+            words.push(completed.format("%Y-%m-%d").to_string());
+        }
+    }
+    // The priority of a done item is kept as pri:X, as most todo.txt tools do
+    let priority = todo.priority.to_letter().or(item
+        .other_priority
+        .filter(|_| todo.priority == Priority::None));
+    if let (Some(letter), false) = (priority, done) {
+        words.push(format!("({})", letter));
+    }
+    if let Some(created) = item.created {
+        words.push(created.format("%Y-%m-%d").to_string());
+    }
+    // The project goes before the other +projects left in the title, the first one is read as it
+    let title: Vec<&str> = todo.todo_name.split(' ').collect();
+    let other_projects = title
+        .iter()
+        .position(|word| word.strip_prefix('+').is_some_and(|p| !p.is_empty()))
+        .unwrap_or(title.len());
+    words.push(title[..other_projects].join(" "));
+    if let Some(project) = &todo.project {
+        words.push(format!("+{}", project));
+    }
+    words.push(title[other_projects..].join(" "));
+    words.extend(todo.tags.iter().map(|tag| format!("@{}", tag)));
+    if let Some(due_date) = todo.due_date {
+        words.push(match todo.due_time {
+            Some(due_time) => format!("due:{}T{}", due_date, due_time.format("%H:%M")),
+            None => format!("due:{}", due_date),
+        });
+    }
+    if let (Some(letter), true) = (priority, done) {
+        words.push(format!("pri:{}", letter));
+    }
+    words.extend(item.extras.iter().cloned());
+    words.retain(|word| !word.is_empty());
+    words.join(" ")
+}
+
+fn parse_date(word: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(word, "%Y-%m-%d").ok()
+}
+
+/// The letter of a priority such as `(A)`
+fn priority_letter(word: &str) -> Option<char> {
+    letter(word.strip_prefix('(')?.strip_suffix(')')?)
+}
+
+/// A single letter from A to Z
+fn letter(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_uppercase() => Some(c),
+        _ => None,
+    }
+}
+
+/// Priorities below E have no level in the calendar, they are kept for the line
+fn set_priority(item: &mut TodoTxtItem, letter: char) {
+    match Priority::from_input(&letter.to_string()) {
+        Some(priority) => item.todo.priority = priority,
+        None => item.other_priority = Some(letter),
+    }
+}
+
+/// A `key:value` word with a key made of letters; times such as `10:30` and links such as
+/// `https://…` are part of the title
+fn key_value(word: &str) -> Option<(&str, &str)> {
+    let (key, value) = word.split_once(':')?;
+    let valid = !key.is_empty()
+        && key.chars().all(|c| c.is_alphabetic())
+        && !value.is_empty()
+        && !value.starts_with("//");
+    valid.then_some((key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, m, d).unwrap()
+    }
+
+    fn item(line: &str) -> TodoTxtItem {
+        parse_line(line, 1).unwrap()
+    }
+
+    #[test]
+    fn full_line() {
+        let line = "x 2024-09-15 2024-09-01 Call Mom +family @phone due:2024-09-14 pri:A";
+        let item = item(line);
+        assert_eq!(item.todo.todo_name, "Call Mom");
+        assert_eq!(item.todo.project.as_deref(), Some("family"));
+        assert_eq!(item.todo.tags, ["phone"]);
+        assert_eq!(item.todo.due_date, Some(date(9, 14)));
+        assert_eq!(item.todo.priority, Priority::A);
+        assert_eq!(
+            item.todo.completed,
+            Some(date(9, 15).and_time(NaiveTime::MIN))
+        );
+        assert_eq!(item.created, Some(date(9, 1)));
+        assert_eq!(line_text(&item.todo, &item), line);
+    }
+
+    #[test]
+    fn done_without_a_date() {
+        let item = item("x Call Mom");
+        assert!(item.undated_completion);
+        assert!(item.todo.is_done());
+        assert_eq!(item.created, None);
+        assert_eq!(item.todo.todo_name, "Call Mom");
+        assert_eq!(line_text(&item.todo, &item), "x Call Mom");
+
+        // A single date after the x is the completion date
+        let item = self::item("x 2024-09-01 Call Mom");
+        assert!(!item.undated_completion);
+        assert_eq!(item.created, None);
+        assert_eq!(line_text(&item.todo, &item), "x 2024-09-01 Call Mom");
+
+        // Without a completion date, the creation date stands in for it
+        let item = self::item("x (B) 2024-09-01 Call Mom");
+        assert!(item.undated_completion);
+        assert_eq!(item.created, Some(date(9, 1)));
+        assert_eq!(
+            item.todo.completed,
+            Some(date(9, 1).and_time(NaiveTime::MIN))
+        );
+        assert_eq!(item.todo.priority, Priority::B);
+        // It is written back, or the creation date would be read as the completion date
+        assert_eq!(
+            line_text(&item.todo, &item),
+            "x 2024-09-01 2024-09-01 Call Mom pri:B"
+        );
+    }
+
+    #[test]
+    fn not_done() {
+        for line in ["X Call Mom", "xylophone lessons", "(A) x marks the spot"] {
+            let item = item(line);
+            assert!(!item.todo.is_done(), "{}", line);
+            assert_eq!(line_text(&item.todo, &item), line);
+        }
+        assert!(parse_line("", 1).is_none());
+        assert!(parse_line("   ", 1).is_none());
+    }
+
+    #[test]
+    fn unknown_parts_are_kept() {
+        let line = "(F) 2024-09-01 Read https://example.com/a:b +books +later @home rec:1w t:2024-09-10 due:soon";
+        let item = item(line);
+        assert_eq!(item.todo.priority, Priority::None);
+        assert_eq!(item.other_priority, Some('F'));
+        assert_eq!(item.todo.project.as_deref(), Some("books"));
+        assert_eq!(item.extras, ["rec:1w", "t:2024-09-10", "due:soon"]);
+        assert_eq!(item.todo.todo_name, "Read https://example.com/a:b +later");
+        assert_eq!(line_text(&item.todo, &item), line);
+
+        // The words are written in the order of the calendar, the unknown ones last
+        let moved = self::item("rec:1w @home Read +books +later");
+        let text = line_text(&moved.todo, &moved);
+        assert_eq!(text, "Read +books +later @home rec:1w");
+        assert_eq!(parse_line(&text, 1).unwrap(), moved);
+
+        // A priority of the calendar replaces the other one
+        let mut todo = item.todo.clone();
+        todo.priority = Priority::C;
+        assert!(line_text(&todo, &item).starts_with("(C) 2024-09-01 Read"));
+    }
+
+    #[test]
+    fn colons_in_the_title() {
+        for line in ["Meeting at 10:30", "Ratio 1:2", "Read https://example.com"] {
+            let item = item(line);
+            assert!(item.extras.is_empty(), "{}", line);
+            assert_eq!(item.todo.todo_name, line);
+        }
+    }
+
+    #[test]
+    fn due_times() {
+        let item = item("Submit due:2024-09-14T17:30");
+        assert_eq!(item.todo.due_date, Some(date(9, 14)));
+        assert_eq!(item.todo.due_time, NaiveTime::from_hms_opt(17, 30, 0));
+        assert_eq!(line_text(&item.todo, &item), "Submit due:2024-09-14T17:30");
+    }
+
+    #[test]
+    fn storage_keeps_the_line_numbers() {
+        let dir = std::env::temp_dir().join(format!("todo-txt-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("todo.txt");
+        let lines = "(A) First +work\n\nx Second\n2024-09-01 Third  with   spaces\n";
+        fs::write(&path, lines).unwrap();
+        let events = crate::json_storage::JsonStorage::new(
+            dir.join("appointments.json"),
+            dir.join("todos.json"),
+        );
+        let mut storage = TodoTxtStorage::open(&path, Box::new(events)).unwrap();
+
+        let todos = storage.load_todos().unwrap();
+        assert_eq!(todos.iter().map(|t| t.id).collect::<Vec<_>>(), [1, 3, 4]);
+
+        // Unchanged items are not rewritten, even with their odd spacing
+        let mut first = todos[0].clone();
+        storage.update_todo(&first).unwrap();
+        first.completed = Some(date(9, 2).and_time(NaiveTime::MIN));
+        storage.update_todo(&first).unwrap();
+        let id = storage
+            .insert_todo(ToDo::new(Priority::None, String::from("Fourth")))
+            .unwrap();
+        assert_eq!(id, 5);
+        assert!(storage.delete_todo(3).unwrap().is_some());
+        assert!(storage.delete_todo(2).unwrap().is_none());
+        assert!(storage.update_todo(&todos[1]).is_err());
+
+        let mut subtask = todos[2].clone();
+        subtask
+            .subtasks
+            .push(ToDo::new(Priority::None, String::from("Part")));
+        assert!(storage.update_todo(&subtask).is_err());
+
+        let text = fs::read_to_string(&path).unwrap();
+        let created = Local::now().date_naive().format("%Y-%m-%d");
+        assert_eq!(
+            text,
+            format!(
+                "x 2024-09-02 First +work pri:A\n\n\n2024-09-01 Third  with   spaces\n{} Fourth\n",
+                created
+            )
+        );
+        assert!(storage.delete_todo(5).unwrap().is_some());
+        // The line of the deleted item stays, the next one gets a number of its own
+        assert!(fs::read_to_string(&path).unwrap().ends_with("spaces\n\n"));
+        let id = storage
+            .insert_todo(ToDo::new(Priority::None, String::from("Fifth")))
+            .unwrap();
+        assert_eq!(id, 6);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,54 @@
+/// Units the weather is shown in
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Units {
+    #[default]
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    pub fn from_input(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "metric" | "si" => Some(Units::Metric),
+            "imperial" | "us" => Some(Units::Imperial),
+            _ => None,
+        }
+    }
+
+    /// Fields of the wttr.in answer for the temperature, feels-like temperature, pressure,
+    /// visibility and wind speed
+    fn wttr_fields(&self) -> [&'static str; 5] {
+        match self {
+            Units::Metric => [
+                "temp_C",
+                "FeelsLikeC",
+                "pressure",
+                "visibility",
+                "windspeedKmph",
+            ],
+            Units::Imperial => [
+                "temp_F",
+                "FeelsLikeF",
+                "pressureInches",
+                "visibilityMiles",
+                "windspeedMiles",
+            ],
+        }
+    }
+
+    /// Labels of the temperature, pressure, visibility and wind speed
+    fn labels(&self) -> [&'static str; 4] {
+        match self {
+            Units::Metric => ["°C", "hPa", "km", "kmph"],
+            Units::Imperial => ["°F", "inHg", "mi", "mph"],
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 struct Temperature {
-    temp_c: String,
-    feels_like_c: String,
+    temperature: String,
+    feels_like: String,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -31,8 +78,8 @@ pub struct Weather {
 impl Weather {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        temp_c: String,
-        feels_like_c: String,
+        temperature: String,
+        feels_like: String,
         uv_index: String,
         humidity: String,
         pressure: String,
@@ -45,8 +92,8 @@ impl Weather {
         weather_emoji: String,
     ) -> Self {
         let temp = Temperature {
-            temp_c,
-            feels_like_c,
+            temperature,
+            feels_like,
         };
         let conditions = WeatherConditions {
             uv_index,
@@ -69,12 +116,12 @@ impl Weather {
         }
     }
 
-    pub fn temp_c(&self) -> &str {
-        &self.temp.temp_c
+    pub fn temperature(&self) -> &str {
+        &self.temp.temperature
     }
 
-    pub fn feels_like_c(&self) -> &str {
-        &self.temp.feels_like_c
+    pub fn feels_like(&self) -> &str {
+        &self.temp.feels_like
     }
 
     pub fn uv_index(&self) -> &str {
@@ -120,15 +167,20 @@ impl Weather {
     pub async fn generate_weather_text(
         &self,
         city: &str,
+        units: Units,
     ) -> Result<String, Box<dyn std::error::Error>> {
-        let current_weather = get_weather(city).await?;
+        let current_weather = get_weather(city, units).await?;
+        let [temperature_label, pressure_label, visibility_label, speed_label] = units.labels();
         let mut city_weather_str = String::new();
         for wtr in current_weather {
             // https://github.com/chubin/wttr.in/blob/master/lib/fields.py
-            city_weather_str.push_str(&format!("Temperature:    {:>2} °C\n", wtr.temp.temp_c));
             city_weather_str.push_str(&format!(
-                "Feels:          {:>2} °C\n",
-                wtr.temp.feels_like_c
+                "Temperature:    {:>2} {}\n",
+                wtr.temp.temperature, temperature_label
+            ));
+            city_weather_str.push_str(&format!(
+                "Feels:          {:>2} {}\n",
+                wtr.temp.feels_like, temperature_label
             ));
             city_weather_str.push_str(&format!(
                 "Condition:      {:>2}{:>1} \n",
@@ -139,24 +191,24 @@ impl Weather {
                 wtr.conditions.uv_index
             ));
             city_weather_str.push_str(&format!(
-                "Pressure:       {:>2} hPa\n",
-                wtr.conditions.pressure
+                "Pressure:       {:>2} {}\n",
+                wtr.conditions.pressure, pressure_label
             ));
             city_weather_str.push_str(&format!(
                 "Humidity:       {:>2} %\n",
                 wtr.conditions.humidity
             ));
             city_weather_str.push_str(&format!(
-                "Visibility:     {:>2} km\n",
-                wtr.conditions.visibility
+                "Visibility:     {:>2} {}\n",
+                wtr.conditions.visibility, visibility_label
             ));
             city_weather_str.push_str(&format!(
                 "Wind dir:       {:>2}° {:>2} \n",
                 wtr.wind.winddir_degree, wtr.wind.winddir_point
             ));
             city_weather_str.push_str(&format!(
-                "Wind speed:     {:>2} kmph\n",
-                wtr.wind.wind_speed
+                "Wind speed:     {:>2} {}\n",
+                wtr.wind.wind_speed, speed_label
             ));
             city_weather_str.push_str(&format!(
                 "Observed:       {:>2} \n",
@@ -168,14 +220,17 @@ impl Weather {
 }
 
 /// Use curl wttr.in in JSON format: https://wttr.in/London?format=j1
-pub async fn get_weather(city: &str) -> Result<Vec<Weather>, Box<dyn std::error::Error>> {
+pub async fn get_weather(
+    city: &str,
+    units: Units,
+) -> Result<Vec<Weather>, Box<dyn std::error::Error>> {
     let url = String::from("https://wttr.in/") + city + &String::from("?format=j1");
     let weather_response = reqwest::get(url).await?;
 
     let mut weather_vec: Vec<Weather> = Vec::new();
     if weather_response.status().is_success() {
         let weather_body = weather_response.text().await?;
-        weather_vec = get_weather_from_json(&weather_body);
+        weather_vec = get_weather_from_json(&weather_body, units);
     } else {
         println!("Failed to get the weather!");
     }
@@ -183,7 +238,9 @@ pub async fn get_weather(city: &str) -> Result<Vec<Weather>, Box<dyn std::error:
     Ok(weather_vec)
 }
 
-fn get_weather_from_json(weather_bod: &str) -> Vec<Weather> {
+fn get_weather_from_json(weather_bod: &str, units: Units) -> Vec<Weather> {
+    let [temperature_field, feels_like_field, pressure_field, visibility_field, speed_field] =
+        units.wttr_fields();
     let serde_weather_json: serde_json::Value =
         serde_json::from_str(weather_bod).expect("Serde error in reading data from JSON");
 
@@ -192,22 +249,22 @@ fn get_weather_from_json(weather_bod: &str) -> Vec<Weather> {
         .unwrap()
         .iter()
         .map(|val| {
-            let temp_c = val["temp_C"].as_str().unwrap().to_string();
-            let feels_like_c = val["FeelsLikeC"].as_str().unwrap().to_string();
+            let temperature = val[temperature_field].as_str().unwrap().to_string();
+            let feels_like = val[feels_like_field].as_str().unwrap().to_string();
             let local_obs_date_time = val["localObsDateTime"].as_str().unwrap().to_string();
             let uv_index = val["uvIndex"].as_str().unwrap().to_string();
             let humidity = val["humidity"].as_str().unwrap().to_string();
-            let pressure = val["pressure"].as_str().unwrap().to_string();
-            let visibility = val["visibility"].as_str().unwrap().to_string();
+            let pressure = val[pressure_field].as_str().unwrap().to_string();
+            let visibility = val[visibility_field].as_str().unwrap().to_string();
             let winddir_degree = val["winddirDegree"].as_str().unwrap().to_string();
             let winddir_point = val["winddir16Point"].as_str().unwrap().to_string();
-            let wind_speed = val["windspeedKmph"].as_str().unwrap().to_string();
+            let wind_speed = val[speed_field].as_str().unwrap().to_string();
             let weather_code = val["weatherCode"].as_str().unwrap().to_string(); // get the code and map it
             let (weather_description, weather_emoji) = get_weather_from_code(weather_code);
 
             let temp = Temperature {
-                temp_c,
-                feels_like_c,
+                temperature,
+                feels_like,
             };
             let conditions = WeatherConditions {
                 uv_index,
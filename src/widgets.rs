@@ -5,28 +5,54 @@ use ratatui::{
 };
 use tui_textarea::TextArea;
 
+use crate::app_state::AppState;
 use crate::calendar_widget::main_calendar_layout;
+use crate::config::Config;
+use crate::tags::Filter;
 use crate::to_do_widget::main_todo_layout;
 
+#[allow(clippy::too_many_arguments)]
 pub fn app_layout(
     frame: &mut Frame<'_>,
     input_todo_textarea: &mut TextArea<'_>,
     calendar_date: &mut NaiveDateTime,
     is_writing_mode: bool,
     weather_text: &String,
-    city_name: &str,
+    filter: &mut Filter,
+    app_state: &mut AppState,
+    config: &Config,
 ) {
+    // Reads the storage only if something changed since the last frame
+    app_state.refresh(*calendar_date, !input_todo_textarea.is_empty());
+
     let main_layout = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].to_vec())
+        .constraints(
+            [
+                Constraint::Percentage(config.layout.calendar_width),
+                Constraint::Percentage(100 - config.layout.calendar_width),
+            ]
+            .to_vec(),
+        )
         .split(frame.area());
 
-    main_calendar_layout(frame, &main_layout, calendar_date, weather_text, city_name);
+    main_calendar_layout(
+        frame,
+        &main_layout,
+        calendar_date,
+        weather_text,
+        filter,
+        app_state,
+        config,
+    );
     main_todo_layout(
         frame,
         &main_layout,
         input_todo_textarea,
         is_writing_mode,
         calendar_date,
+        filter,
+        app_state,
+        config,
     );
 }
@@ -0,0 +1,284 @@
+/// Namespaces of the WebDAV and CalDAV elements
+pub const DAV: &str = "DAV:";
+pub const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+
+/// An element of an XML document, named by its namespace URI and local name
+/// Only what the WebDAV responses need is kept: the attributes (by local name), the children
+/// and the text directly inside
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Element {
+    pub namespace: String,
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    pub fn is(&self, namespace: &str, name: &str) -> bool {
+        self.namespace == namespace && self.name == name
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn child(&self, namespace: &str, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.is(namespace, name))
+    }
+
+    pub fn children<'a>(
+        &'a self,
+        namespace: &'a str,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.is(namespace, name))
+    }
+
+    /// Text of a child, trimmed, e.g. the URL in `<d:href>`
+    pub fn child_text(&self, namespace: &str, name: &str) -> Option<String> {
+        self.child(namespace, name)
+            .map(|c| c.text.trim().to_string())
+    }
+}
+
+/// An element being read, with the namespace prefixes it declares
+struct OpenElement {
+    element: Element,
+    prefixes: Vec<(String, String)>,
+}
+
+/// Reads a document into its root element
+/// Comments, processing instructions and the doctype are skipped
+pub fn parse(xml: &str) -> Result<Element, String> {
+    let mut open: Vec<OpenElement> = Vec::new();
+    let mut rest = xml;
+    while !rest.is_empty() {
+        if rest.starts_with("<?") {
+            rest = skip_past(rest, "?>")?;
+        } else if rest.starts_with("<!--") {
+            rest = skip_past(rest, "-->")?;
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").ok_or("unterminated CDATA section")?;
+            if let Some(current) = open.last_mut() {
+                current.element.text.push_str(&cdata[..end]);
+            }
+            rest = &cdata[end + 3..];
+        } else if rest.starts_with("<!") {
+            rest = skip_past(rest, ">")?;
+        } else if let Some(closing) = rest.strip_prefix("</") {
+            let end = closing.find('>').ok_or("unterminated end tag")?;
+            let closed = open
+                .pop()
+                .ok_or(format!("unexpected end tag </{}>", closing[..end].trim()))?;
+            match open.last_mut() {
+                Some(parent) => parent.element.children.push(closed.element),
+                None => return Ok(closed.element),
+            }
+            rest = &closing[end + 1..];
+        } else if rest.starts_with('<') {
+            let end = tag_end(rest).ok_or("unterminated start tag")?;
+            let tag = &rest[1..end];
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let element = start_element(tag, &open)?;
+            rest = &rest[end + 1..];
+            if !self_closing {
+                open.push(element);
+                continue;
+            }
+            match open.last_mut() {
+                Some(parent) => parent.element.children.push(element.element),
+                None => return Ok(element.element),
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            if let Some(current) = open.last_mut() {
+                current.element.text.push_str(&unescape(&rest[..end]));
+            }
+            rest = &rest[end..];
+        }
+    }
+    Err(String::from("the document has no complete root element"))
+}
+
+/// Escapes text for an element or attribute value
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn skip_past<'a>(s: &'a str, end: &str) -> Result<&'a str, String> {
+    s.find(end)
+        .map(|i| &s[i + end.len()..])
+        .ok_or(format!("missing {:?}", end))
+}
+
+/// Position of the `>` closing a tag, outside of quoted attribute values
+fn tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('>', None) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Reads the name and attributes of a start tag, resolving its namespace prefix
+fn start_element(tag: &str, open: &[OpenElement]) -> Result<OpenElement, String> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let qualified_name = &tag[..name_end];
+    if qualified_name.is_empty() {
+        return Err(String::from("start tag without a name"));
+    }
+
+    let mut prefixes = Vec::new();
+    let mut attributes = Vec::new();
+    let mut rest = tag[name_end..].trim();
+    while let Some(equals) = rest.find('=') {
+        let key = rest[..equals].trim();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or(format!(
+                "unquoted attribute {} in <{}>",
+                key, qualified_name
+            ))?;
+        let end = value[1..]
+            .find(quote)
+            .ok_or(format!("unterminated attribute {}", key))?;
+        let value = unescape(&value[1..end + 1]);
+        if key == "xmlns" {
+            prefixes.push((String::new(), value));
+        } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+            prefixes.push((prefix.to_string(), value));
+        } else {
+            let local_name = key.split_once(':').map_or(key, |(_, name)| name);
+            attributes.push((local_name.to_string(), value));
+        }
+        rest = rest[equals + 1..].trim_start()[end + 2..].trim_start();
+    }
+
+    let (prefix, name) = qualified_name
+        .split_once(':')
+        .unwrap_or(("", qualified_name));
+    // The element's own declarations come first, then the ones of its ancestors
+    let namespace = prefixes
+        .iter()
+        .chain(open.iter().rev().flat_map(|o| o.prefixes.iter()))
+        .find(|(p, _)| p == prefix)
+        .map(|(_, uri)| uri.clone())
+        .unwrap_or_default();
+    Ok(OpenElement {
+        element: Element {
+            namespace,
+            name: name.to_string(),
+            attributes,
+            ..Element::default()
+        },
+        prefixes,
+    })
+}
+
+/// Replaces the predefined entities and character references
+fn unescape(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let mut text = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        text.push_str(&rest[..start]);
+        let entity_end = rest[start..].find(';').map(|i| start + i);
+        let Some(end) = entity_end else {
+            text.push_str(&rest[start..]);
+            return text;
+        };
+        let entity = &rest[start + 1..end];
+        let character = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse::<u32>()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match character {
+            Some(c) => text.push(c),
+            None => text.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_are_resolved() {
+        let root = parse(concat!(
+            r#"<?xml version="1.0"?><!-- a comment --><d:multistatus xmlns:d="DAV:">"#,
+            r#"<d:response><d:prop xmlns="urn:ietf:params:xml:ns:caldav">"#,
+            r#"<calendar-data/><d:getetag>"1"</d:getetag></d:prop></d:response>"#,
+            r#"</d:multistatus>"#
+        ))
+        .unwrap();
+        assert!(root.is(DAV, "multistatus"));
+        let prop = root
+            .child(DAV, "response")
+            .unwrap()
+            .child(DAV, "prop")
+            .unwrap();
+        assert!(prop.child(CALDAV, "calendar-data").is_some());
+        assert!(prop.child(DAV, "calendar-data").is_none());
+        assert_eq!(prop.child_text(DAV, "getetag").as_deref(), Some("\"1\""));
+    }
+
+    #[test]
+    fn text_and_attributes_are_unescaped() {
+        let root = parse(concat!(
+            "<root a='x &amp; y' c:b=\"&#65;&#x42;\">",
+            "Fish &amp; chips &lt;3 &unknown; <![CDATA[<raw> & ]]>",
+            "</root>"
+        ))
+        .unwrap();
+        assert_eq!(root.attribute("a"), Some("x & y"));
+        assert_eq!(root.attribute("b"), Some("AB"));
+        assert_eq!(root.text, "Fish & chips <3 &unknown; <raw> & ");
+        assert_eq!(
+            escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn malformed_documents() {
+        assert!(parse("").is_err());
+        assert!(parse("<a><b></b>").is_err());
+        assert!(parse("</a>").is_err());
+        assert!(parse("<a b=c></a>").is_err());
+        assert!(parse("<a><!-- open").is_err());
+    }
+}